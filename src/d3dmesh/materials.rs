@@ -1,4 +1,7 @@
-use std::io::{Read, Seek, SeekFrom};
+use std::{
+    collections::BTreeMap,
+    io::{Read, Seek, SeekFrom},
+};
 
 use anyhow::Result;
 use byteorder::{LittleEndian, ReadBytesExt};
@@ -12,6 +15,12 @@ use super::textures::Texture;
 pub struct Material {
     pub textures: Vec<Texture>,
     pub material_id: u64,
+    /// Unknown hash following the material ID, presumably identifies the shader/effect.
+    pub unknown_hash: u64,
+    /// Unknown list of hashes in the material header, presumably shader/effect features.
+    pub unknown_hashes: Vec<u64>,
+    /// All decoded parameters of the material, keyed by the CRC64 of their name.
+    pub parameters: BTreeMap<u64, Parameter>,
 }
 
 impl Material {
@@ -21,10 +30,11 @@ impl Material {
         texture_mapping: &ChecksumMap,
     ) -> Result<Self> {
         let mut textures = Vec::new();
+        let mut parameters = BTreeMap::new();
 
         let material_id = input.read_u64::<LittleEndian>()?;
-        let _unk_hash_2 = input.read_u32::<LittleEndian>()?;
-        let _unk_hash_1 = input.read_u32::<LittleEndian>()?;
+        // Note: the two 32-bit halves (unk_hash_2, unk_hash_1) are read as one 64-bit value
+        let unknown_hash = input.read_u64::<LittleEndian>()?;
         let material_header_size = input.read_u32::<LittleEndian>()?;
         // the end of the material section can be used to seek to the end of the section
         let material_section_end = input.stream_position()? as u32 + material_header_size - 4;
@@ -35,9 +45,10 @@ impl Material {
         let _mat_header_size_b = input.read_u32::<LittleEndian>()?;
 
         let mat_unk_3_count = input.read_u32::<LittleEndian>()?;
+        let mut unknown_hashes = Vec::new();
         for _ in 0..mat_unk_3_count {
-            let _mat_unk_3_hash_2 = input.read_u32::<LittleEndian>()?;
-            let _mat_unk_3_hash_1 = input.read_u32::<LittleEndian>()?;
+            // Note: the two 32-bit halves (mat_unk_3_hash_2, mat_unk_3_hash_1) are read as one 64-bit value
+            unknown_hashes.push(input.read_u64::<LittleEndian>()?);
         }
 
         let mat_param_count = input.read_u32::<LittleEndian>()?;
@@ -83,68 +94,58 @@ impl Material {
                 }
                 0xb76e07d6bb899bfe => {
                     for _ in 0..mat_section_count {
-                        // Four floats (alternate?)
-                        let _unknown_hash_2 = input.read_u32::<LittleEndian>()?;
-                        let _unknown_hash_1 = input.read_u32::<LittleEndian>()?;
-                        let _unknown_float_1 = input.read_f32::<LittleEndian>()?;
-                        let _unknown_float_2 = input.read_f32::<LittleEndian>()?;
-                        let _unknown_float_3 = input.read_f32::<LittleEndian>()?;
-                        let _unknown_float_4 = input.read_f32::<LittleEndian>()?;
+                        // Four floats (alternate?), presumably a color
+                        let hash = input.read_u64::<LittleEndian>()?;
+                        let value = ParameterValue::Color(parse_floats(&mut input)?);
+                        parameters.insert(hash, Parameter::new(hash, value, texture_mapping));
                     }
                 }
                 0x004f023463d89fb0 => {
                     for _ in 0..mat_section_count {
                         // One hash set
-                        let _unknown_hash_2 = input.read_u32::<LittleEndian>()?;
-                        let _unknown_hash_1 = input.read_u32::<LittleEndian>()?;
-                        let _unknown_hash_4 = input.read_u32::<LittleEndian>()?;
-                        let _unknown_hash_3 = input.read_u32::<LittleEndian>()?;
+                        let hash = input.read_u64::<LittleEndian>()?;
+                        let value = ParameterValue::Hash(input.read_u64::<LittleEndian>()?);
+                        parameters.insert(hash, Parameter::new(hash, value, texture_mapping));
                     }
                 }
                 0xbae4cbd77f139a91 => {
                     for _ in 0..mat_section_count {
                         // One float
-                        let _unknown_hash_2 = input.read_u32::<LittleEndian>()?;
-                        let _unknown_hash_1 = input.read_u32::<LittleEndian>()?;
-                        let _unknown_float_1 = input.read_f32::<LittleEndian>()?;
+                        let hash = input.read_u64::<LittleEndian>()?;
+                        let value = ParameterValue::Float(input.read_f32::<LittleEndian>()?);
+                        parameters.insert(hash, Parameter::new(hash, value, texture_mapping));
                     }
                 }
                 0x9004c5587575d6c0 => {
                     for _ in 0..mat_section_count {
                         // One byte, boolean?
-                        let _unknown_hash_2 = input.read_u32::<LittleEndian>()?;
-                        let _unknown_hash_1 = input.read_u32::<LittleEndian>()?;
-                        let _unknown_byte_1 = input.read_u8()?;
+                        let hash = input.read_u64::<LittleEndian>()?;
+                        let value = ParameterValue::Bool(input.read_u8()? != 0);
+                        parameters.insert(hash, Parameter::new(hash, value, texture_mapping));
                     }
                 }
                 0x394c43af4ff52c94 => {
                     for _ in 0..mat_section_count {
                         // Three floats
-                        let _unknown_hash_2 = input.read_u32::<LittleEndian>()?;
-                        let _unknown_hash_1 = input.read_u32::<LittleEndian>()?;
-                        let _unknown_float_1 = input.read_f32::<LittleEndian>()?;
-                        let _unknown_float_2 = input.read_f32::<LittleEndian>()?;
-                        let _unknown_float_3 = input.read_f32::<LittleEndian>()?;
+                        let hash = input.read_u64::<LittleEndian>()?;
+                        let value = ParameterValue::Vector3(parse_floats(&mut input)?);
+                        parameters.insert(hash, Parameter::new(hash, value, texture_mapping));
                     }
                 }
                 0x7bbca244e61f1a07 => {
                     for _ in 0..mat_section_count {
                         // Two floats
-                        let _unknown_hash_2 = input.read_u32::<LittleEndian>()?;
-                        let _unknown_hash_1 = input.read_u32::<LittleEndian>()?;
-                        let _unknown_float_1 = input.read_f32::<LittleEndian>()?;
-                        let _unknown_float_2 = input.read_f32::<LittleEndian>()?;
+                        let hash = input.read_u64::<LittleEndian>()?;
+                        let value = ParameterValue::Vector2(parse_floats(&mut input)?);
+                        parameters.insert(hash, Parameter::new(hash, value, texture_mapping));
                     }
                 }
                 0xc16762f7763d62ab => {
                     for _ in 0..mat_section_count {
                         // Four floats
-                        let _unknown_hash_2 = input.read_u32::<LittleEndian>()?;
-                        let _unknown_hash_1 = input.read_u32::<LittleEndian>()?;
-                        let _unknown_float_1 = input.read_f32::<LittleEndian>()?;
-                        let _unknown_float_2 = input.read_f32::<LittleEndian>()?;
-                        let _unknown_float_3 = input.read_f32::<LittleEndian>()?;
-                        let _unknown_float_4 = input.read_f32::<LittleEndian>()?;
+                        let hash = input.read_u64::<LittleEndian>()?;
+                        let value = ParameterValue::Vector4(parse_floats(&mut input)?);
+                        parameters.insert(hash, Parameter::new(hash, value, texture_mapping));
                     }
                 }
                 0x52a09151f1c3f2c7 => {
@@ -157,12 +158,11 @@ impl Material {
                 0xe2ba743e952f9338 => {
                     for _ in 0..mat_section_count {
                         // Two hash sets
-                        let _unknown_hash_2 = input.read_u32::<LittleEndian>()?;
-                        let _unknown_hash_1 = input.read_u32::<LittleEndian>()?;
-                        let _unknown_hash_4 = input.read_u32::<LittleEndian>()?;
-                        let _unknown_hash_3 = input.read_u32::<LittleEndian>()?;
-                        let _unknown_hash_6 = input.read_u32::<LittleEndian>()?;
-                        let _unknown_hash_5 = input.read_u32::<LittleEndian>()?;
+                        let hash = input.read_u64::<LittleEndian>()?;
+                        let first = input.read_u64::<LittleEndian>()?;
+                        let second = input.read_u64::<LittleEndian>()?;
+                        let value = ParameterValue::HashPair(first, second);
+                        parameters.insert(hash, Parameter::new(hash, value, texture_mapping));
                     }
                 }
                 _ => {
@@ -178,8 +178,197 @@ impl Material {
         Ok(Self {
             textures,
             material_id,
+            unknown_hash,
+            unknown_hashes,
+            parameters,
         })
     }
+
+    /// Returns the parameter whose resolved name equals one of the given names, earlier names first.
+    /// The comparison ignores case and any characters that are not alphanumeric,
+    /// e.g. the name "alphatestthreshold" matches "Alpha Test Threshold", but "alphatest" does not.
+    pub fn find_parameter(&self, names: &[&str]) -> Option<&Parameter> {
+        names.iter().find_map(|name| {
+            self.parameters.values().find(|parameter| {
+                parameter
+                    .name
+                    .as_ref()
+                    .is_some_and(|parameter_name| normalize_parameter_name(parameter_name) == *name)
+            })
+        })
+    }
+
+    /// The tint or diffuse color of the material (RGBA).
+    pub fn diffuse_color(&self) -> Option<[f32; 4]> {
+        self.find_parameter(&["diffusecolor", "basecolor", "tint"])
+            .and_then(|parameter| parameter.value.as_color())
+    }
+
+    /// The threshold below which pixels are discarded via alpha testing.
+    pub fn alpha_test_threshold(&self) -> Option<f32> {
+        self.find_parameter(&[
+            "alphatestthreshold",
+            "alphatest",
+            "alphathreshold",
+            "alphacutoff",
+            "alpharef",
+        ])
+        .and_then(|parameter| parameter.value.as_float())
+    }
+
+    /// The color of the emitted light (RGB).
+    pub fn emissive_color(&self) -> Option<[f32; 3]> {
+        self.find_parameter(&["emissivecolor", "emissioncolor", "glowcolor"])
+            .and_then(|parameter| parameter.value.as_color())
+            .map(|color| [color[0], color[1], color[2]])
+    }
+
+    /// The intensity multiplier of the emitted light.
+    pub fn emissive_intensity(&self) -> Option<f32> {
+        self.find_parameter(&[
+            "emissiveintensity",
+            "emissionintensity",
+            "emissivestrength",
+            "glowintensity",
+        ])
+        .and_then(|parameter| parameter.value.as_float())
+    }
+
+    /// The specular power (or gloss exponent) of the material.
+    pub fn specular_power(&self) -> Option<f32> {
+        self.find_parameter(&["specularpower", "specularexponent", "glossexponent"])
+            .and_then(|parameter| parameter.value.as_float())
+    }
+
+    /// The scale that is applied to the texture coordinates (i.e. the tiling).
+    pub fn uv_scale(&self) -> Option<[f32; 2]> {
+        self.find_parameter(&["uvscale", "uvtiling", "texturescale"])
+            .and_then(|parameter| parameter.value.as_vector2())
+    }
+
+    /// The offset that is applied to the texture coordinates.
+    pub fn uv_offset(&self) -> Option<[f32; 2]> {
+        self.find_parameter(&["uvoffset", "textureoffset"])
+            .and_then(|parameter| parameter.value.as_vector2())
+    }
+
+    /// The speed with which the texture coordinates scroll over time (units per second).
+    pub fn uv_scroll_speed(&self) -> Option<[f32; 2]> {
+        self.find_parameter(&["uvscrollspeed", "uvscroll", "scrollspeed"])
+            .and_then(|parameter| parameter.value.as_vector2())
+    }
+}
+
+/// A single decoded material parameter, e.g. a tint color or an alpha test threshold.
+#[derive(Debug, Clone)]
+pub struct Parameter {
+    pub hash: u64,
+    /// The resolved name of the parameter, if the CRC64 is known.
+    pub name: Option<String>,
+    pub value: ParameterValue,
+    /// The resolved names of hash values, e.g. two entries for `ParameterValue::HashPair`.
+    /// Empty for all other values.
+    pub value_names: Vec<Option<String>>,
+}
+
+impl Parameter {
+    fn new(hash: u64, value: ParameterValue, checksum_mapping: &ChecksumMap) -> Self {
+        let name = checksum_mapping.get_mapping(hash);
+        log::debug!(
+            "Material parameter {:016x} ({:?}) = {:?}",
+            hash,
+            name,
+            value
+        );
+        let value_names = match value {
+            ParameterValue::Hash(value) => vec![checksum_mapping.get_mapping(value)],
+            ParameterValue::HashPair(first, second) => vec![
+                checksum_mapping.get_mapping(first),
+                checksum_mapping.get_mapping(second),
+            ],
+            _ => Vec::new(),
+        };
+        Self {
+            hash,
+            name,
+            value,
+            value_names,
+        }
+    }
+}
+
+/// The value of a material parameter.
+/// The variants correspond to the parameter blocks of the material section.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParameterValue {
+    Float(f32),
+    Vector2([f32; 2]),
+    Vector3([f32; 3]),
+    Vector4([f32; 4]),
+    Color([f32; 4]),
+    Bool(bool),
+    Hash(u64),
+    HashPair(u64, u64),
+}
+
+impl ParameterValue {
+    /// A short, stable name of the type of the value.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::Float(_) => "float",
+            Self::Vector2(_) => "vector2",
+            Self::Vector3(_) => "vector3",
+            Self::Vector4(_) => "vector4",
+            Self::Color(_) => "color",
+            Self::Bool(_) => "bool",
+            Self::Hash(_) => "hash",
+            Self::HashPair(_, _) => "hashPair",
+        }
+    }
+
+    /// Returns the value as a single float, if it is a scalar.
+    pub fn as_float(&self) -> Option<f32> {
+        match *self {
+            Self::Float(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as a two-component vector.
+    pub fn as_vector2(&self) -> Option<[f32; 2]> {
+        match *self {
+            Self::Vector2(value) => Some(value),
+            Self::Vector3([x, y, _]) => Some([x, y]),
+            Self::Vector4([x, y, _, _]) | Self::Color([x, y, _, _]) => Some([x, y]),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as RGBA color. Three-component vectors get an alpha of 1.
+    pub fn as_color(&self) -> Option<[f32; 4]> {
+        match *self {
+            Self::Vector3([r, g, b]) => Some([r, g, b, 1.0]),
+            Self::Vector4(value) | Self::Color(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+/// Reads a fixed number of little endian floats.
+fn parse_floats<T: Read, const N: usize>(mut input: T) -> Result<[f32; N]> {
+    let mut values = [0.0; N];
+    for value in &mut values {
+        *value = input.read_f32::<LittleEndian>()?;
+    }
+    Ok(values)
+}
+
+/// Lowercases the name and strips anything that is not alphanumeric.
+fn normalize_parameter_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(|c| c.to_lowercase())
+        .collect()
 }
 
 /// A material groups holds a reference to a specific material.
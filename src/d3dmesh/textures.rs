@@ -5,11 +5,15 @@ use byteorder::{LittleEndian, ReadBytesExt};
 
 use crate::checksum_mapping::ChecksumMap;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Texture {
     pub kind: TextureType,
     pub map: TextureMap,
     pub name: String,
+    /// The CRC64 that identifies the texture slot, i.e. the kind and map of the texture.
+    pub type_hash: u64,
+    /// The CRC64 of the texture name.
+    pub name_hash: u64,
 }
 
 impl Texture {
@@ -91,6 +95,8 @@ impl Texture {
                 kind: TextureType::Unknown,
                 map: TextureMap::Unknown,
                 name: "".to_string(),
+                type_hash,
+                name_hash: texture_hash,
             });
         };
 
@@ -106,11 +112,13 @@ impl Texture {
             kind: texture_type,
             map: texture_map,
             name: texture_name,
+            type_hash,
+            name_hash: texture_hash,
         })
     }
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum TextureType {
     Anisotropy,
     AnisotropyMask,
@@ -153,7 +161,7 @@ pub enum TextureType {
     Unknown,
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum TextureMap {
     Map,
     MapA,
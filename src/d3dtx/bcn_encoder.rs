@@ -0,0 +1,417 @@
+//! Encoding of images to BCn block compressed data, i.e. the reverse of `bcn_image`.
+//!
+//! The encoders aim for reasonable quality at a low complexity: the endpoints of a block are
+//! the extremes of its pixels along their principal axis and each pixel uses the closest
+//! interpolated color. BC7 is always encoded with mode 6 and BC6H with mode 11, i.e. a single
+//! subset per block.
+
+use image::{DynamicImage, GenericImageView};
+
+use super::bcn_image::{
+    alpha_table_dxt5, bc6_finish_unquantize, bc6_unquantize, bc7_interpolate, bc7_unquantize,
+    enc565_decode, BCnVariant, WEIGHTS_4,
+};
+use super::uncompressed::normalized_pixels;
+
+/// Encodes the image with the given BCn variant. Blocks are stored row by row, images whose size
+/// is no multiple of four are padded by repeating their last row and column.
+/// BC4 uses the luminance and BC5 the luminance and alpha of the image (like the decoded images).
+pub fn encode(image: &DynamicImage, variant: BCnVariant) -> Vec<u8> {
+    let width = image.width();
+    let height = image.height();
+    let mut output = Vec::new();
+    match variant {
+        BCnVariant::BC1 | BCnVariant::BC2 | BCnVariant::BC3 | BCnVariant::BC7 => {
+            let pixels: Vec<[u8; 4]> = image.to_rgba8().pixels().map(|pixel| pixel.0).collect();
+            for_each_block(&pixels, width, height, |block| match variant {
+                BCnVariant::BC1 => output.extend_from_slice(&encode_bc1_block(block)),
+                BCnVariant::BC2 => output.extend_from_slice(&encode_bc2_block(block)),
+                BCnVariant::BC3 => output.extend_from_slice(&encode_bc3_block(block)),
+                _ => output.extend_from_slice(&encode_bc7_block(block)),
+            });
+        }
+        BCnVariant::BC4 => {
+            let pixels: Vec<u8> = image.to_luma8().into_raw();
+            for_each_block(&pixels, width, height, |block| {
+                output.extend_from_slice(&encode_alpha_block(block))
+            });
+        }
+        BCnVariant::BC5 => {
+            let pixels: Vec<[u8; 2]> = image
+                .to_luma_alpha8()
+                .pixels()
+                .map(|pixel| pixel.0)
+                .collect();
+            for_each_block(&pixels, width, height, |block| {
+                output.extend_from_slice(&encode_alpha_block(&block.map(|pixel| pixel[0])));
+                output.extend_from_slice(&encode_alpha_block(&block.map(|pixel| pixel[1])));
+            });
+        }
+        BCnVariant::BC6H => {
+            let pixels: Vec<[f32; 3]> = normalized_pixels(image, false)
+                .iter()
+                .map(|pixel| [pixel[0], pixel[1], pixel[2]])
+                .collect();
+            for_each_block(&pixels, width, height, |block| {
+                output.extend_from_slice(&encode_bc6h_block(block))
+            });
+        }
+    }
+    output
+}
+
+/// Calls `f` with the 16 pixels of every 4x4 block, row by row.
+fn for_each_block<T: Copy + Default>(
+    pixels: &[T],
+    width: u32,
+    height: u32,
+    mut f: impl FnMut(&[T; 16]),
+) {
+    let width = width as usize;
+    let height = height as usize;
+    let mut block = [T::default(); 16];
+    for block_y in (0..height).step_by(4) {
+        for block_x in (0..width).step_by(4) {
+            for (index, pixel) in block.iter_mut().enumerate() {
+                let x = (block_x + index % 4).min(width - 1);
+                let y = (block_y + index / 4).min(height - 1);
+                *pixel = pixels[y * width + x];
+            }
+            f(&block);
+        }
+    }
+}
+
+/// Returns the two extremes of the points along their principal axis.
+fn principal_endpoints<const N: usize>(points: &[[f32; N]]) -> ([f32; N], [f32; N]) {
+    if points.is_empty() {
+        return ([0.0; N], [0.0; N]);
+    }
+    let mut mean = [0.0; N];
+    for point in points {
+        for (mean, value) in mean.iter_mut().zip(point) {
+            *mean += value / points.len() as f32;
+        }
+    }
+    let mut covariance = [[0.0; N]; N];
+    for point in points {
+        for row in 0..N {
+            for column in 0..N {
+                covariance[row][column] +=
+                    (point[row] - mean[row]) * (point[column] - mean[column]);
+            }
+        }
+    }
+    // power iteration, starting with the diagonal
+    let mut axis = [1.0; N];
+    for _ in 0..8 {
+        let mut next = [0.0; N];
+        for (row, next) in next.iter_mut().enumerate() {
+            *next = (0..N)
+                .map(|column| covariance[row][column] * axis[column])
+                .sum();
+        }
+        let length = next.iter().map(|value| value * value).sum::<f32>().sqrt();
+        if length < 1e-6 {
+            break;
+        }
+        axis = next.map(|value| value / length);
+    }
+
+    let project = |point: &[f32; N]| -> f32 {
+        point
+            .iter()
+            .zip(&mean)
+            .zip(&axis)
+            .map(|((value, mean), axis)| (value - mean) * axis)
+            .sum()
+    };
+    let (min, max) = points
+        .iter()
+        .fold((f32::MAX, f32::MIN), |(min, max), point| {
+            let projection = project(point);
+            (min.min(projection), max.max(projection))
+        });
+    let mut start = [0.0; N];
+    let mut end = [0.0; N];
+    for channel in 0..N {
+        start[channel] = mean[channel] + axis[channel] * min;
+        end[channel] = mean[channel] + axis[channel] * max;
+    }
+    (start, end)
+}
+
+/// Returns the index of the palette entry that is closest to the value.
+fn closest<const N: usize>(palette: &[[f32; N]], value: &[f32; N]) -> usize {
+    let distance = |entry: &[f32; N]| -> f32 {
+        entry
+            .iter()
+            .zip(value)
+            .map(|(entry, value)| (entry - value) * (entry - value))
+            .sum()
+    };
+    (0..palette.len())
+        .min_by(|a, b| distance(&palette[*a]).total_cmp(&distance(&palette[*b])))
+        .unwrap_or_default()
+}
+
+fn enc565_encode(color: [f32; 3]) -> u16 {
+    let red = (color[0] / 255.0 * 31.0).round().clamp(0.0, 31.0) as u16;
+    let green = (color[1] / 255.0 * 63.0).round().clamp(0.0, 63.0) as u16;
+    let blue = (color[2] / 255.0 * 31.0).round().clamp(0.0, 31.0) as u16;
+    (red << 11) | (green << 5) | blue
+}
+
+/// Encodes the colors of the block to an 8-byte color block with four colors.
+/// BC1 textures are decoded without alpha, so its transparent mode is never used.
+fn encode_color_block(block: &[[u8; 4]; 16]) -> [u8; 8] {
+    let colors: Vec<[f32; 3]> = block
+        .iter()
+        .map(|pixel| [pixel[0], pixel[1], pixel[2]].map(f32::from))
+        .collect();
+    let (start, end) = principal_endpoints(&colors);
+    let mut color0 = enc565_encode(end);
+    let mut color1 = enc565_encode(start);
+    // four colors require color0 > color1 in BC1, equal endpoints need no interpolation anyway
+    if color0 < color1 {
+        std::mem::swap(&mut color0, &mut color1);
+    }
+
+    let endpoint0 = enc565_decode(color0).map(u16::from);
+    let endpoint1 = enc565_decode(color1).map(u16::from);
+    let palette = [
+        endpoint0.map(f32::from),
+        endpoint1.map(f32::from),
+        [0, 1, 2].map(|i| ((endpoint0[i] * 2 + endpoint1[i] + 1) / 3) as f32),
+        [0, 1, 2].map(|i| ((endpoint0[i] + endpoint1[i] * 2 + 1) / 3) as f32),
+    ];
+
+    let mut indices = 0u32;
+    for (pixel_index, pixel) in block.iter().enumerate() {
+        let index = closest(&palette, &[pixel[0], pixel[1], pixel[2]].map(f32::from));
+        indices |= (index as u32) << (pixel_index * 2);
+    }
+
+    let mut output = [0u8; 8];
+    output[0..2].copy_from_slice(&color0.to_le_bytes());
+    output[2..4].copy_from_slice(&color1.to_le_bytes());
+    output[4..8].copy_from_slice(&indices.to_le_bytes());
+    output
+}
+
+/// Encodes 16 values to an 8-byte block of interpolated values (DXT5 alpha, BC4 and BC5).
+fn encode_alpha_block(values: &[u8; 16]) -> [u8; 8] {
+    let max = *values.iter().max().unwrap_or(&0);
+    let min = *values.iter().min().unwrap_or(&0);
+    // max > min selects eight interpolated values
+    let table = alpha_table_dxt5(max, min);
+    let mut indices = 0u64;
+    for (pixel, value) in values.iter().enumerate() {
+        let index = if max == min {
+            0
+        } else {
+            (0..8)
+                .min_by_key(|index| (i32::from(table[*index]) - i32::from(*value)).abs())
+                .unwrap_or_default()
+        };
+        indices |= (index as u64) << (pixel * 3);
+    }
+    let mut output = [0u8; 8];
+    output[0] = max;
+    output[1] = min;
+    output[2..8].copy_from_slice(&indices.to_le_bytes()[0..6]);
+    output
+}
+
+fn encode_bc1_block(block: &[[u8; 4]; 16]) -> [u8; 8] {
+    encode_color_block(block)
+}
+
+fn encode_bc2_block(block: &[[u8; 4]; 16]) -> [u8; 16] {
+    let mut alpha = 0u64;
+    for (pixel, value) in block.iter().enumerate() {
+        let value = (u64::from(value[3]) * 15 + 127) / 255;
+        alpha |= value << (pixel * 4);
+    }
+    let mut output = [0u8; 16];
+    output[0..8].copy_from_slice(&alpha.to_le_bytes());
+    output[8..16].copy_from_slice(&encode_color_block(block));
+    output
+}
+
+fn encode_bc3_block(block: &[[u8; 4]; 16]) -> [u8; 16] {
+    let mut output = [0u8; 16];
+    output[0..8].copy_from_slice(&encode_alpha_block(&block.map(|pixel| pixel[3])));
+    output[8..16].copy_from_slice(&encode_color_block(block));
+    output
+}
+
+/// Writes the bits of a 16-byte BC6H or BC7 block, starting with the least significant bit.
+struct BlockWriter {
+    bits: u128,
+    position: u32,
+}
+
+impl BlockWriter {
+    fn new() -> Self {
+        Self {
+            bits: 0,
+            position: 0,
+        }
+    }
+
+    fn write(&mut self, value: u32, count: u32) {
+        self.bits |= (u128::from(value) & ((1 << count) - 1)) << self.position;
+        self.position += count;
+    }
+
+    fn finish(self) -> [u8; 16] {
+        debug_assert_eq!(self.position, 128);
+        self.bits.to_le_bytes()
+    }
+}
+
+/// Encodes the block with BC7 mode 6, i.e. RGBA endpoints with 7 bits and a p-bit each and
+/// 4-bit indices.
+fn encode_bc7_block(block: &[[u8; 4]; 16]) -> [u8; 16] {
+    let pixels: Vec<[f32; 4]> = block.iter().map(|pixel| pixel.map(f32::from)).collect();
+    let (start, end) = principal_endpoints(&pixels);
+
+    // every endpoint uses the p-bit that represents it best
+    let quantize = |endpoint: [f32; 4]| -> ([u32; 4], u32) {
+        (0..2)
+            .map(|p_bit| {
+                let values = endpoint
+                    .map(|value| ((value - p_bit as f32) / 2.0).round().clamp(0.0, 127.0) as u32);
+                let error: f32 = values
+                    .iter()
+                    .zip(&endpoint)
+                    .map(|(value, target)| {
+                        let value = f32::from(bc7_unquantize((value << 1) | p_bit, 8));
+                        (value - target) * (value - target)
+                    })
+                    .sum();
+                (values, p_bit, error)
+            })
+            .min_by(|a, b| a.2.total_cmp(&b.2))
+            .map(|(values, p_bit, _)| (values, p_bit))
+            .unwrap_or_default()
+    };
+    let mut endpoints = [quantize(start), quantize(end)];
+    let colors = |endpoints: &[([u32; 4], u32); 2]| {
+        endpoints.map(|(values, p_bit)| values.map(|value| bc7_unquantize((value << 1) | p_bit, 8)))
+    };
+    let [color0, color1] = colors(&endpoints);
+    let palette: Vec<[f32; 4]> = (0..16)
+        .map(|index| {
+            [0, 1, 2, 3].map(|channel| {
+                f32::from(bc7_interpolate(color0[channel], color1[channel], index, 4))
+            })
+        })
+        .collect();
+    let mut indices = pixels
+        .iter()
+        .map(|pixel| closest(&palette, pixel) as u32)
+        .collect::<Vec<_>>();
+    // the most significant bit of the first index is implicitly zero
+    if indices[0] >= 8 {
+        endpoints.swap(0, 1);
+        indices.iter_mut().for_each(|index| *index = 15 - *index);
+    }
+
+    let mut writer = BlockWriter::new();
+    writer.write(1 << 6, 7);
+    for channel in 0..4 {
+        for (values, _) in &endpoints {
+            writer.write(values[channel], 7);
+        }
+    }
+    for (_, p_bit) in &endpoints {
+        writer.write(*p_bit, 1);
+    }
+    for (pixel, index) in indices.iter().enumerate() {
+        writer.write(*index, if pixel == 0 { 3 } else { 4 });
+    }
+    writer.finish()
+}
+
+/// Converts a f32 to the bits of an IEEE 754 half-precision float (rounding to nearest).
+/// Values that are too big for a half are stored as infinity.
+pub fn f32_to_half(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    if value.is_nan() {
+        return sign | 0x7E00;
+    }
+    let magnitude = value.abs();
+    if magnitude >= 65520.0 {
+        return sign | 0x7C00;
+    }
+    if magnitude < 2f32.powi(-14) {
+        // subnormal half
+        return sign | (magnitude / 2f32.powi(-24)).round() as u16;
+    }
+    let exponent = ((bits >> 23) & 0xFF) as i32 - 127 + 15;
+    let mantissa = bits & 0x7F_FFFF;
+    // rounding may carry into the exponent, which results in the correct value as well
+    let half = ((exponent as u32) << 10) + ((mantissa + 0x1000) >> 13);
+    sign | half as u16
+}
+
+/// Encodes the block with BC6H mode 11 (unsigned), i.e. RGB endpoints with 10 bits and
+/// 4-bit indices. Negative values are clamped to zero.
+fn encode_bc6h_block(block: &[[f32; 3]; 16]) -> [u8; 16] {
+    // BC6H interpolates the bits of the half floats, which are scaled to 16 bits
+    let to_interpolated =
+        |value: f32| f32::from(f32_to_half(value.max(0.0)).min(0x7BFF)) * 64.0 / 31.0;
+    let pixels: Vec<[f32; 3]> = block
+        .iter()
+        .map(|pixel| pixel.map(to_interpolated))
+        .collect();
+    let (start, end) = principal_endpoints(&pixels);
+
+    let quantize = |value: f32| -> i32 {
+        let estimate = ((value - 32.0) / 64.0).round() as i32;
+        (estimate - 1..=estimate + 1)
+            .map(|candidate| candidate.clamp(0, 1023))
+            .min_by_key(|candidate| (bc6_unquantize(*candidate, 10, false) - value as i32).abs())
+            .unwrap_or_default()
+    };
+    let mut endpoints = [start.map(quantize), end.map(quantize)];
+    let unquantized =
+        endpoints.map(|endpoint| endpoint.map(|value| bc6_unquantize(value, 10, false)));
+    let palette: Vec<[f32; 3]> = WEIGHTS_4
+        .iter()
+        .map(|weight| {
+            let weight = *weight as i32;
+            [0, 1, 2].map(|channel| {
+                let value = ((64 - weight) * unquantized[0][channel]
+                    + weight * unquantized[1][channel]
+                    + 32)
+                    >> 6;
+                f32::from(bc6_finish_unquantize(value, false)) * 64.0 / 31.0
+            })
+        })
+        .collect();
+    let mut indices = pixels
+        .iter()
+        .map(|pixel| closest(&palette, pixel) as u32)
+        .collect::<Vec<_>>();
+    // the most significant bit of the first index is implicitly zero
+    if indices[0] >= 8 {
+        endpoints.swap(0, 1);
+        indices.iter_mut().for_each(|index| *index = 15 - *index);
+    }
+
+    let mut writer = BlockWriter::new();
+    writer.write(0x03, 5);
+    for endpoint in &endpoints {
+        for value in endpoint {
+            writer.write(*value as u32, 10);
+        }
+    }
+    for (pixel, index) in indices.iter().enumerate() {
+        writer.write(*index, if pixel == 0 { 3 } else { 4 });
+    }
+    writer.finish()
+}
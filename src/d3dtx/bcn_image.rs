@@ -24,13 +24,15 @@ OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 SOFTWARE.
 */
 
-//!  Decoding of DXT (S3TC) compression
+//!  Decoding of DXT (S3TC) compression and its successors BC6H and BC7 (BPTC)
 //!
 //!  DXT is an image format that supports lossy compression
 //!
 //!  # Related Links
 //!  * <https://www.khronos.org/registry/OpenGL/extensions/EXT/EXT_texture_compression_s3tc.txt> - Description of the DXT compression OpenGL extensions.
 //!  * <http://sv-journal.org/2014-1/06.php?lang=en> - Texture Compression Techniques (T. Paltashev and I. Perminov; 2014)
+//!  * <https://www.khronos.org/registry/OpenGL/extensions/ARB/ARB_texture_compression_bptc.txt> - Description of the BC6H and BC7 formats.
+//!  * <https://docs.microsoft.com/en-us/windows/win32/direct3d11/bc6h-format> - Bit layouts of the BC6H modes.
 //!
 //!  Note: this module only implements bare DXT encoding/decoding, it does not parse formats that can contain DXT files like .dds
 
@@ -38,7 +40,13 @@ use std::convert::TryFrom;
 use std::io::{self, Read};
 
 use anyhow::{anyhow, Result};
-use image::{ColorType, DynamicImage, GrayAlphaImage, GrayImage, RgbImage, RgbaImage};
+use image::{
+    ColorType, DynamicImage, GrayAlphaImage, GrayImage, ImageBuffer, Rgb as RgbPixel, RgbImage,
+    RgbaImage,
+};
+
+/// An RGB image with floating point channels, e.g. the result of decoding a BC6H texture.
+pub type Rgb32FImage = ImageBuffer<RgbPixel<f32>, Vec<f32>>;
 
 /// What version of BCn compression are we using?
 /// Note that DXT2 and DXT4 are left away as they're
@@ -66,6 +74,12 @@ pub enum BCnVariant {
     /// 32 bytes of RG data in a 4x4 pixel square is compressed into a 16 byte
     /// block of two DXT5 alpha data.
     BC5,
+    /// The BC6H format (unsigned). 16 RGB half-float values in a 4x4 pixel square
+    /// are compressed into a 16 byte block. The decoded values are HDR, i.e. not limited to [0, 1].
+    BC6H,
+    /// The BC7 format. 64 bytes of RGBA data in a 4x4 pixel square is
+    /// compressed into a 16 byte block using one of eight modes.
+    BC7,
 }
 
 impl BCnVariant {
@@ -74,17 +88,18 @@ impl BCnVariant {
     const fn decoded_bytes_per_block(self) -> usize {
         match self {
             Self::BC1 => 48,
-            Self::BC2 | Self::BC3 => 64,
+            Self::BC2 | Self::BC3 | Self::BC7 => 64,
             Self::BC4 => 16,
             Self::BC5 => 32,
+            Self::BC6H => 96,
         }
     }
 
     /// Returns the amount of bytes per block of encoded DXTn data
-    const fn encoded_bytes_per_block(self) -> usize {
+    pub const fn encoded_bytes_per_block(self) -> usize {
         match self {
             Self::BC1 | Self::BC4 => 8,
-            Self::BC2 | Self::BC3 | Self::BC5 => 16,
+            Self::BC2 | Self::BC3 | Self::BC5 | Self::BC6H | Self::BC7 => 16,
         }
     }
 
@@ -92,9 +107,10 @@ impl BCnVariant {
     pub const fn color_type(self) -> ColorType {
         match self {
             Self::BC1 => ColorType::Rgb8,
-            Self::BC2 | Self::BC3 => ColorType::Rgba8,
+            Self::BC2 | Self::BC3 | Self::BC7 => ColorType::Rgba8,
             Self::BC4 => ColorType::L8,
             Self::BC5 => ColorType::La8,
+            Self::BC6H => ColorType::Rgb16,
         }
     }
 }
@@ -146,10 +162,30 @@ impl<R: Read> DxtDecoder<R> {
             BCnVariant::BC3 => decode_dxt5_row(&src, buf),
             BCnVariant::BC4 => decode_bc4_row(&src, buf),
             BCnVariant::BC5 => decode_bc5_row(&src, buf),
+            BCnVariant::BC6H => unreachable!("BC6H is decoded by read_hdr_image"),
+            BCnVariant::BC7 => decode_bc7_row(&src, buf),
         }
         self.row += 1;
         Ok(buf.len())
     }
+
+    /// Reads the image data of a BC6H texture as floating point values.
+    pub fn read_hdr_image(mut self) -> Result<Rgb32FImage> {
+        if self.variant != BCnVariant::BC6H {
+            return Err(anyhow!("{:?} does not contain HDR data", self.variant));
+        }
+        let row_values = 48 * self.width_blocks as usize;
+        let mut buf = vec![0.0f32; row_values * self.height_blocks as usize];
+        let mut src = vec![0u8; 16 * self.width_blocks as usize];
+        for chunk in buf.chunks_mut(row_values) {
+            self.inner.read_exact(&mut src)?;
+            decode_bc6h_row(&src, chunk);
+            self.row += 1;
+        }
+
+        let (width, height) = (self.width_blocks * 4, self.height_blocks * 4);
+        Rgb32FImage::from_vec(width, height, buf).ok_or(anyhow!("could not construct Rgb32F image"))
+    }
 }
 
 // Note that, due to the way that DXT compression works, a scanline is considered to consist out of
@@ -177,6 +213,11 @@ impl<'a, R: 'a + Read> DxtDecoder<R> {
     */
 
     pub fn read_image(mut self) -> Result<DynamicImage> {
+        if self.variant == BCnVariant::BC6H {
+            let hdr_image = self.read_hdr_image()?;
+            return Ok(DynamicImage::ImageRgb16(hdr_to_rgb16(&hdr_image)));
+        }
+
         let mut buf = vec![0; self.total_bytes() as usize];
 
         for chunk in buf.chunks_mut(self.scanline_bytes() as usize) {
@@ -219,7 +260,7 @@ type Rgb = [u8; 3];
 /// decodes a 5-bit R, 6-bit G, 5-bit B 16-bit packed color value into 8-bit RGB
 /// mapping is done so min/max range values are preserved. So for 5-bit
 /// values 0x00 -> 0x00 and 0x1F -> 0xFF
-fn enc565_decode(value: u16) -> Rgb {
+pub(super) fn enc565_decode(value: u16) -> Rgb {
     let red = (value >> 11) & 0x1F;
     let green = (value >> 5) & 0x3F;
     let blue = (value) & 0x1F;
@@ -233,7 +274,7 @@ fn enc565_decode(value: u16) -> Rgb {
 /// Constructs the DXT5 alpha lookup table from the two alpha entries
 /// if alpha0 > alpha1, constructs a table of [a0, a1, 6 linearly interpolated values from a0 to a1]
 /// if alpha0 <= alpha1, constructs a table of [a0, a1, 4 linearly interpolated values from a0 to a1, 0, 0xFF]
-fn alpha_table_dxt5(alpha0: u8, alpha1: u8) -> [u8; 8] {
+pub(super) fn alpha_table_dxt5(alpha0: u8, alpha1: u8) -> [u8; 8] {
     let mut table = [alpha0, alpha1, 0, 0, 0, 0, 0, 0xFF];
     if alpha0 > alpha1 {
         for i in 2..8u16 {
@@ -502,3 +543,934 @@ fn decode_bc5_row(source: &[u8], dest: &mut [u8]) {
         }
     }
 }
+
+/// Converts an HDR image to a 16-bit image by clamping all values to [0, 1].
+pub fn hdr_to_rgb16(image: &Rgb32FImage) -> image::ImageBuffer<RgbPixel<u16>, Vec<u16>> {
+    let data = image
+        .as_raw()
+        .iter()
+        .map(|value| (value.clamp(0.0, 1.0) * f32::from(u16::MAX)).round() as u16)
+        .collect();
+    image::ImageBuffer::from_vec(image.width(), image.height(), data)
+        .expect("buffer has the same dimensions as the HDR image")
+}
+
+/// Reads the bits of a 16-byte BC6H or BC7 block, starting with the least significant bit.
+struct BlockBits {
+    bits: u128,
+}
+
+impl BlockBits {
+    fn new(source: &[u8]) -> Self {
+        let mut bytes = [0u8; 16];
+        bytes.copy_from_slice(source);
+        Self {
+            bits: u128::from_le_bytes(bytes),
+        }
+    }
+
+    fn read(&mut self, count: u32) -> u32 {
+        if count == 0 {
+            return 0;
+        }
+        let value = (self.bits & ((1 << count) - 1)) as u32;
+        self.bits >>= count;
+        value
+    }
+}
+
+/// Interpolation weights for 2-, 3- and 4-bit indices, shared by BC6H and BC7.
+const WEIGHTS_2: [u32; 4] = [0, 21, 43, 64];
+const WEIGHTS_3: [u32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+pub(super) const WEIGHTS_4: [u32; 16] =
+    [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+fn weight(index: u32, index_bits: u32) -> u32 {
+    match index_bits {
+        2 => WEIGHTS_2[index as usize],
+        3 => WEIGHTS_3[index as usize],
+        _ => WEIGHTS_4[index as usize],
+    }
+}
+
+/// Partitions of blocks with two subsets, one bit per pixel selects the subset.
+/// The first 32 entries are shared with BC6H.
+const PARTITIONS_2: [u16; 64] = [
+    0xCCCC, 0x8888, 0xEEEE, 0xECC8, 0xC880, 0xFEEC, 0xFEC8, 0xEC80, 0xC800, 0xFFEC, 0xFE80, 0xE800,
+    0xFFE8, 0xFF00, 0xFFF0, 0xF000, 0xF710, 0x008E, 0x7100, 0x08CE, 0x008C, 0x7310, 0x3100, 0x8CCE,
+    0x088C, 0x3110, 0x6666, 0x366C, 0x17E8, 0x0FF0, 0x718E, 0x399C, 0xAAAA, 0xF0F0, 0x5A5A, 0x33CC,
+    0x3C3C, 0x55AA, 0x9696, 0xA55A, 0x73CE, 0x13C8, 0x324C, 0x3BDC, 0x6996, 0xC33C, 0x9966, 0x0660,
+    0x0272, 0x04E4, 0x4E40, 0x2720, 0xC936, 0x936C, 0x39C6, 0x639C, 0x9336, 0x9CC6, 0x817E, 0xE718,
+    0xCCF0, 0x0FCC, 0x7744, 0xEE22,
+];
+
+/// Partitions of blocks with three subsets, two bits per pixel select the subset.
+const PARTITIONS_3: [u32; 64] = [
+    0xAA685050, 0x6A5A5040, 0x5A5A4200, 0x5450A0A8, 0xA5A50000, 0xA0A05050, 0x5555A0A0, 0x5A5A5050,
+    0xAA550000, 0xAA555500, 0xAAAA5500, 0x90909090, 0x94949494, 0xA4A4A4A4, 0xA9A59450, 0x2A0A4250,
+    0xA5945040, 0x0A425054, 0xA5A5A500, 0x55A0A0A0, 0xA8A85454, 0x6A6A4040, 0xA4A45000, 0x1A1A0500,
+    0x0050A4A4, 0xAAA59090, 0x14696914, 0x69691400, 0xA08585A0, 0xAA821414, 0x50A4A450, 0x6A5A0200,
+    0xA9A58000, 0x5090A0A8, 0xA8A09050, 0x24242424, 0x00AA5500, 0x24924924, 0x24499224, 0x50A50A50,
+    0x500AA550, 0xAAAA4444, 0x66660000, 0xA5A0A5A0, 0x50A050A0, 0x69286928, 0x44AAAA44, 0x66666600,
+    0xAA444444, 0x54A854A8, 0x95809580, 0x96969600, 0xA85454A8, 0x80959580, 0xAA141414, 0x96960000,
+    0xAAAA1414, 0xA05050A0, 0xA0A5A5A0, 0x96000000, 0x40804080, 0xA9A8A9A8, 0xAAAAAA44, 0x2A4A5254,
+];
+
+/// Anchor pixel of the second subset of two-subset partitions.
+const ANCHORS_2: [u8; 64] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 2, 8, 2, 2, 8, 8, 15, 2, 8,
+    2, 2, 8, 8, 2, 2, 15, 15, 6, 8, 2, 8, 15, 15, 2, 8, 2, 2, 2, 15, 15, 6, 6, 2, 6, 8, 15, 15, 2,
+    2, 15, 15, 15, 15, 15, 2, 2, 15,
+];
+
+/// Anchor pixel of the second subset of three-subset partitions.
+const ANCHORS_3_SECOND: [u8; 64] = [
+    3, 3, 15, 15, 8, 3, 15, 15, 8, 8, 6, 6, 6, 5, 3, 3, 3, 3, 8, 15, 3, 3, 6, 10, 5, 8, 8, 6, 8, 5,
+    15, 15, 8, 15, 3, 5, 6, 10, 8, 15, 15, 3, 15, 5, 15, 15, 15, 15, 3, 15, 5, 5, 5, 8, 5, 10, 5,
+    10, 8, 13, 15, 12, 3, 3,
+];
+
+/// Anchor pixel of the third subset of three-subset partitions.
+const ANCHORS_3_THIRD: [u8; 64] = [
+    15, 8, 8, 3, 15, 15, 3, 8, 15, 15, 15, 15, 15, 15, 15, 8, 15, 8, 15, 3, 15, 8, 15, 8, 3, 15, 6,
+    10, 15, 15, 10, 8, 15, 3, 15, 10, 10, 8, 9, 10, 6, 15, 8, 15, 3, 6, 6, 8, 15, 3, 15, 15, 15,
+    15, 15, 15, 15, 15, 15, 15, 3, 15, 15, 8,
+];
+
+/// Returns the subset the pixel belongs to.
+fn partition_subset(subsets: usize, partition: usize, pixel: usize) -> usize {
+    match subsets {
+        2 => (PARTITIONS_2[partition] >> pixel) as usize & 1,
+        3 => (PARTITIONS_3[partition] >> (2 * pixel)) as usize & 3,
+        _ => 0,
+    }
+}
+
+/// Anchor pixels store their index with one bit less, as its most significant bit is always zero.
+fn is_anchor(subsets: usize, partition: usize, pixel: usize) -> bool {
+    pixel == 0
+        || match subsets {
+            2 => ANCHORS_2[partition] as usize == pixel,
+            3 => {
+                ANCHORS_3_SECOND[partition] as usize == pixel
+                    || ANCHORS_3_THIRD[partition] as usize == pixel
+            }
+            _ => false,
+        }
+}
+
+/// The properties of one of the eight BC7 modes
+struct Bc7Mode {
+    subsets: usize,
+    partition_bits: u32,
+    rotation_bits: u32,
+    index_selection_bits: u32,
+    color_bits: u32,
+    alpha_bits: u32,
+    /// one p-bit per endpoint
+    endpoint_p_bits: bool,
+    /// one p-bit per subset
+    shared_p_bits: bool,
+    index_bits: u32,
+    secondary_index_bits: u32,
+}
+
+#[rustfmt::skip]
+const BC7_MODES: [Bc7Mode; 8] = [
+    Bc7Mode { subsets: 3, partition_bits: 4, rotation_bits: 0, index_selection_bits: 0, color_bits: 4, alpha_bits: 0, endpoint_p_bits: true, shared_p_bits: false, index_bits: 3, secondary_index_bits: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 6, alpha_bits: 0, endpoint_p_bits: false, shared_p_bits: true, index_bits: 3, secondary_index_bits: 0 },
+    Bc7Mode { subsets: 3, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 5, alpha_bits: 0, endpoint_p_bits: false, shared_p_bits: false, index_bits: 2, secondary_index_bits: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 7, alpha_bits: 0, endpoint_p_bits: true, shared_p_bits: false, index_bits: 2, secondary_index_bits: 0 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 2, index_selection_bits: 1, color_bits: 5, alpha_bits: 6, endpoint_p_bits: false, shared_p_bits: false, index_bits: 2, secondary_index_bits: 3 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 2, index_selection_bits: 0, color_bits: 7, alpha_bits: 8, endpoint_p_bits: false, shared_p_bits: false, index_bits: 2, secondary_index_bits: 2 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 0, index_selection_bits: 0, color_bits: 7, alpha_bits: 7, endpoint_p_bits: true, shared_p_bits: false, index_bits: 4, secondary_index_bits: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 5, alpha_bits: 5, endpoint_p_bits: true, shared_p_bits: false, index_bits: 2, secondary_index_bits: 0 },
+];
+
+/// Expands a quantized endpoint component with `bits` bits (including p-bit) to 8 bits
+pub(super) fn bc7_unquantize(value: u32, bits: u32) -> u8 {
+    let value = value << (8 - bits);
+    (value | (value >> bits)) as u8
+}
+
+pub(super) fn bc7_interpolate(e0: u8, e1: u8, index: u32, index_bits: u32) -> u8 {
+    let weight = weight(index, index_bits);
+    (((64 - weight) * u32::from(e0) + weight * u32::from(e1) + 32) >> 6) as u8
+}
+
+/// Decodes a 16-byte block of BC7 data to a 16xRGBA block
+fn decode_bc7_block(source: &[u8], dest: &mut [u8]) {
+    assert!(source.len() == 16 && dest.len() == 64);
+
+    // the mode is encoded as the position of the lowest set bit
+    let mode_number = source[0].trailing_zeros() as usize;
+    if mode_number >= BC7_MODES.len() {
+        // reserved mode, decoders have to return transparent black
+        dest.iter_mut().for_each(|value| *value = 0);
+        return;
+    }
+    let mode = &BC7_MODES[mode_number];
+
+    let mut bits = BlockBits::new(source);
+    bits.read(mode_number as u32 + 1);
+    let partition = bits.read(mode.partition_bits) as usize;
+    let rotation = bits.read(mode.rotation_bits);
+    let index_selection = bits.read(mode.index_selection_bits);
+
+    // endpoints are stored channel by channel
+    let endpoint_count = mode.subsets * 2;
+    let mut endpoints = [[0u32; 4]; 6];
+    for channel in 0..3 {
+        for endpoint in endpoints.iter_mut().take(endpoint_count) {
+            endpoint[channel] = bits.read(mode.color_bits);
+        }
+    }
+    if mode.alpha_bits > 0 {
+        for endpoint in endpoints.iter_mut().take(endpoint_count) {
+            endpoint[3] = bits.read(mode.alpha_bits);
+        }
+    }
+
+    // p-bits are appended as the least significant bit to every channel of an endpoint
+    let mut color_bits = mode.color_bits;
+    let mut alpha_bits = mode.alpha_bits;
+    if mode.endpoint_p_bits || mode.shared_p_bits {
+        let mut p_bits = [0u32; 6];
+        if mode.endpoint_p_bits {
+            for p_bit in p_bits.iter_mut().take(endpoint_count) {
+                *p_bit = bits.read(1);
+            }
+        } else {
+            for subset in 0..mode.subsets {
+                let p_bit = bits.read(1);
+                p_bits[subset * 2] = p_bit;
+                p_bits[subset * 2 + 1] = p_bit;
+            }
+        }
+        let channels = if alpha_bits > 0 { 4 } else { 3 };
+        for (endpoint, p_bit) in endpoints.iter_mut().zip(p_bits.iter()) {
+            for value in endpoint.iter_mut().take(channels) {
+                *value = (*value << 1) | p_bit;
+            }
+        }
+        color_bits += 1;
+        if alpha_bits > 0 {
+            alpha_bits += 1;
+        }
+    }
+
+    let mut colors = [[0u8; 4]; 6];
+    for (color, endpoint) in colors.iter_mut().zip(endpoints.iter()) {
+        for channel in 0..3 {
+            color[channel] = bc7_unquantize(endpoint[channel], color_bits);
+        }
+        color[3] = if alpha_bits > 0 {
+            bc7_unquantize(endpoint[3], alpha_bits)
+        } else {
+            0xFF
+        };
+    }
+
+    let mut indices = [0u32; 16];
+    for (pixel, index) in indices.iter_mut().enumerate() {
+        let anchor = is_anchor(mode.subsets, partition, pixel);
+        *index = bits.read(mode.index_bits - anchor as u32);
+    }
+    let mut secondary_indices = [0u32; 16];
+    if mode.secondary_index_bits > 0 {
+        for (pixel, index) in secondary_indices.iter_mut().enumerate() {
+            *index = bits.read(mode.secondary_index_bits - (pixel == 0) as u32);
+        }
+    }
+
+    for pixel in 0..16 {
+        let subset = partition_subset(mode.subsets, partition, pixel);
+        let e0 = colors[subset * 2];
+        let e1 = colors[subset * 2 + 1];
+        let (color_index, color_index_bits, alpha_index, alpha_index_bits) =
+            if mode.secondary_index_bits == 0 {
+                (
+                    indices[pixel],
+                    mode.index_bits,
+                    indices[pixel],
+                    mode.index_bits,
+                )
+            } else if index_selection == 0 {
+                (
+                    indices[pixel],
+                    mode.index_bits,
+                    secondary_indices[pixel],
+                    mode.secondary_index_bits,
+                )
+            } else {
+                (
+                    secondary_indices[pixel],
+                    mode.secondary_index_bits,
+                    indices[pixel],
+                    mode.index_bits,
+                )
+            };
+
+        let mut rgba = [0u8; 4];
+        for channel in 0..3 {
+            rgba[channel] =
+                bc7_interpolate(e0[channel], e1[channel], color_index, color_index_bits);
+        }
+        rgba[3] = bc7_interpolate(e0[3], e1[3], alpha_index, alpha_index_bits);
+        // the rotation swaps the alpha channel with one of the color channels
+        if rotation > 0 {
+            rgba.swap(3, rotation as usize - 1);
+        }
+        dest[pixel * 4..pixel * 4 + 4].copy_from_slice(&rgba);
+    }
+}
+
+/// Decode a row of BC7 data to four rows of RGBA data.
+/// source.len() should be a multiple of 16, otherwise this panics.
+fn decode_bc7_row(source: &[u8], dest: &mut [u8]) {
+    assert!(source.len().is_multiple_of(16));
+    let block_count = source.len() / 16;
+    assert!(dest.len() >= block_count * 64);
+
+    // contains the 16 decoded pixels per block
+    let mut decoded_block = [0u8; 64];
+
+    for (x, encoded_block) in source.chunks(16).enumerate() {
+        decode_bc7_block(encoded_block, &mut decoded_block);
+
+        // copy the values from the decoded block to linewise RGBA layout
+        for line in 0..4 {
+            let offset = (block_count * line + x) * 16;
+            dest[offset..offset + 16].copy_from_slice(&decoded_block[line * 16..(line + 1) * 16]);
+        }
+    }
+}
+
+/// A value stored in the header of a BC6H block:
+/// a channel (R, G, B) of one of the four endpoints or the partition index (D).
+#[derive(Clone, Copy)]
+enum Bc6Field {
+    R(usize),
+    G(usize),
+    B(usize),
+    D,
+}
+
+use Bc6Field::{B, D, G, R};
+
+/// A range of bits of a field in the notation of the specification, i.e. `(field, msb, lsb)`
+/// corresponds to `field[msb:lsb]`. The bits are stored starting with `lsb`. Some modes store bits
+/// in reversed order, those ranges have `msb < lsb`.
+type Bc6Bits = (Bc6Field, u8, u8);
+
+/// The properties of one of the fourteen BC6H modes
+struct Bc6Mode {
+    /// value of the mode bits
+    mode_value: u32,
+    /// whether endpoints other than the first are stored as a delta
+    transformed: bool,
+    endpoint_bits: u32,
+    delta_bits: [u32; 3],
+    subsets: usize,
+    layout: &'static [Bc6Bits],
+}
+
+#[rustfmt::skip]
+const BC6_MODES: [Bc6Mode; 14] = [
+    Bc6Mode { mode_value: 0x00, transformed: true, endpoint_bits: 10, delta_bits: [5, 5, 5], subsets: 2, layout: &[
+        (G(2), 4, 4), (B(2), 4, 4), (B(3), 4, 4), (R(0), 9, 0), (G(0), 9, 0), (B(0), 9, 0), (R(1), 4, 0), (G(3), 4, 4),
+        (G(2), 3, 0), (G(1), 4, 0), (B(3), 0, 0), (G(3), 3, 0), (B(1), 4, 0), (B(3), 1, 1), (B(2), 3, 0), (R(2), 4, 0),
+        (B(3), 2, 2), (R(3), 4, 0), (B(3), 3, 3), (D, 4, 0),
+    ] },
+    Bc6Mode { mode_value: 0x01, transformed: true, endpoint_bits: 7, delta_bits: [6, 6, 6], subsets: 2, layout: &[
+        (G(2), 5, 5), (G(3), 4, 4), (G(3), 5, 5), (R(0), 6, 0), (B(3), 0, 0), (B(3), 1, 1), (B(2), 4, 4), (G(0), 6, 0),
+        (B(2), 5, 5), (B(3), 2, 2), (G(2), 4, 4), (B(0), 6, 0), (B(3), 3, 3), (B(3), 5, 5), (B(3), 4, 4), (R(1), 5, 0),
+        (G(2), 3, 0), (G(1), 5, 0), (G(3), 3, 0), (B(1), 5, 0), (B(2), 3, 0), (R(2), 5, 0), (R(3), 5, 0), (D, 4, 0),
+    ] },
+    Bc6Mode { mode_value: 0x02, transformed: true, endpoint_bits: 11, delta_bits: [5, 4, 4], subsets: 2, layout: &[
+        (R(0), 9, 0), (G(0), 9, 0), (B(0), 9, 0), (R(1), 4, 0), (R(0), 10, 10), (G(2), 3, 0), (G(1), 3, 0), (G(0), 10, 10),
+        (B(3), 0, 0), (G(3), 3, 0), (B(1), 3, 0), (B(0), 10, 10), (B(3), 1, 1), (B(2), 3, 0), (R(2), 4, 0), (B(3), 2, 2),
+        (R(3), 4, 0), (B(3), 3, 3), (D, 4, 0),
+    ] },
+    Bc6Mode { mode_value: 0x06, transformed: true, endpoint_bits: 11, delta_bits: [4, 5, 4], subsets: 2, layout: &[
+        (R(0), 9, 0), (G(0), 9, 0), (B(0), 9, 0), (R(1), 3, 0), (R(0), 10, 10), (G(3), 4, 4), (G(2), 3, 0), (G(1), 4, 0),
+        (G(0), 10, 10), (G(3), 3, 0), (B(1), 3, 0), (B(0), 10, 10), (B(3), 1, 1), (B(2), 3, 0), (R(2), 3, 0), (B(3), 0, 0),
+        (B(3), 2, 2), (R(3), 3, 0), (G(2), 4, 4), (B(3), 3, 3), (D, 4, 0),
+    ] },
+    Bc6Mode { mode_value: 0x0A, transformed: true, endpoint_bits: 11, delta_bits: [4, 4, 5], subsets: 2, layout: &[
+        (R(0), 9, 0), (G(0), 9, 0), (B(0), 9, 0), (R(1), 3, 0), (R(0), 10, 10), (B(2), 4, 4), (G(2), 3, 0), (G(1), 3, 0),
+        (G(0), 10, 10), (B(3), 0, 0), (G(3), 3, 0), (B(1), 4, 0), (B(0), 10, 10), (B(2), 3, 0), (R(2), 3, 0), (B(3), 1, 1),
+        (B(3), 2, 2), (R(3), 3, 0), (B(3), 4, 4), (B(3), 3, 3), (D, 4, 0),
+    ] },
+    Bc6Mode { mode_value: 0x0E, transformed: true, endpoint_bits: 9, delta_bits: [5, 5, 5], subsets: 2, layout: &[
+        (R(0), 8, 0), (B(2), 4, 4), (G(0), 8, 0), (G(2), 4, 4), (B(0), 8, 0), (B(3), 4, 4), (R(1), 4, 0), (G(3), 4, 4),
+        (G(2), 3, 0), (G(1), 4, 0), (B(3), 0, 0), (G(3), 3, 0), (B(1), 4, 0), (B(3), 1, 1), (B(2), 3, 0), (R(2), 4, 0),
+        (B(3), 2, 2), (R(3), 4, 0), (B(3), 3, 3), (D, 4, 0),
+    ] },
+    Bc6Mode { mode_value: 0x12, transformed: true, endpoint_bits: 8, delta_bits: [6, 5, 5], subsets: 2, layout: &[
+        (R(0), 7, 0), (G(3), 4, 4), (B(2), 4, 4), (G(0), 7, 0), (B(3), 2, 2), (G(2), 4, 4), (B(0), 7, 0), (B(3), 3, 3),
+        (B(3), 4, 4), (R(1), 5, 0), (G(2), 3, 0), (G(1), 4, 0), (B(3), 0, 0), (G(3), 3, 0), (B(1), 4, 0), (B(3), 1, 1),
+        (B(2), 3, 0), (R(2), 5, 0), (R(3), 5, 0), (D, 4, 0),
+    ] },
+    Bc6Mode { mode_value: 0x16, transformed: true, endpoint_bits: 8, delta_bits: [5, 6, 5], subsets: 2, layout: &[
+        (R(0), 7, 0), (B(3), 0, 0), (B(2), 4, 4), (G(0), 7, 0), (G(2), 5, 5), (G(2), 4, 4), (B(0), 7, 0), (G(3), 5, 5),
+        (B(3), 4, 4), (R(1), 4, 0), (G(3), 4, 4), (G(2), 3, 0), (G(1), 5, 0), (G(3), 3, 0), (B(1), 4, 0), (B(3), 1, 1),
+        (B(2), 3, 0), (R(2), 4, 0), (B(3), 2, 2), (R(3), 4, 0), (B(3), 3, 3), (D, 4, 0),
+    ] },
+    Bc6Mode { mode_value: 0x1A, transformed: true, endpoint_bits: 8, delta_bits: [5, 5, 6], subsets: 2, layout: &[
+        (R(0), 7, 0), (B(3), 1, 1), (B(2), 4, 4), (G(0), 7, 0), (B(2), 5, 5), (G(2), 4, 4), (B(0), 7, 0), (B(3), 5, 5),
+        (B(3), 4, 4), (R(1), 4, 0), (G(3), 4, 4), (G(2), 3, 0), (G(1), 4, 0), (B(3), 0, 0), (G(3), 3, 0), (B(1), 5, 0),
+        (B(2), 3, 0), (R(2), 4, 0), (B(3), 2, 2), (R(3), 4, 0), (B(3), 3, 3), (D, 4, 0),
+    ] },
+    Bc6Mode { mode_value: 0x1E, transformed: false, endpoint_bits: 6, delta_bits: [6, 6, 6], subsets: 2, layout: &[
+        (R(0), 5, 0), (G(3), 4, 4), (B(3), 0, 0), (B(3), 1, 1), (B(2), 4, 4), (G(0), 5, 0), (G(2), 5, 5), (B(2), 5, 5),
+        (B(3), 2, 2), (G(2), 4, 4), (B(0), 5, 0), (G(3), 5, 5), (B(3), 3, 3), (B(3), 5, 5), (B(3), 4, 4), (R(1), 5, 0),
+        (G(2), 3, 0), (G(1), 5, 0), (G(3), 3, 0), (B(1), 5, 0), (B(2), 3, 0), (R(2), 5, 0), (R(3), 5, 0), (D, 4, 0),
+    ] },
+    Bc6Mode { mode_value: 0x03, transformed: false, endpoint_bits: 10, delta_bits: [10, 10, 10], subsets: 1, layout: &[
+        (R(0), 9, 0), (G(0), 9, 0), (B(0), 9, 0), (R(1), 9, 0), (G(1), 9, 0), (B(1), 9, 0),
+    ] },
+    Bc6Mode { mode_value: 0x07, transformed: true, endpoint_bits: 11, delta_bits: [9, 9, 9], subsets: 1, layout: &[
+        (R(0), 9, 0), (G(0), 9, 0), (B(0), 9, 0), (R(1), 8, 0), (R(0), 10, 10), (G(1), 8, 0), (G(0), 10, 10), (B(1), 8, 0),
+        (B(0), 10, 10),
+    ] },
+    Bc6Mode { mode_value: 0x0B, transformed: true, endpoint_bits: 12, delta_bits: [8, 8, 8], subsets: 1, layout: &[
+        (R(0), 9, 0), (G(0), 9, 0), (B(0), 9, 0), (R(1), 7, 0), (R(0), 10, 11), (G(1), 7, 0), (G(0), 10, 11), (B(1), 7, 0),
+        (B(0), 10, 11),
+    ] },
+    Bc6Mode { mode_value: 0x0F, transformed: true, endpoint_bits: 16, delta_bits: [4, 4, 4], subsets: 1, layout: &[
+        (R(0), 9, 0), (G(0), 9, 0), (B(0), 9, 0), (R(1), 3, 0), (R(0), 10, 15), (G(1), 3, 0), (G(0), 10, 15), (B(1), 3, 0),
+        (B(0), 10, 15),
+    ] },
+];
+
+fn sign_extend(value: i32, bits: u32) -> i32 {
+    let shift = 32 - bits;
+    (value << shift) >> shift
+}
+
+/// Scales an endpoint component with `bits` bits to the 16-bit range used for interpolation
+pub(super) fn bc6_unquantize(value: i32, bits: u32, signed: bool) -> i32 {
+    if signed {
+        if bits >= 16 {
+            return value;
+        }
+        let magnitude = value.abs();
+        let unquantized = if magnitude == 0 {
+            0
+        } else if magnitude >= (1 << (bits - 1)) - 1 {
+            0x7FFF
+        } else {
+            ((magnitude << 15) + 0x4000) >> (bits - 1)
+        };
+        if value < 0 {
+            -unquantized
+        } else {
+            unquantized
+        }
+    } else if bits >= 15 {
+        value
+    } else if value == 0 {
+        0
+    } else if value == (1 << bits) - 1 {
+        0xFFFF
+    } else {
+        ((value << 16) + 0x8000) >> bits
+    }
+}
+
+/// Scales an interpolated value to the bits of a half-float
+pub(super) fn bc6_finish_unquantize(value: i32, signed: bool) -> u16 {
+    if signed {
+        if value < 0 {
+            0x8000 | (((-value) * 31) >> 5) as u16
+        } else {
+            ((value * 31) >> 5) as u16
+        }
+    } else {
+        ((value * 31) >> 6) as u16
+    }
+}
+
+/// Converts the bits of an IEEE 754 half-precision float to a f32
+pub fn half_to_f32(half: u16) -> f32 {
+    let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = i32::from((half >> 10) & 0x1F);
+    let mantissa = f32::from(half & 0x3FF);
+    match exponent {
+        0 => sign * mantissa * 2f32.powi(-24),
+        0x1F if mantissa == 0.0 => sign * f32::INFINITY,
+        0x1F => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+/// Decodes a 16-byte block of BC6H data to a 16xRGB block of floats
+fn decode_bc6h_block(source: &[u8], dest: &mut [f32], signed: bool) {
+    assert!(source.len() == 16 && dest.len() == 48);
+
+    let mut bits = BlockBits::new(source);
+    let mut mode_value = bits.read(2);
+    if mode_value > 1 {
+        mode_value |= bits.read(3) << 2;
+    }
+    let mode = match BC6_MODES.iter().find(|mode| mode.mode_value == mode_value) {
+        Some(mode) => mode,
+        None => {
+            // reserved mode, decoders have to return black
+            dest.iter_mut().for_each(|value| *value = 0.0);
+            return;
+        }
+    };
+
+    let mut endpoints = [[0i32; 3]; 4];
+    let mut partition = 0;
+    for &(field, msb, lsb) in mode.layout {
+        let value = match field {
+            R(endpoint) => &mut endpoints[endpoint][0],
+            G(endpoint) => &mut endpoints[endpoint][1],
+            B(endpoint) => &mut endpoints[endpoint][2],
+            D => &mut partition,
+        };
+        if msb >= lsb {
+            *value |= (bits.read(u32::from(msb - lsb) + 1) as i32) << lsb;
+        } else {
+            for bit in (msb..=lsb).rev() {
+                *value |= (bits.read(1) as i32) << bit;
+            }
+        }
+    }
+    let partition = partition as usize;
+
+    // sign extension and inverse transform of the deltas
+    let endpoint_count = mode.subsets * 2;
+    let endpoint_bits = mode.endpoint_bits;
+    for channel in 0..3 {
+        if signed {
+            endpoints[0][channel] = sign_extend(endpoints[0][channel], endpoint_bits);
+        }
+        if signed || mode.transformed {
+            let bits = if mode.transformed {
+                mode.delta_bits[channel]
+            } else {
+                endpoint_bits
+            };
+            for endpoint in endpoints.iter_mut().take(endpoint_count).skip(1) {
+                endpoint[channel] = sign_extend(endpoint[channel], bits);
+            }
+        }
+        if mode.transformed {
+            let base = endpoints[0][channel];
+            let mask = (1 << endpoint_bits) - 1;
+            for endpoint in endpoints.iter_mut().take(endpoint_count).skip(1) {
+                endpoint[channel] = (endpoint[channel] + base) & mask;
+                if signed {
+                    endpoint[channel] = sign_extend(endpoint[channel], endpoint_bits);
+                }
+            }
+        }
+        for endpoint in endpoints.iter_mut().take(endpoint_count) {
+            endpoint[channel] = bc6_unquantize(endpoint[channel], endpoint_bits, signed);
+        }
+    }
+
+    let index_bits = if mode.subsets == 2 { 3 } else { 4 };
+    for pixel in 0..16 {
+        let anchor = is_anchor(mode.subsets, partition, pixel);
+        let index = bits.read(index_bits - anchor as u32);
+        let weight = weight(index, index_bits) as i32;
+        let subset = partition_subset(mode.subsets, partition, pixel);
+        let e0 = endpoints[subset * 2];
+        let e1 = endpoints[subset * 2 + 1];
+        for channel in 0..3 {
+            let value = ((64 - weight) * e0[channel] + weight * e1[channel] + 32) >> 6;
+            dest[pixel * 3 + channel] = half_to_f32(bc6_finish_unquantize(value, signed));
+        }
+    }
+}
+
+/// Decode a row of BC6H data to four rows of RGB float data.
+/// source.len() should be a multiple of 16, otherwise this panics.
+fn decode_bc6h_row(source: &[u8], dest: &mut [f32]) {
+    assert!(source.len().is_multiple_of(16));
+    let block_count = source.len() / 16;
+    assert!(dest.len() >= block_count * 48);
+
+    // contains the 16 decoded pixels per block
+    let mut decoded_block = [0f32; 48];
+
+    for (x, encoded_block) in source.chunks(16).enumerate() {
+        decode_bc6h_block(encoded_block, &mut decoded_block, false);
+
+        // copy the values from the decoded block to linewise RGB layout
+        for line in 0..4 {
+            let offset = (block_count * line + x) * 12;
+            dest[offset..offset + 12].copy_from_slice(&decoded_block[line * 12..(line + 1) * 12]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes the fields of a 16-byte block, starting with the least significant bit.
+    struct BitWriter {
+        bits: u128,
+        position: u32,
+    }
+
+    impl BitWriter {
+        fn new() -> Self {
+            Self {
+                bits: 0,
+                position: 0,
+            }
+        }
+
+        fn write(&mut self, value: u32, count: u32) -> &mut Self {
+            assert!(count == 32 || value >> count == 0, "value does not fit");
+            self.bits |= u128::from(value) << self.position;
+            self.position += count;
+            self
+        }
+
+        fn write_all(&mut self, values: &[u32], count: u32) -> &mut Self {
+            for &value in values {
+                self.write(value, count);
+            }
+            self
+        }
+
+        /// Writes one index per pixel, anchor pixels have one bit less.
+        fn write_indices(
+            &mut self,
+            indices: &[u32; 16],
+            bits: u32,
+            anchors: &[usize],
+        ) -> &mut Self {
+            for (pixel, &index) in indices.iter().enumerate() {
+                let anchor = pixel == 0 || anchors.contains(&pixel);
+                self.write(index, bits - anchor as u32);
+            }
+            self
+        }
+
+        fn block(&self) -> [u8; 16] {
+            assert_eq!(self.position, 128, "block is incomplete");
+            self.bits.to_le_bytes()
+        }
+    }
+
+    fn bc7_pixels(block: &[u8; 16]) -> Vec<[u8; 4]> {
+        let mut dest = [0xAAu8; 64];
+        decode_bc7_block(block, &mut dest);
+        dest.chunks(4)
+            .map(|pixel| [pixel[0], pixel[1], pixel[2], pixel[3]])
+            .collect()
+    }
+
+    fn bc6h_pixels(block: &[u8; 16], signed: bool) -> Vec<[f32; 3]> {
+        let mut dest = [1.0f32; 48];
+        decode_bc6h_block(block, &mut dest, signed);
+        dest.chunks(3)
+            .map(|pixel| [pixel[0], pixel[1], pixel[2]])
+            .collect()
+    }
+
+    #[test]
+    fn bc7_mode_0() {
+        // three subsets of partition 0, 4-bit endpoints with a p-bit each
+        let block = BitWriter::new()
+            .write(0b1, 1)
+            .write(0, 4)
+            .write_all(&[15, 15, 0, 0, 0, 0], 4)
+            .write_all(&[0, 0, 15, 15, 0, 0], 4)
+            .write_all(&[0, 0, 0, 0, 15, 15], 4)
+            .write_all(&[1; 6], 1)
+            .write_indices(&[0; 16], 3, &[3, 15])
+            .block();
+        let subsets = [0, 0, 1, 1, 0, 0, 1, 1, 0, 2, 2, 1, 2, 2, 2, 2];
+        let colors = [[255, 8, 8, 255], [8, 255, 8, 255], [8, 8, 255, 255]];
+        let expected: Vec<_> = subsets.iter().map(|&subset| colors[subset]).collect();
+        assert_eq!(bc7_pixels(&block), expected);
+    }
+
+    #[test]
+    fn bc7_mode_1() {
+        // two subsets of partition 13 (upper and lower half), 6-bit endpoints with shared p-bits
+        let block = BitWriter::new()
+            .write(0b10, 2)
+            .write(13, 6)
+            .write_all(&[0, 63, 63, 63], 6)
+            .write_all(&[0, 63, 0, 0], 6)
+            .write_all(&[0, 63, 32, 32], 6)
+            .write_all(&[1, 0], 1)
+            .write_indices(&[0, 4, 7, 7, 7, 7, 7, 7, 0, 0, 0, 0, 0, 0, 0, 0], 3, &[15])
+            .block();
+        let pixels = bc7_pixels(&block);
+        assert_eq!(pixels[0], [2, 2, 2, 255]);
+        assert_eq!(pixels[1], [148, 148, 148, 255]);
+        assert!(pixels[2..8].iter().all(|&pixel| pixel == [255; 4]));
+        assert!(pixels[8..].iter().all(|&pixel| pixel == [253, 0, 129, 255]));
+    }
+
+    #[test]
+    fn bc7_mode_2() {
+        // three subsets of partition 13 (columns 0, 1 and 2-3), 5-bit endpoints without p-bits
+        let block = BitWriter::new()
+            .write(0b100, 3)
+            .write(13, 6)
+            .write_all(&[31, 31, 0, 0, 0, 0], 5)
+            .write_all(&[0, 0, 31, 31, 0, 0], 5)
+            .write_all(&[0, 0, 0, 0, 31, 0], 5)
+            .write_indices(
+                &[0, 0, 1, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+                2,
+                &[5, 15],
+            )
+            .block();
+        let pixels = bc7_pixels(&block);
+        for row in 0..4 {
+            assert_eq!(pixels[row * 4], [255, 0, 0, 255]);
+            assert_eq!(pixels[row * 4 + 1], [0, 255, 0, 255]);
+        }
+        assert_eq!(pixels[2], [0, 0, 171, 255]);
+        assert_eq!(pixels[3], [0, 0, 0, 255]);
+        assert_eq!(pixels[6], [0, 0, 255, 255]);
+        assert_eq!(pixels[15], [0, 0, 171, 255]);
+    }
+
+    #[test]
+    fn bc7_mode_3() {
+        // two subsets of partition 1 (last column), 7-bit endpoints with a p-bit each
+        let block = BitWriter::new()
+            .write(0b1000, 4)
+            .write(1, 6)
+            .write_all(&[127, 127, 0, 0], 7)
+            .write_all(&[0, 0, 127, 127], 7)
+            .write_all(&[64, 64, 0, 0], 7)
+            .write_all(&[1, 0, 1, 1], 1)
+            .write_indices(&[0, 3, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], 2, &[15])
+            .block();
+        let pixels = bc7_pixels(&block);
+        assert_eq!(pixels[0], [255, 1, 129, 255]);
+        assert_eq!(pixels[1], [254, 0, 128, 255]);
+        assert_eq!(pixels[2], [255, 1, 129, 255]);
+        for row in 0..4 {
+            assert_eq!(pixels[row * 4 + 3], [1, 255, 1, 255]);
+        }
+    }
+
+    #[test]
+    fn bc7_mode_4() {
+        // rotation 1 swaps red and alpha, index selection 1 interpolates the color with the 3-bit indices
+        let mut secondary = [0; 16];
+        secondary[1] = 7;
+        let mut primary = [3; 16];
+        primary[0] = 0;
+        primary[1] = 1;
+        let block = BitWriter::new()
+            .write(0b10000, 5)
+            .write(1, 2)
+            .write(1, 1)
+            .write_all(&[31, 0], 5)
+            .write_all(&[0, 31], 5)
+            .write_all(&[0, 0], 5)
+            .write_all(&[0, 63], 6)
+            .write_indices(&primary, 2, &[])
+            .write_indices(&secondary, 3, &[])
+            .block();
+        let pixels = bc7_pixels(&block);
+        assert_eq!(pixels[0], [0, 0, 0, 255]);
+        assert_eq!(pixels[1], [84, 255, 0, 0]);
+        assert!(pixels[2..].iter().all(|&pixel| pixel == [255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn bc7_mode_5() {
+        // separate indices for color and alpha, 8-bit alpha endpoints without p-bits
+        let mut color = [3; 16];
+        color[0] = 0;
+        color[1] = 1;
+        let mut alpha = [3; 16];
+        alpha[0] = 0;
+        alpha[1] = 2;
+        let block = BitWriter::new()
+            .write(0b100000, 6)
+            .write(0, 2)
+            .write_all(&[127, 0], 7)
+            .write_all(&[0, 0], 7)
+            .write_all(&[0, 127], 7)
+            .write_all(&[200, 100], 8)
+            .write_indices(&color, 2, &[])
+            .write_indices(&alpha, 2, &[])
+            .block();
+        let pixels = bc7_pixels(&block);
+        assert_eq!(pixels[0], [255, 0, 0, 200]);
+        assert_eq!(pixels[1], [171, 0, 84, 133]);
+        assert!(pixels[2..].iter().all(|&pixel| pixel == [0, 0, 255, 100]));
+    }
+
+    #[test]
+    fn bc7_mode_6() {
+        // one subset with 4-bit indices, 7-bit endpoints with a p-bit each
+        let block = BitWriter::new()
+            .write(0b1000000, 7)
+            .write_all(&[127, 0], 7)
+            .write_all(&[0, 127], 7)
+            .write_all(&[64, 64], 7)
+            .write_all(&[127, 127], 7)
+            .write_all(&[1, 0], 1)
+            .write_indices(&[0, 15, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], 4, &[])
+            .block();
+        let pixels = bc7_pixels(&block);
+        assert_eq!(pixels[0], [255, 1, 129, 255]);
+        assert_eq!(pixels[1], [0, 254, 128, 254]);
+        assert_eq!(pixels[2], [120, 135, 128, 254]);
+        assert!(pixels[3..].iter().all(|&pixel| pixel == [255, 1, 129, 255]));
+    }
+
+    #[test]
+    fn bc7_mode_7() {
+        // two subsets of partition 13 with alpha, 5-bit endpoints with a p-bit each
+        let block = BitWriter::new()
+            .write(0b10000000, 8)
+            .write(13, 6)
+            .write_all(&[31, 31, 0, 0], 5)
+            .write_all(&[0, 0, 31, 31], 5)
+            .write_all(&[0, 0, 0, 0], 5)
+            .write_all(&[31, 31, 0, 0], 5)
+            .write_all(&[1, 1, 0, 0], 1)
+            .write_indices(&[0; 16], 2, &[15])
+            .block();
+        let pixels = bc7_pixels(&block);
+        assert!(pixels[..8].iter().all(|&pixel| pixel == [255, 4, 4, 255]));
+        assert!(pixels[8..].iter().all(|&pixel| pixel == [0, 251, 0, 0]));
+    }
+
+    #[test]
+    fn bc7_reserved_mode() {
+        let pixels = bc7_pixels(&[0; 16]);
+        assert!(pixels.iter().all(|&pixel| pixel == [0; 4]));
+        let mut block = [0xFF; 16];
+        block[0] = 0;
+        assert!(bc7_pixels(&block).iter().all(|&pixel| pixel == [0; 4]));
+    }
+
+    #[test]
+    fn bc6h_unsigned_mode_11() {
+        // one subset, 10-bit endpoints without transform
+        let mut indices = [0; 16];
+        indices[1] = 15;
+        let block = BitWriter::new()
+            .write(0x03, 5)
+            .write_all(&[0, 512, 1023], 10)
+            .write_all(&[1023, 0, 512], 10)
+            .write_indices(&indices, 4, &[])
+            .block();
+        let pixels = bc6h_pixels(&block, false);
+        assert_eq!(pixels[0], [0.0, 1551.0 / 1024.0, 65504.0]);
+        assert_eq!(pixels[1], [65504.0, 0.0, 1551.0 / 1024.0]);
+        assert!(pixels[2..]
+            .iter()
+            .all(|&pixel| pixel == [0.0, 1551.0 / 1024.0, 65504.0]));
+    }
+
+    #[test]
+    fn bc6h_unsigned_mode_12() {
+        // one subset, 11-bit base endpoint and 9-bit deltas, which wrap around
+        let mut indices = [0; 16];
+        indices[1] = 15;
+        let block = BitWriter::new()
+            .write(0x07, 5)
+            .write(1024 & 0x3FF, 10)
+            .write(0, 10)
+            .write(2047 & 0x3FF, 10)
+            .write(512 - 24, 9)
+            .write(1024 >> 10, 1)
+            .write(0, 9)
+            .write(0, 1)
+            .write(1, 9)
+            .write(2047 >> 10, 1)
+            .write_indices(&indices, 4, &[])
+            .block();
+        let pixels = bc6h_pixels(&block, false);
+        assert_eq!(pixels[0], [1543.0 / 1024.0, 0.0, 65504.0]);
+        assert_eq!(pixels[1], [1171.0 / 1024.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn bc6h_signed_mode_11() {
+        // negative endpoints are stored as two's complement
+        let block = BitWriter::new()
+            .write(0x03, 5)
+            .write_all(&[1024 - 100, 100, 0], 10)
+            .write_all(&[0, 0, 0], 10)
+            .write_indices(&[0; 16], 4, &[])
+            .block();
+        let pixels = bc6h_pixels(&block, true);
+        let value = 1111.0 / 1024.0 / 512.0;
+        assert!(pixels.iter().all(|&pixel| pixel == [-value, value, 0.0]));
+    }
+
+    #[test]
+    fn bc6h_unsigned_mode_10() {
+        // two subsets of partition 13 (upper and lower half), 6-bit endpoints without transform,
+        // whose bits are scattered over the header
+        let (r0, g0, b0) = (63, 0, 32);
+        let (r1, g1, b1) = (0, 63, 0);
+        let (r2, g2, b2) = (0, 48, 63);
+        let (r3, g3, b3) = (32, 33, 47);
+        let bit = |value: u32, index: u32| (value >> index) & 1;
+        let mut indices = [0; 16];
+        indices[1] = 7;
+        indices[14] = 7;
+        let block = BitWriter::new()
+            .write(0x1E, 5)
+            .write(r0, 6)
+            .write(bit(g3, 4), 1)
+            .write(bit(b3, 0), 1)
+            .write(bit(b3, 1), 1)
+            .write(bit(b2, 4), 1)
+            .write(g0, 6)
+            .write(bit(g2, 5), 1)
+            .write(bit(b2, 5), 1)
+            .write(bit(b3, 2), 1)
+            .write(bit(g2, 4), 1)
+            .write(b0, 6)
+            .write(bit(g3, 5), 1)
+            .write(bit(b3, 3), 1)
+            .write(bit(b3, 5), 1)
+            .write(bit(b3, 4), 1)
+            .write(r1, 6)
+            .write(g2 & 0xF, 4)
+            .write(g1, 6)
+            .write(g3 & 0xF, 4)
+            .write(b1, 6)
+            .write(b2 & 0xF, 4)
+            .write(r2, 6)
+            .write(r3, 6)
+            .write(13, 5)
+            .write_indices(&indices, 3, &[15])
+            .block();
+        let pixels = bc6h_pixels(&block, false);
+        assert_eq!(pixels[0], [65504.0, 0.0, 1.7421875]);
+        assert_eq!(pixels[1], [0.0, 65504.0, 0.0]);
+        assert_eq!(pixels[8], [0.0, 382.0, 65504.0]);
+        assert_eq!(pixels[14], [1.7421875, 2.453125, 258.0]);
+        assert_eq!(pixels[15], [0.0, 382.0, 65504.0]);
+    }
+
+    #[test]
+    fn bc6h_reserved_modes() {
+        for mode in [0x13, 0x17, 0x1B, 0x1F] {
+            let mut block = [0xFF; 16];
+            block[0] = 0xE0 | mode;
+            for signed in [false, true] {
+                let pixels = bc6h_pixels(&block, signed);
+                assert!(pixels.iter().all(|&pixel| pixel == [0.0; 3]));
+            }
+        }
+    }
+}
@@ -0,0 +1,326 @@
+//! Writing of DDS files with the unmodified data of d3dtx textures.
+//!
+//! BC1 to BC3 use the legacy header with a FourCC code, all other formats (and sRGB textures
+//! as well as arrays) use the DX10 header extension with a DXGI format.
+//!
+//! # Related Links
+//! * <https://docs.microsoft.com/en-us/windows/win32/direct3ddds/dx-graphics-dds-pguide> - Programming guide for DDS.
+
+use std::io::Write;
+
+use anyhow::{anyhow, Context, Result};
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use super::{BCnVariant, ColorSpace, PixelFormat, Texture, TextureFormat, TextureLayout};
+
+const DDSD_CAPS: u32 = 0x1;
+const DDSD_HEIGHT: u32 = 0x2;
+const DDSD_WIDTH: u32 = 0x4;
+const DDSD_PITCH: u32 = 0x8;
+const DDSD_PIXELFORMAT: u32 = 0x1000;
+const DDSD_MIPMAPCOUNT: u32 = 0x20000;
+const DDSD_LINEARSIZE: u32 = 0x80000;
+const DDSD_DEPTH: u32 = 0x800000;
+
+const DDPF_FOURCC: u32 = 0x4;
+
+const DDSCAPS_COMPLEX: u32 = 0x8;
+const DDSCAPS_TEXTURE: u32 = 0x1000;
+const DDSCAPS_MIPMAP: u32 = 0x400000;
+
+const DDSCAPS2_CUBEMAP_ALL_FACES: u32 = 0x200 | 0xFC00;
+const DDSCAPS2_VOLUME: u32 = 0x200000;
+
+const D3D10_RESOURCE_DIMENSION_TEXTURE2D: u32 = 3;
+const D3D10_RESOURCE_DIMENSION_TEXTURE3D: u32 = 4;
+const D3D10_RESOURCE_MISC_TEXTURECUBE: u32 = 0x4;
+
+/// How the format is stored in the DDS header
+enum DdsFormat {
+    FourCC(&'static [u8; 4]),
+    Dxgi(u32),
+}
+
+impl DdsFormat {
+    /// `is_array` forces the DX10 header, since only it stores the number of array elements.
+    fn new(format: TextureFormat, color_space: Option<ColorSpace>, is_array: bool) -> Result<Self> {
+        if color_space == Some(ColorSpace::Srgb) {
+            let srgb_format = match format {
+                TextureFormat::BCn(BCnVariant::BC1) => Some(72),
+                TextureFormat::BCn(BCnVariant::BC2) => Some(75),
+                TextureFormat::BCn(BCnVariant::BC3) => Some(78),
+                TextureFormat::BCn(BCnVariant::BC7) => Some(99),
+                TextureFormat::Uncompressed(PixelFormat::ARGB8) => Some(91),
+                TextureFormat::Uncompressed(PixelFormat::RGBA8) => Some(29),
+                _ => None,
+            };
+            if let Some(srgb_format) = srgb_format {
+                return Ok(Self::Dxgi(srgb_format));
+            }
+        }
+        Ok(match format {
+            TextureFormat::BCn(BCnVariant::BC1) if is_array => Self::Dxgi(71),
+            TextureFormat::BCn(BCnVariant::BC2) if is_array => Self::Dxgi(74),
+            TextureFormat::BCn(BCnVariant::BC3) if is_array => Self::Dxgi(77),
+            TextureFormat::BCn(BCnVariant::BC1) => Self::FourCC(b"DXT1"),
+            TextureFormat::BCn(BCnVariant::BC2) => Self::FourCC(b"DXT3"),
+            TextureFormat::BCn(BCnVariant::BC3) => Self::FourCC(b"DXT5"),
+            TextureFormat::BCn(BCnVariant::BC4) => Self::Dxgi(80),
+            TextureFormat::BCn(BCnVariant::BC5) => Self::Dxgi(83),
+            TextureFormat::BCn(BCnVariant::BC6H) => Self::Dxgi(95),
+            TextureFormat::BCn(BCnVariant::BC7) => Self::Dxgi(98),
+            TextureFormat::Uncompressed(format) => Self::Dxgi(match format {
+                PixelFormat::ARGB8 => 87,
+                PixelFormat::ARGB16 | PixelFormat::RGBA16 => 11,
+                PixelFormat::RGB565 => 85,
+                PixelFormat::ARGB1555 => 86,
+                PixelFormat::ARGB4 => 115,
+                PixelFormat::ARGB2101010 => 24,
+                PixelFormat::R16 | PixelFormat::L16 => 56,
+                PixelFormat::RG16 => 35,
+                PixelFormat::RG8 | PixelFormat::AL8 => 49,
+                PixelFormat::RGBA8 => 28,
+                PixelFormat::R8 | PixelFormat::L8 => 61,
+                PixelFormat::RGBA8S => 31,
+                PixelFormat::A8 => 65,
+                PixelFormat::R16F => 54,
+                PixelFormat::RG16F => 34,
+                PixelFormat::RGBA16F => 10,
+                PixelFormat::R32F => 41,
+                PixelFormat::RG32F => 16,
+                PixelFormat::RGBA32F => 2,
+                PixelFormat::RGB111110F => 26,
+                PixelFormat::RGB9E5F => 67,
+            }),
+            TextureFormat::Unknown(code) => {
+                return Err(anyhow!(
+                    "texture format {} ({:#04X}) can not be stored as DDS",
+                    code,
+                    code
+                ))
+            }
+        })
+    }
+}
+
+/// Writes the texture with all of its mip-maps, faces and slices as DDS file.
+/// The pixel data is copied without any conversion.
+/// sRGB textures use the sRGB variant of their format, if there is one.
+pub fn write_dds<W: Write>(texture: &Texture, mut out: W) -> Result<()> {
+    let is_array = texture.array_size > 1 || texture.layout == TextureLayout::CubeArray;
+    let format = DdsFormat::new(texture.format, texture.color_space, is_array)?;
+    let biggest_mip = texture
+        .mips
+        .first()
+        .ok_or(anyhow!("texture does not contain any mip-map"))?;
+    let is_cube = texture.layout.is_cube();
+    let is_volume = texture.layout == TextureLayout::Volume;
+
+    let mut flags = DDSD_CAPS | DDSD_HEIGHT | DDSD_WIDTH | DDSD_PIXELFORMAT | DDSD_MIPMAPCOUNT;
+    let pitch_or_linear_size = match texture.format {
+        TextureFormat::Uncompressed(format) => {
+            flags |= DDSD_PITCH;
+            biggest_mip.width * format.bytes_per_pixel() as u32
+        }
+        _ => {
+            flags |= DDSD_LINEARSIZE;
+            biggest_mip.data.len() as u32 / biggest_mip.depth
+        }
+    };
+    if is_volume {
+        flags |= DDSD_DEPTH;
+    }
+    let mut caps = DDSCAPS_TEXTURE;
+    if texture.mip_count > 1 {
+        caps |= DDSCAPS_MIPMAP | DDSCAPS_COMPLEX;
+    }
+    if is_cube || is_volume {
+        caps |= DDSCAPS_COMPLEX;
+    }
+    let caps2 = if is_cube {
+        DDSCAPS2_CUBEMAP_ALL_FACES
+    } else if is_volume {
+        DDSCAPS2_VOLUME
+    } else {
+        0
+    };
+
+    out.write_all(b"DDS ")?;
+    // DDS_HEADER
+    out.write_u32::<LittleEndian>(124)?;
+    out.write_u32::<LittleEndian>(flags)?;
+    out.write_u32::<LittleEndian>(biggest_mip.height)?;
+    out.write_u32::<LittleEndian>(biggest_mip.width)?;
+    out.write_u32::<LittleEndian>(pitch_or_linear_size)?;
+    out.write_u32::<LittleEndian>(if is_volume { texture.depth } else { 0 })?;
+    out.write_u32::<LittleEndian>(texture.mip_count)?;
+    out.write_all(&[0; 11 * 4])?;
+    // DDS_PIXELFORMAT, always a FourCC code (DX10 for the extended header)
+    out.write_u32::<LittleEndian>(32)?;
+    out.write_u32::<LittleEndian>(DDPF_FOURCC)?;
+    match format {
+        DdsFormat::FourCC(code) => out.write_all(code)?,
+        DdsFormat::Dxgi(_) => out.write_all(b"DX10")?,
+    }
+    out.write_all(&[0; 5 * 4])?;
+    out.write_u32::<LittleEndian>(caps)?;
+    out.write_u32::<LittleEndian>(caps2)?;
+    out.write_all(&[0; 3 * 4])?;
+
+    if let DdsFormat::Dxgi(dxgi_format) = format {
+        // DDS_HEADER_DXT10
+        out.write_u32::<LittleEndian>(dxgi_format)?;
+        out.write_u32::<LittleEndian>(if is_volume {
+            D3D10_RESOURCE_DIMENSION_TEXTURE3D
+        } else {
+            D3D10_RESOURCE_DIMENSION_TEXTURE2D
+        })?;
+        out.write_u32::<LittleEndian>(if is_cube {
+            D3D10_RESOURCE_MISC_TEXTURECUBE
+        } else {
+            0
+        })?;
+        // the number of cubes for cube maps
+        out.write_u32::<LittleEndian>(match texture.layout {
+            TextureLayout::Array2D | TextureLayout::CubeArray => texture.array_size,
+            _ => 1,
+        })?;
+        out.write_u32::<LittleEndian>(0)?;
+    }
+
+    // DDS stores all mips of a face after each other
+    let mut mips: Vec<_> = texture.mips.iter().collect();
+    mips.sort_by_key(|mip| (mip.face, mip.level));
+    for mip in mips {
+        out.write_all(&mip.data)
+            .with_context(|| format!("could not write mip-map level {}", mip.level))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::{ByteOrder, LittleEndian};
+
+    use super::*;
+    use crate::d3dtx::test_files::texture;
+
+    const HEADER_SIZE: usize = 4 + 124;
+    const DX10_HEADER_SIZE: usize = 20;
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        LittleEndian::read_u32(&data[offset..])
+    }
+
+    fn write(texture: &Texture) -> Vec<u8> {
+        let mut data = Vec::new();
+        write_dds(texture, &mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn dx10_header_of_bc4_bc5_and_bc7() {
+        for (variant, dxgi_format) in [
+            (BCnVariant::BC4, 80),
+            (BCnVariant::BC5, 83),
+            (BCnVariant::BC7, 98),
+        ] {
+            let texture = texture(
+                TextureFormat::BCn(variant),
+                TextureLayout::Texture2D,
+                1,
+                8,
+                8,
+                2,
+            );
+            let data = write(&texture);
+            assert_eq!(&data[..4], b"DDS ");
+            assert_eq!(u32_at(&data, 4), 124);
+            assert_eq!(
+                u32_at(&data, 8),
+                DDSD_CAPS
+                    | DDSD_HEIGHT
+                    | DDSD_WIDTH
+                    | DDSD_PIXELFORMAT
+                    | DDSD_MIPMAPCOUNT
+                    | DDSD_LINEARSIZE
+            );
+            assert_eq!((u32_at(&data, 12), u32_at(&data, 16)), (8, 8));
+            assert_eq!(
+                u32_at(&data, 20),
+                4 * variant.encoded_bytes_per_block() as u32
+            );
+            assert_eq!(u32_at(&data, 28), 2);
+            // the pixel format refers to the DX10 header
+            assert_eq!(u32_at(&data, 76), 32);
+            assert_eq!(u32_at(&data, 80), DDPF_FOURCC);
+            assert_eq!(&data[84..88], b"DX10");
+            assert_eq!(
+                u32_at(&data, 108),
+                DDSCAPS_TEXTURE | DDSCAPS_MIPMAP | DDSCAPS_COMPLEX
+            );
+            assert_eq!(u32_at(&data, 112), 0);
+            // DXGI format, dimension, misc flags, array size, misc flags 2
+            let dx10: Vec<u32> = (0..5)
+                .map(|index| u32_at(&data, HEADER_SIZE + index * 4))
+                .collect();
+            assert_eq!(
+                dx10,
+                [dxgi_format, D3D10_RESOURCE_DIMENSION_TEXTURE2D, 0, 1, 0],
+                "{:?}",
+                variant
+            );
+            let data_size: usize = texture.mips.iter().map(|mip| mip.data.len()).sum();
+            assert_eq!(data.len(), HEADER_SIZE + DX10_HEADER_SIZE + data_size);
+        }
+    }
+
+    #[test]
+    fn legacy_header_of_bc1() {
+        let texture = texture(
+            TextureFormat::BCn(BCnVariant::BC1),
+            TextureLayout::Texture2D,
+            1,
+            4,
+            4,
+            1,
+        );
+        let data = write(&texture);
+        assert_eq!(&data[84..88], b"DXT1");
+        assert_eq!(u32_at(&data, 108), DDSCAPS_TEXTURE);
+        assert_eq!(data.len(), HEADER_SIZE + 8);
+    }
+
+    #[test]
+    fn cube_map_with_mips() {
+        let texture = texture(
+            TextureFormat::BCn(BCnVariant::BC7),
+            TextureLayout::Cube,
+            1,
+            8,
+            8,
+            2,
+        );
+        let data = write(&texture);
+        assert_eq!(
+            u32_at(&data, 108),
+            DDSCAPS_TEXTURE | DDSCAPS_MIPMAP | DDSCAPS_COMPLEX
+        );
+        assert_eq!(u32_at(&data, 112), DDSCAPS2_CUBEMAP_ALL_FACES);
+        assert_eq!(u32_at(&data, HEADER_SIZE), 98);
+        assert_eq!(
+            u32_at(&data, HEADER_SIZE + 8),
+            D3D10_RESOURCE_MISC_TEXTURECUBE
+        );
+        assert_eq!(u32_at(&data, HEADER_SIZE + 12), 1);
+
+        // all mip-maps of a face follow each other, the texture stores all faces of a level
+        // after each other, i.e. each face consists of 64 bytes of level 0 and 16 of level 1
+        let surfaces = &data[HEADER_SIZE + DX10_HEADER_SIZE..];
+        assert_eq!(surfaces.len(), 6 * (64 + 16));
+        for (face, surface) in surfaces.chunks(64 + 16).enumerate() {
+            assert_eq!(surface[..64], [face as u8; 64]);
+            assert_eq!(surface[64..], [16 + face as u8; 16]);
+        }
+    }
+}
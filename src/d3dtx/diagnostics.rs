@@ -0,0 +1,291 @@
+//! Diagnostic decoding of .d3dtx files with every plausible format.
+//!
+//! Used to validate the mapping of Telltales surface format codes (see `TextureFormat::parse`):
+//! the data of the biggest mip-map is decoded with every format that fits its byte size and the
+//! results are scored, so that wrong interpretations can be spotted without inspecting every image.
+
+use std::io::{Read, Seek};
+
+use anyhow::{anyhow, Context, Result};
+use image::{DynamicImage, GenericImageView};
+
+use super::{bcn_image::BCnVariant, uncompressed::PixelFormat, D3DTXHeader, TextureFormat};
+
+const BCN_VARIANTS: [BCnVariant; 7] = [
+    BCnVariant::BC1,
+    BCnVariant::BC2,
+    BCnVariant::BC3,
+    BCnVariant::BC4,
+    BCnVariant::BC5,
+    BCnVariant::BC6H,
+    BCnVariant::BC7,
+];
+
+const PIXEL_FORMATS: [PixelFormat; 25] = [
+    PixelFormat::ARGB8,
+    PixelFormat::ARGB16,
+    PixelFormat::RGB565,
+    PixelFormat::ARGB1555,
+    PixelFormat::ARGB4,
+    PixelFormat::ARGB2101010,
+    PixelFormat::R16,
+    PixelFormat::RG16,
+    PixelFormat::RGBA16,
+    PixelFormat::RG8,
+    PixelFormat::RGBA8,
+    PixelFormat::R8,
+    PixelFormat::RGBA8S,
+    PixelFormat::A8,
+    PixelFormat::L8,
+    PixelFormat::AL8,
+    PixelFormat::L16,
+    PixelFormat::R16F,
+    PixelFormat::RG16F,
+    PixelFormat::RGBA16F,
+    PixelFormat::R32F,
+    PixelFormat::RG32F,
+    PixelFormat::RGBA32F,
+    PixelFormat::RGB111110F,
+    PixelFormat::RGB9E5F,
+];
+
+/// The result of diagnosing a single .d3dtx file
+pub struct Diagnosis {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    /// the raw surface format code of the header
+    pub format_code: u32,
+    /// the format the code is currently mapped to
+    pub mapped_format: String,
+    /// the size of the biggest mip-map in bytes
+    pub data_size: u32,
+    /// all formats that could decode the data, best score first
+    pub candidates: Vec<Candidate>,
+}
+
+/// A format that could decode the texture data, together with its scores
+pub struct Candidate {
+    pub format: String,
+    /// how much bigger the mean color difference of neighbouring pixels across 4x4 block
+    /// boundaries is compared to inside of blocks, in [0, 1]. About 0 for plausible images,
+    /// block compressed data decoded with the wrong variant typically results in higher values.
+    pub block_discontinuity: f32,
+    /// mean color difference of neighbouring pixels in [0, 1], high for noise-like images
+    pub noise: f32,
+    /// 1 for plausible alpha channels (opaque, or smooth transitions), 0 for noise or
+    /// completely transparent images. Always 1 for formats without alpha.
+    pub alpha_plausibility: f32,
+    /// combination of the scores above, lower is better
+    pub score: f32,
+    pub image: DynamicImage,
+}
+
+/// Decodes the texture with every format that fits the byte size of its biggest mip-map and
+/// scores the results.
+pub fn diagnose<T: Read + Seek>(mut input: T) -> Result<Diagnosis> {
+    let header = D3DTXHeader::parse(&mut input).context("could not parse D3DTX header")?;
+    let mips = header
+        .read_mips(&mut input)
+        .context("could not read mip-map data")?;
+    let mip = mips
+        .into_iter()
+        .next()
+        .ok_or(anyhow!("texture does not contain any mip-map"))?;
+
+    let formats = candidate_formats(&header, mip.data.len());
+    let mut candidates: Vec<Candidate> = formats
+        .into_iter()
+        .filter_map(|format| {
+            // pads partial blocks (see `Mip::decode`)
+            match mip.decode(format, 0) {
+                Ok((image, _)) => Some(score(format, image)),
+                Err(err) => {
+                    log::debug!("{:?} could not decode the texture: {:?}", format, err);
+                    None
+                }
+            }
+        })
+        .collect();
+    candidates.sort_by(|a, b| {
+        a.score
+            .partial_cmp(&b.score)
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+
+    Ok(Diagnosis {
+        name: header.name,
+        width: header.width,
+        height: header.height,
+        format_code: header.format_code,
+        mapped_format: format!("{:?}", header.format),
+        data_size: header.data_size,
+        candidates,
+    })
+}
+
+/// Returns all formats whose size matches the size of the biggest mip-map.
+/// If the size in the header does not match any format, all formats that fit into the
+/// remaining data are returned.
+fn candidate_formats(header: &D3DTXHeader, available: usize) -> Vec<TextureFormat> {
+    let blocks = (header.width.div_ceil(4) * header.height.div_ceil(4)) as usize;
+    let pixels = (header.width * header.height) as usize;
+    let formats: Vec<(TextureFormat, usize)> = BCN_VARIANTS
+        .iter()
+        .map(|variant| {
+            (
+                TextureFormat::BCn(*variant),
+                blocks * variant.encoded_bytes_per_block(),
+            )
+        })
+        .chain(PIXEL_FORMATS.iter().map(|format| {
+            (
+                TextureFormat::Uncompressed(*format),
+                pixels * format.bytes_per_pixel(),
+            )
+        }))
+        .collect();
+
+    let exact: Vec<TextureFormat> = formats
+        .iter()
+        .filter(|(_, size)| *size == header.data_size as usize)
+        .map(|(format, _)| *format)
+        .collect();
+    if !exact.is_empty() {
+        return exact;
+    }
+    log::warn!(
+        "no format matches the mip-map size of {} bytes, trying all formats that fit",
+        header.data_size
+    );
+    formats
+        .into_iter()
+        .filter(|(_, size)| *size > 0 && *size <= available)
+        .map(|(format, _)| format)
+        .collect()
+}
+
+fn score(format: TextureFormat, image: DynamicImage) -> Candidate {
+    let (width, height) = image.dimensions();
+    let has_alpha = image.color().has_alpha();
+    let rgba = image.to_rgba8();
+
+    let color_difference = |a: &image::Rgba<u8>, b: &image::Rgba<u8>| {
+        (0..3)
+            .map(|channel| (f32::from(a[channel]) - f32::from(b[channel])).abs())
+            .sum::<f32>()
+            / (3.0 * 255.0)
+    };
+
+    // horizontal and vertical neighbours, separated by whether they cross a block boundary
+    let (mut boundary_sum, mut boundary_count) = (0.0, 0usize);
+    let (mut interior_sum, mut interior_count) = (0.0, 0usize);
+    let (mut alpha_sum, mut alpha_count) = (0.0, 0usize);
+    let mut transparent = 0usize;
+    for (x, y, pixel) in rgba.enumerate_pixels() {
+        if pixel[3] == 0 {
+            transparent += 1;
+        }
+        let mut neighbours = Vec::with_capacity(2);
+        if x + 1 < width {
+            neighbours.push((rgba.get_pixel(x + 1, y), x % 4 == 3));
+        }
+        if y + 1 < height {
+            neighbours.push((rgba.get_pixel(x, y + 1), y % 4 == 3));
+        }
+        for (neighbour, crosses_boundary) in neighbours {
+            let difference = color_difference(pixel, neighbour);
+            if crosses_boundary {
+                boundary_sum += difference;
+                boundary_count += 1;
+            } else {
+                interior_sum += difference;
+                interior_count += 1;
+            }
+            alpha_sum += (f32::from(pixel[3]) - f32::from(neighbour[3])).abs() / 255.0;
+            alpha_count += 1;
+        }
+    }
+
+    let mean = |sum: f32, count: usize| if count == 0 { 0.0 } else { sum / count as f32 };
+    let boundary = mean(boundary_sum, boundary_count);
+    let interior = mean(interior_sum, interior_count);
+    let block_discontinuity = (boundary - interior).max(0.0);
+    let noise = mean(boundary_sum + interior_sum, boundary_count + interior_count);
+    let alpha_plausibility = if !has_alpha {
+        1.0
+    } else if transparent == rgba.pixels().len() {
+        0.0
+    } else {
+        1.0 - (mean(alpha_sum, alpha_count) * 4.0).min(1.0)
+    };
+
+    let score = block_discontinuity * 8.0 + noise * 4.0 + (1.0 - alpha_plausibility);
+
+    Candidate {
+        format: format!("{:?}", format),
+        block_discontinuity,
+        noise,
+        alpha_plausibility,
+        score,
+        image,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::d3dtx::{
+        test_files::{gradient, reference},
+        writer::write_d3dtx,
+    };
+
+    /// Diagnoses the gradient encoded with the format of the reference .d3dtx file.
+    fn diagnose_gradient(width: u32, height: u32, format_code: u32) -> Diagnosis {
+        let reference = reference(width, height, 1, format_code);
+        let mut file = Vec::new();
+        write_d3dtx(&gradient(width, height), &reference, &mut file).unwrap();
+        diagnose(Cursor::new(file)).unwrap()
+    }
+
+    fn formats(diagnosis: &Diagnosis) -> Vec<&str> {
+        diagnosis
+            .candidates
+            .iter()
+            .map(|candidate| candidate.format.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn bc1_scores_best() {
+        let diagnosis = diagnose_gradient(64, 64, 64);
+        assert_eq!(diagnosis.data_size, 2048);
+        // BC4 has the same block size
+        assert_eq!(formats(&diagnosis), ["BCn(BC1)", "BCn(BC4)"]);
+    }
+
+    #[test]
+    fn bc3_scores_best() {
+        let diagnosis = diagnose_gradient(64, 64, 66);
+        assert_eq!(diagnosis.data_size, 4096);
+        let formats = formats(&diagnosis);
+        assert_eq!(formats[0], "BCn(BC3)");
+        // all other formats with 16-byte blocks and 8-bit formats
+        assert_eq!(formats.len(), 8, "{:?}", formats);
+    }
+
+    #[test]
+    fn counts_partial_blocks() {
+        // 2x2 blocks, the ones at the right and bottom edges only partially cover the image
+        let diagnosis = diagnose_gradient(6, 6, 64);
+        assert_eq!(diagnosis.data_size, 32);
+        assert_eq!(formats(&diagnosis), ["BCn(BC1)", "BCn(BC4)"]);
+
+        // a single block for a texture smaller than a block
+        let diagnosis = diagnose_gradient(2, 2, 66);
+        assert_eq!(diagnosis.data_size, 16);
+        assert!(formats(&diagnosis).contains(&"BCn(BC3)"));
+    }
+}
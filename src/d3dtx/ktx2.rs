@@ -0,0 +1,712 @@
+//! Writing of KTX2 files with the unmodified data of d3dtx textures.
+//!
+//! The mip-maps are optionally supercompressed with zlib, the block data itself is never changed.
+//!
+//! # Related Links
+//! * <https://github.khronos.org/KTX-Specification/> - KTX 2.0 specification.
+//! * <https://registry.khronos.org/DataFormat/specs/1.3/dataformat.1.3.html> - Khronos Data Format
+//!   specification, which describes the data format descriptor (DFD).
+
+use std::io::Write;
+
+use anyhow::{anyhow, Result};
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use super::{BCnVariant, ColorSpace, PixelFormat, Texture, TextureFormat, TextureLayout};
+
+const IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+const HEADER_SIZE: usize = 12 + 9 * 4 + 4 * 4 + 2 * 8;
+const LEVEL_INDEX_ENTRY_SIZE: usize = 3 * 8;
+
+const SUPERCOMPRESSION_NONE: u32 = 0;
+const SUPERCOMPRESSION_ZLIB: u32 = 3;
+
+// color models of the data format descriptor
+const MODEL_RGBSDA: u8 = 1;
+const MODEL_BC1A: u8 = 128;
+const MODEL_BC2: u8 = 129;
+const MODEL_BC3: u8 = 130;
+const MODEL_BC4: u8 = 131;
+const MODEL_BC5: u8 = 132;
+const MODEL_BC6H: u8 = 133;
+const MODEL_BC7: u8 = 134;
+
+const PRIMARIES_BT709: u8 = 1;
+const TRANSFER_LINEAR: u8 = 1;
+const TRANSFER_SRGB: u8 = 2;
+
+// channel ids (of the RGBSDA model, the BCn models use the same ids for their channels)
+const CHANNEL_R: u8 = 0;
+const CHANNEL_G: u8 = 1;
+const CHANNEL_B: u8 = 2;
+const CHANNEL_A: u8 = 15;
+const CHANNEL_BC1A_ALPHA_PRESENT: u8 = 1;
+
+// qualifiers of a channel
+const QUALIFIER_LINEAR: u8 = 0x10;
+const QUALIFIER_EXPONENT: u8 = 0x20;
+const QUALIFIER_SIGNED: u8 = 0x40;
+const QUALIFIER_FLOAT: u8 = 0x80;
+
+const FLOAT_ONE: u32 = 0x3F80_0000;
+const FLOAT_MINUS_ONE: u32 = 0xBF80_0000;
+
+/// How the values of a channel are stored
+#[derive(Clone, Copy)]
+enum Number {
+    Unorm,
+    Snorm,
+    SignedFloat,
+    UnsignedFloat,
+}
+
+/// A sample of the data format descriptor, i.e. the bits of one channel in a texel block
+struct Sample {
+    bit_offset: u16,
+    bit_length: u8,
+    channel: u8,
+    lower: u32,
+    upper: u32,
+}
+
+impl Sample {
+    fn new(channel: u8, bit_offset: u16, bit_length: u8, number: Number) -> Self {
+        let (qualifiers, lower, upper) = match number {
+            Number::Unorm => (0, 0, u32::MAX >> (32 - u32::from(bit_length))),
+            Number::Snorm => {
+                let max = u32::MAX >> (33 - u32::from(bit_length));
+                (QUALIFIER_SIGNED, (max as i32).wrapping_neg() as u32, max)
+            }
+            Number::SignedFloat => (
+                QUALIFIER_FLOAT | QUALIFIER_SIGNED,
+                FLOAT_MINUS_ONE,
+                FLOAT_ONE,
+            ),
+            Number::UnsignedFloat => (QUALIFIER_FLOAT, 0, FLOAT_ONE),
+        };
+        Self {
+            bit_offset,
+            bit_length,
+            channel: channel | qualifiers,
+            lower,
+            upper,
+        }
+    }
+
+    /// A whole (compressed) block as one sample
+    fn block(channel: u8, bit_offset: u16, bit_length: u8) -> Self {
+        Self {
+            bit_offset,
+            bit_length,
+            channel,
+            lower: 0,
+            upper: u32::MAX,
+        }
+    }
+}
+
+/// The description of a format as needed for the KTX2 header and its data format descriptor
+struct FormatDescription {
+    vk_format: u32,
+    /// size of the data type for endianness conversion (1 for block compressed formats)
+    type_size: u32,
+    color_model: u8,
+    transfer_function: u8,
+    /// texel block dimensions, i.e. 4 for block compressed formats and 1 otherwise
+    block_size: u8,
+    bytes_per_block: u8,
+    samples: Vec<Sample>,
+}
+
+impl FormatDescription {
+    fn new(format: TextureFormat) -> Result<Self> {
+        let description = match format {
+            TextureFormat::BCn(variant) => {
+                let (vk_format, color_model, samples) = match variant {
+                    BCnVariant::BC1 => (
+                        133,
+                        MODEL_BC1A,
+                        vec![Sample::block(CHANNEL_BC1A_ALPHA_PRESENT, 0, 64)],
+                    ),
+                    BCnVariant::BC2 => (
+                        135,
+                        MODEL_BC2,
+                        vec![
+                            Sample::block(CHANNEL_A, 0, 64),
+                            Sample::block(CHANNEL_R, 64, 64),
+                        ],
+                    ),
+                    BCnVariant::BC3 => (
+                        137,
+                        MODEL_BC3,
+                        vec![
+                            Sample::block(CHANNEL_A, 0, 64),
+                            Sample::block(CHANNEL_R, 64, 64),
+                        ],
+                    ),
+                    BCnVariant::BC4 => (139, MODEL_BC4, vec![Sample::block(CHANNEL_R, 0, 64)]),
+                    BCnVariant::BC5 => (
+                        141,
+                        MODEL_BC5,
+                        vec![
+                            Sample::block(CHANNEL_R, 0, 64),
+                            Sample::block(CHANNEL_G, 64, 64),
+                        ],
+                    ),
+                    BCnVariant::BC6H => (
+                        143,
+                        MODEL_BC6H,
+                        vec![Sample::new(CHANNEL_R, 0, 128, Number::UnsignedFloat)],
+                    ),
+                    BCnVariant::BC7 => (145, MODEL_BC7, vec![Sample::block(CHANNEL_R, 0, 128)]),
+                };
+                Self {
+                    vk_format,
+                    type_size: 1,
+                    color_model,
+                    transfer_function: TRANSFER_LINEAR,
+                    block_size: 4,
+                    bytes_per_block: variant.encoded_bytes_per_block() as u8,
+                    samples,
+                }
+            }
+            TextureFormat::Uncompressed(format) => Self::uncompressed(format),
+            TextureFormat::Unknown(code) => {
+                return Err(anyhow!(
+                    "texture format {} ({:#04X}) can not be stored as KTX2",
+                    code,
+                    code
+                ))
+            }
+        };
+        Ok(description)
+    }
+
+    /// Switches to the sRGB variant of the format, if there is one.
+    /// Alpha channels stay linear.
+    fn into_srgb(mut self) -> Self {
+        let srgb_format = match self.vk_format {
+            133 => 134, // BC1
+            135 => 136, // BC2
+            137 => 138, // BC3
+            145 => 146, // BC7
+            44 => 50,   // B8G8R8A8
+            37 => 43,   // R8G8B8A8
+            16 => 22,   // R8G8
+            9 => 15,    // R8
+            _ => return self,
+        };
+        self.vk_format = srgb_format;
+        self.transfer_function = TRANSFER_SRGB;
+        for sample in &mut self.samples {
+            if sample.channel & 0x0F == CHANNEL_A {
+                sample.channel |= QUALIFIER_LINEAR;
+            }
+        }
+        self
+    }
+
+    fn uncompressed(format: PixelFormat) -> Self {
+        use Number::*;
+        /// vkFormat, type size, samples as (channel, bit offset, bit length)
+        type Layout = (u32, u32, &'static [(u8, u16, u8)]);
+        const RGBA8: &[(u8, u16, u8)] = &[
+            (CHANNEL_R, 0, 8),
+            (CHANNEL_G, 8, 8),
+            (CHANNEL_B, 16, 8),
+            (CHANNEL_A, 24, 8),
+        ];
+        const RGBA16: &[(u8, u16, u8)] = &[
+            (CHANNEL_R, 0, 16),
+            (CHANNEL_G, 16, 16),
+            (CHANNEL_B, 32, 16),
+            (CHANNEL_A, 48, 16),
+        ];
+        const RGBA32: &[(u8, u16, u8)] = &[
+            (CHANNEL_R, 0, 32),
+            (CHANNEL_G, 32, 32),
+            (CHANNEL_B, 64, 32),
+            (CHANNEL_A, 96, 32),
+        ];
+
+        let ((vk_format, type_size, layout), number): (Layout, Number) = match format {
+            PixelFormat::ARGB8 => (
+                (
+                    44,
+                    1,
+                    &[
+                        (CHANNEL_B, 0, 8),
+                        (CHANNEL_G, 8, 8),
+                        (CHANNEL_R, 16, 8),
+                        (CHANNEL_A, 24, 8),
+                    ],
+                ),
+                Unorm,
+            ),
+            PixelFormat::RGBA8 => ((37, 1, RGBA8), Unorm),
+            PixelFormat::RGBA8S => ((38, 1, RGBA8), Snorm),
+            PixelFormat::RG8 | PixelFormat::AL8 => ((16, 1, &RGBA8[..2]), Unorm),
+            PixelFormat::R8 | PixelFormat::L8 | PixelFormat::A8 => ((9, 1, &RGBA8[..1]), Unorm),
+            PixelFormat::ARGB16 | PixelFormat::RGBA16 => ((91, 2, RGBA16), Unorm),
+            PixelFormat::RG16 => ((77, 2, &RGBA16[..2]), Unorm),
+            PixelFormat::R16 | PixelFormat::L16 => ((70, 2, &RGBA16[..1]), Unorm),
+            PixelFormat::RGB565 => (
+                (
+                    4,
+                    2,
+                    &[(CHANNEL_B, 0, 5), (CHANNEL_G, 5, 6), (CHANNEL_R, 11, 5)],
+                ),
+                Unorm,
+            ),
+            PixelFormat::ARGB1555 => (
+                (
+                    8,
+                    2,
+                    &[
+                        (CHANNEL_B, 0, 5),
+                        (CHANNEL_G, 5, 5),
+                        (CHANNEL_R, 10, 5),
+                        (CHANNEL_A, 15, 1),
+                    ],
+                ),
+                Unorm,
+            ),
+            PixelFormat::ARGB4 => (
+                (
+                    1_000_340_000,
+                    2,
+                    &[
+                        (CHANNEL_B, 0, 4),
+                        (CHANNEL_G, 4, 4),
+                        (CHANNEL_R, 8, 4),
+                        (CHANNEL_A, 12, 4),
+                    ],
+                ),
+                Unorm,
+            ),
+            PixelFormat::ARGB2101010 => (
+                (
+                    64,
+                    4,
+                    &[
+                        (CHANNEL_R, 0, 10),
+                        (CHANNEL_G, 10, 10),
+                        (CHANNEL_B, 20, 10),
+                        (CHANNEL_A, 30, 2),
+                    ],
+                ),
+                Unorm,
+            ),
+            PixelFormat::RGBA16F => ((97, 2, RGBA16), SignedFloat),
+            PixelFormat::RG16F => ((83, 2, &RGBA16[..2]), SignedFloat),
+            PixelFormat::R16F => ((76, 2, &RGBA16[..1]), SignedFloat),
+            PixelFormat::RGBA32F => ((109, 4, RGBA32), SignedFloat),
+            PixelFormat::RG32F => ((103, 4, &RGBA32[..2]), SignedFloat),
+            PixelFormat::R32F => ((100, 4, &RGBA32[..1]), SignedFloat),
+            PixelFormat::RGB111110F => (
+                (
+                    122,
+                    4,
+                    &[(CHANNEL_R, 0, 11), (CHANNEL_G, 11, 11), (CHANNEL_B, 22, 10)],
+                ),
+                UnsignedFloat,
+            ),
+            PixelFormat::RGB9E5F => {
+                // every channel consists of its mantissa and the shared exponent
+                let samples = [CHANNEL_R, CHANNEL_G, CHANNEL_B]
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(index, &channel)| {
+                        vec![
+                            Sample {
+                                bit_offset: index as u16 * 9,
+                                bit_length: 9,
+                                channel,
+                                lower: 0,
+                                upper: 8448,
+                            },
+                            Sample {
+                                bit_offset: 27,
+                                bit_length: 5,
+                                channel: channel | QUALIFIER_EXPONENT,
+                                lower: 15,
+                                upper: 31,
+                            },
+                        ]
+                    })
+                    .collect();
+                return Self {
+                    vk_format: 123,
+                    type_size: 4,
+                    color_model: MODEL_RGBSDA,
+                    transfer_function: TRANSFER_LINEAR,
+                    block_size: 1,
+                    bytes_per_block: 4,
+                    samples,
+                };
+            }
+        };
+
+        Self {
+            vk_format,
+            type_size,
+            color_model: MODEL_RGBSDA,
+            transfer_function: TRANSFER_LINEAR,
+            block_size: 1,
+            bytes_per_block: format.bytes_per_pixel() as u8,
+            samples: layout
+                .iter()
+                .map(|&(channel, offset, length)| Sample::new(channel, offset, length, number))
+                .collect(),
+        }
+    }
+
+    /// Writes the data format descriptor with a single basic descriptor block
+    fn write_dfd<W: Write>(&self, mut out: W) -> Result<()> {
+        let block_size = 24 + 16 * self.samples.len();
+        out.write_u32::<LittleEndian>(4 + block_size as u32)?;
+        // vendor id and descriptor type (both 0 = Khronos basic descriptor block)
+        out.write_u32::<LittleEndian>(0)?;
+        out.write_u16::<LittleEndian>(2)?;
+        out.write_u16::<LittleEndian>(block_size as u16)?;
+        out.write_all(&[self.color_model, PRIMARIES_BT709, self.transfer_function, 0])?;
+        let dimension = self.block_size - 1;
+        out.write_all(&[dimension, dimension, 0, 0])?;
+        out.write_all(&[self.bytes_per_block, 0, 0, 0, 0, 0, 0, 0])?;
+        for sample in &self.samples {
+            out.write_u16::<LittleEndian>(sample.bit_offset)?;
+            out.write_u8(sample.bit_length - 1)?;
+            out.write_u8(sample.channel)?;
+            out.write_u32::<LittleEndian>(0)?;
+            out.write_u32::<LittleEndian>(sample.lower)?;
+            out.write_u32::<LittleEndian>(sample.upper)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the texture with all of its mip-maps, faces, array elements and slices as KTX2 file.
+/// The pixel data is copied without any conversion, but each mip-map level is compressed with
+/// zlib if `supercompression` is set.
+/// sRGB textures use the sRGB variant of their format, if there is one.
+pub fn write_ktx2<W: Write>(texture: &Texture, mut out: W, supercompression: bool) -> Result<()> {
+    let mut format = FormatDescription::new(texture.format)?;
+    if texture.color_space == Some(ColorSpace::Srgb) {
+        format = format.into_srgb();
+    }
+    let biggest_mip = texture
+        .mips
+        .first()
+        .ok_or(anyhow!("texture does not contain any mip-map"))?;
+
+    // the data of a level consists of all its array elements and faces
+    let mut levels = vec![Vec::new(); texture.mip_count as usize];
+    for mip in &texture.mips {
+        levels
+            .get_mut(mip.level as usize)
+            .ok_or(anyhow!("invalid mip-map level {}", mip.level))?
+            .extend_from_slice(&mip.data);
+    }
+    let uncompressed_lengths: Vec<usize> = levels.iter().map(Vec::len).collect();
+    if supercompression {
+        for level in &mut levels {
+            *level = deflate::deflate_bytes_zlib(level);
+        }
+    }
+
+    let mut dfd = Vec::new();
+    format.write_dfd(&mut dfd)?;
+    let mut kvd = Vec::new();
+    let writer = format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+    write_key_value(&mut kvd, "KTXwriter", &writer)?;
+
+    // levels are stored from the smallest to the biggest one, each aligned to the block size
+    let alignment = if supercompression {
+        1
+    } else {
+        lcm(format.bytes_per_block as usize, 4)
+    };
+    let dfd_offset = HEADER_SIZE + LEVEL_INDEX_ENTRY_SIZE * levels.len();
+    let kvd_offset = dfd_offset + dfd.len();
+    let mut offset = kvd_offset + kvd.len();
+    let mut level_offsets = vec![0; levels.len()];
+    for (index, level) in levels.iter().enumerate().rev() {
+        offset = align(offset, alignment);
+        level_offsets[index] = offset;
+        offset += level.len();
+    }
+
+    let (layer_count, face_count) = match texture.layout {
+        TextureLayout::Cube => (0, 6),
+        TextureLayout::CubeArray => (texture.array_size, 6),
+        TextureLayout::Array2D => (texture.array_size, 1),
+        _ => (0, 1),
+    };
+    out.write_all(&IDENTIFIER)?;
+    out.write_u32::<LittleEndian>(format.vk_format)?;
+    out.write_u32::<LittleEndian>(format.type_size)?;
+    out.write_u32::<LittleEndian>(biggest_mip.width)?;
+    out.write_u32::<LittleEndian>(biggest_mip.height)?;
+    out.write_u32::<LittleEndian>(if texture.layout == TextureLayout::Volume {
+        texture.depth
+    } else {
+        0
+    })?;
+    out.write_u32::<LittleEndian>(layer_count)?;
+    out.write_u32::<LittleEndian>(face_count)?;
+    out.write_u32::<LittleEndian>(texture.mip_count)?;
+    out.write_u32::<LittleEndian>(if supercompression {
+        SUPERCOMPRESSION_ZLIB
+    } else {
+        SUPERCOMPRESSION_NONE
+    })?;
+    // index
+    out.write_u32::<LittleEndian>(dfd_offset as u32)?;
+    out.write_u32::<LittleEndian>(dfd.len() as u32)?;
+    out.write_u32::<LittleEndian>(kvd_offset as u32)?;
+    out.write_u32::<LittleEndian>(kvd.len() as u32)?;
+    // no supercompression global data
+    out.write_u64::<LittleEndian>(0)?;
+    out.write_u64::<LittleEndian>(0)?;
+    for ((level, offset), uncompressed_length) in
+        levels.iter().zip(&level_offsets).zip(&uncompressed_lengths)
+    {
+        out.write_u64::<LittleEndian>(*offset as u64)?;
+        out.write_u64::<LittleEndian>(level.len() as u64)?;
+        out.write_u64::<LittleEndian>(*uncompressed_length as u64)?;
+    }
+    out.write_all(&dfd)?;
+    out.write_all(&kvd)?;
+
+    let mut position = kvd_offset + kvd.len();
+    for (level, offset) in levels.iter().zip(&level_offsets).rev() {
+        out.write_all(&vec![0; offset - position])?;
+        out.write_all(level)?;
+        position = offset + level.len();
+    }
+    Ok(())
+}
+
+/// Writes a key/value pair of the key/value data, including its padding.
+fn write_key_value<W: Write>(mut out: W, key: &str, value: &str) -> Result<()> {
+    // both key and value are NUL terminated
+    let length = key.len() + 1 + value.len() + 1;
+    out.write_u32::<LittleEndian>(length as u32)?;
+    out.write_all(key.as_bytes())?;
+    out.write_u8(0)?;
+    out.write_all(value.as_bytes())?;
+    out.write_u8(0)?;
+    out.write_all(&vec![0; align(length, 4) - length])?;
+    Ok(())
+}
+
+fn align(value: usize, alignment: usize) -> usize {
+    value.div_ceil(alignment) * alignment
+}
+
+/// least common multiple
+fn lcm(a: usize, b: usize) -> usize {
+    let gcd = |mut a: usize, mut b: usize| {
+        while b != 0 {
+            let remainder = a % b;
+            a = b;
+            b = remainder;
+        }
+        a
+    };
+    a / gcd(a, b) * b
+}
+
+#[cfg(test)]
+mod tests {
+    use ::ktx2::{
+        ColorModel, DfdBlockBasic, Format, Reader, SupercompressionScheme, TransferFunction,
+    };
+    use byteorder::ByteOrder;
+
+    use super::*;
+    use crate::d3dtx::test_files::texture;
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        LittleEndian::read_u32(&data[offset..])
+    }
+
+    fn u64_at(data: &[u8], offset: usize) -> u64 {
+        LittleEndian::read_u64(&data[offset..])
+    }
+
+    /// The offset, length and uncompressed length of every level in the level index.
+    fn level_index(data: &[u8]) -> Vec<(usize, usize, usize)> {
+        (0..u32_at(data, 40) as usize)
+            .map(|level| {
+                let entry = HEADER_SIZE + level * LEVEL_INDEX_ENTRY_SIZE;
+                (
+                    u64_at(data, entry) as usize,
+                    u64_at(data, entry + 8) as usize,
+                    u64_at(data, entry + 16) as usize,
+                )
+            })
+            .collect()
+    }
+
+    fn write(texture: &Texture, supercompression: bool) -> Vec<u8> {
+        let mut data = Vec::new();
+        write_ktx2(texture, &mut data, supercompression).unwrap();
+        data
+    }
+
+    #[test]
+    fn bc1_with_mips() {
+        let texture = texture(
+            TextureFormat::BCn(BCnVariant::BC1),
+            TextureLayout::Texture2D,
+            1,
+            8,
+            8,
+            3,
+        );
+        let data = write(&texture, false);
+
+        assert_eq!(data[..12], IDENTIFIER);
+        // vkFormat, type size, width, height, depth, layers, faces, levels, supercompression
+        let header: Vec<u32> = (0..9).map(|index| u32_at(&data, 12 + index * 4)).collect();
+        assert_eq!(header, [133, 1, 8, 8, 0, 0, 1, 3, 0]);
+        // the DFD follows the level index and consists of a basic block with a single sample
+        let dfd_offset = HEADER_SIZE + 3 * LEVEL_INDEX_ENTRY_SIZE;
+        assert_eq!(u32_at(&data, 48) as usize, dfd_offset);
+        assert_eq!(u32_at(&data, 52), 4 + 24 + 16);
+        assert_eq!(u32_at(&data, dfd_offset), 4 + 24 + 16);
+        let kvd_offset = u32_at(&data, 56) as usize;
+        let kvd_end = kvd_offset + u32_at(&data, 60) as usize;
+        assert_eq!(kvd_offset, dfd_offset + 44);
+        assert_eq!(u64_at(&data, 64), 0);
+        assert_eq!(u64_at(&data, 72), 0);
+
+        // the levels are stored from the smallest to the biggest one, aligned to 8-byte blocks
+        let first = align(kvd_end, 8);
+        assert_eq!(
+            level_index(&data),
+            [(first + 16, 32, 32), (first + 8, 8, 8), (first, 8, 8)]
+        );
+        assert_eq!(data.len(), first + 48);
+        for (level, (offset, length, _)) in level_index(&data).into_iter().enumerate() {
+            assert_eq!(data[offset..offset + length], texture.mips[level].data[..]);
+        }
+
+        let reader = Reader::new(&data).unwrap();
+        let header = reader.header();
+        assert_eq!(header.format, Some(Format::BC1_RGBA_UNORM_BLOCK));
+        assert_eq!((header.pixel_width, header.pixel_height), (8, 8));
+        assert_eq!(header.level_count, 3);
+        assert_eq!(header.supercompression_scheme, None);
+        let levels: Vec<_> = reader.levels().map(|level| level.data).collect();
+        assert_eq!(levels, [&[0; 32][..], &[16; 8][..], &[32; 8][..]]);
+        let writer = reader.key_value_data().next().unwrap();
+        assert_eq!(writer.0, "KTXwriter");
+
+        let dfd_blocks: Vec<_> = reader.dfd_blocks().collect();
+        assert_eq!(dfd_blocks.len(), 1);
+        let dfd = DfdBlockBasic::parse(dfd_blocks[0].data).unwrap();
+        assert_eq!(dfd.header.color_model, Some(ColorModel::BC1A));
+        assert_eq!(dfd.header.transfer_function, Some(TransferFunction::Linear));
+        assert_eq!(
+            dfd.header
+                .texel_block_dimensions
+                .map(|dimension| dimension.get()),
+            [4, 4, 1, 1]
+        );
+        assert_eq!(dfd.header.bytes_planes, [8, 0, 0, 0, 0, 0, 0, 0]);
+        let samples: Vec<_> = dfd.sample_information().collect();
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].bit_offset, 0);
+        assert_eq!(samples[0].bit_length.get(), 64);
+        assert_eq!(samples[0].channel_type, CHANNEL_BC1A_ALPHA_PRESENT);
+        assert_eq!((samples[0].lower, samples[0].upper), (0, u32::MAX));
+    }
+
+    #[test]
+    fn bc7_srgb() {
+        let mut texture = texture(
+            TextureFormat::BCn(BCnVariant::BC7),
+            TextureLayout::Texture2D,
+            1,
+            8,
+            8,
+            2,
+        );
+        texture.color_space = Some(ColorSpace::Srgb);
+        let data = write(&texture, false);
+
+        let kvd_end = u32_at(&data, 56) as usize + u32_at(&data, 60) as usize;
+        let first = align(kvd_end, 16);
+        assert_eq!(level_index(&data), [(first + 16, 64, 64), (first, 16, 16)]);
+
+        let reader = Reader::new(&data).unwrap();
+        assert_eq!(reader.header().format, Some(Format::BC7_SRGB_BLOCK));
+        assert_eq!(reader.header().type_size, 1);
+        let dfd_block = reader.dfd_blocks().next().unwrap();
+        let dfd = DfdBlockBasic::parse(dfd_block.data).unwrap();
+        assert_eq!(dfd.header.color_model, Some(ColorModel::BC7));
+        assert_eq!(dfd.header.transfer_function, Some(TransferFunction::SRGB));
+        assert_eq!(dfd.header.bytes_planes[0], 16);
+        let samples: Vec<_> = dfd.sample_information().collect();
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].bit_length.get(), 128);
+        assert_eq!(samples[0].channel_type, CHANNEL_R);
+    }
+
+    #[test]
+    fn zlib_supercompression() {
+        let texture = texture(
+            TextureFormat::BCn(BCnVariant::BC1),
+            TextureLayout::Texture2D,
+            1,
+            8,
+            8,
+            3,
+        );
+        let data = write(&texture, true);
+        assert_eq!(u32_at(&data, 44), SUPERCOMPRESSION_ZLIB);
+
+        // the compressed levels are not aligned and follow each other without padding
+        let kvd_end = u32_at(&data, 56) as usize + u32_at(&data, 60) as usize;
+        let index = level_index(&data);
+        assert_eq!(index[2].0, kvd_end);
+        assert_eq!(index[1].0, index[2].0 + index[2].1);
+        assert_eq!(index[0].0, index[1].0 + index[1].1);
+        assert_eq!(data.len(), index[0].0 + index[0].1);
+
+        let reader = Reader::new(&data).unwrap();
+        assert_eq!(
+            reader.header().supercompression_scheme,
+            Some(SupercompressionScheme::ZLIB)
+        );
+        for (level, mip) in reader.levels().zip(&texture.mips) {
+            assert_eq!(level.uncompressed_byte_length, mip.data.len() as u64);
+            let decompressed = miniz_oxide::inflate::decompress_to_vec_zlib(level.data).unwrap();
+            assert_eq!(decompressed, mip.data);
+        }
+    }
+
+    #[test]
+    fn cube_map_faces() {
+        let texture = texture(
+            TextureFormat::BCn(BCnVariant::BC1),
+            TextureLayout::Cube,
+            1,
+            4,
+            4,
+            1,
+        );
+        let data = write(&texture, false);
+        let reader = Reader::new(&data).unwrap();
+        assert_eq!(reader.header().layer_count, 0);
+        assert_eq!(reader.header().face_count, 6);
+        // the level consists of all faces after each other
+        let level = reader.levels().next().unwrap().data;
+        let expected: Vec<u8> = (0..6).flat_map(|face| [face; 8]).collect();
+        assert_eq!(level, &expected[..]);
+    }
+}
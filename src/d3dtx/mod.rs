@@ -1,53 +1,313 @@
+mod bcn_encoder;
 mod bcn_image;
+pub mod dds;
+pub mod diagnostics;
+pub mod ktx2;
+#[cfg(test)]
+mod test_files;
+mod uncompressed;
+pub mod writer;
 
-use std::io::{Read, Seek, SeekFrom};
+use std::io::{Cursor, Read, Seek, SeekFrom};
 
 use anyhow::{anyhow, Context, Result};
 use byteorder::{LittleEndian, ReadBytesExt};
-use image::{DynamicImage, GrayImage};
+use image::{imageops, DynamicImage, GenericImage, GenericImageView};
 
 use crate::{
     byte_reading::{D3DName, VersionHeader},
     d3dtx::bcn_image::DxtDecoder,
 };
 
-use self::bcn_image::BCnVariant;
+pub use self::{
+    bcn_image::{BCnVariant, Rgb32FImage},
+    uncompressed::PixelFormat,
+};
 
 #[derive(Debug)]
 pub struct Texture {
     pub name: String,
+    /// The decoded biggest mip-map level (the first face or slice of cube maps, arrays and volumes).
     pub image: DynamicImage,
+    /// The unclamped floating point data of HDR textures (BC6H and floating point formats).
+    /// `image` contains the same data clamped to [0, 1] in this case.
+    pub hdr_image: Option<Rgb32FImage>,
+    pub format: TextureFormat,
+    /// The raw surface format code of the header (see `TextureFormat::parse`).
+    pub format_code: u32,
+    /// The color space of the color channels as stored in the header, `None` if it is unknown.
+    pub color_space: Option<ColorSpace>,
+    pub layout: TextureLayout,
+    /// The number of slices of volume textures, 1 otherwise.
+    pub depth: u32,
+    /// The number of elements of array textures, 1 otherwise.
+    pub array_size: u32,
+    pub mip_count: u32,
+    /// The raw data of all surfaces, sorted by mip-map level (starting with the biggest level)
+    /// and face, i.e. cube map faces or array elements.
+    pub mips: Vec<Mip>,
 }
 
 impl Texture {
     pub fn parse<T: Read + Seek>(mut input: T) -> Result<Self> {
         let header = D3DTXHeader::parse(&mut input).context("could not parse D3DTX header")?;
-        log::debug!("last mip-map start = {:#X}", &input.stream_position()?);
-
-        let image: DynamicImage = match header.format {
-            TextureFormat::BCn(variant) => {
-                let decoder = DxtDecoder::new(input, header.width, header.height, variant)?;
-                let image = decoder
-                    .read_image()
-                    .context("could not decode BCn image data")?;
-                image
-            }
-            TextureFormat::A8 => {
-                let mut data = Vec::new();
-                input.read_to_end(&mut data)?;
-                DynamicImage::ImageLuma8(
-                    GrayImage::from_vec(header.width, header.height, data)
-                        .ok_or(anyhow!("data buffer not big enough for A8 texture"))?,
-                )
-            }
-            unknown => return Err(anyhow!("unknown TextureFormat: {:?}", unknown)),
-        };
+        log::debug!("mip-map data start = {:#X}", &input.stream_position()?);
+
+        let mips = header
+            .read_mips(&mut input)
+            .context("could not read mip-map data")?;
+        let biggest_mip = mips
+            .first()
+            .ok_or(anyhow!("texture does not contain any mip-map"))?;
+        let (image, hdr_image) = biggest_mip.decode(header.format, 0)?;
 
         Ok(Self {
             name: header.name,
             image,
+            hdr_image,
+            format: header.format,
+            format_code: header.format_code,
+            color_space: header.color_space,
+            layout: header.layout,
+            depth: header.depth,
+            array_size: header.array_size,
+            mip_count: header.mip_count,
+            mips,
         })
     }
+
+    /// Decodes the given mip-map level, 0 being the biggest level.
+    /// Only the first surface is decoded for cube maps, arrays and volumes.
+    pub fn decode_mip(&self, level: usize) -> Result<DynamicImage> {
+        let mip = self
+            .mips
+            .iter()
+            .find(|mip| mip.level as usize == level)
+            .ok_or(anyhow!("texture has no mip-map level {}", level))?;
+        let (image, _) = mip.decode(self.format, 0)?;
+        Ok(image)
+    }
+
+    /// Removes the given number of the biggest mip-map levels, i.e. level `levels` becomes the
+    /// biggest level and `image` is decoded from it. The smallest level is always kept.
+    pub fn drop_mips(mut self, levels: u32) -> Result<Self> {
+        let levels = levels.min(self.mip_count.saturating_sub(1));
+        if levels == 0 {
+            return Ok(self);
+        }
+        self.mips.retain(|mip| mip.level >= levels);
+        self.mips.iter_mut().for_each(|mip| mip.level -= levels);
+        self.mip_count -= levels;
+        if self.layout == TextureLayout::Volume {
+            self.depth = (self.depth >> levels).max(1);
+        }
+        let biggest_mip = self
+            .mips
+            .first()
+            .ok_or(anyhow!("texture has no mip-map level {}", levels))?;
+        let (image, hdr_image) = biggest_mip.decode(self.format, 0)?;
+        self.image = image;
+        self.hdr_image = hdr_image;
+        Ok(self)
+    }
+
+    /// Decodes all surfaces of the given mip-map level, i.e. the faces of cube maps
+    /// (in the order +X, -X, +Y, -Y, +Z, -Z), the elements of arrays or the slices of volumes.
+    pub fn decode_surfaces(&self, level: usize) -> Result<Vec<DynamicImage>> {
+        let mut surfaces = Vec::new();
+        for mip in self.mips.iter().filter(|mip| mip.level as usize == level) {
+            for slice in 0..mip.depth {
+                let (image, _) = mip.decode(self.format, slice)?;
+                surfaces.push(image);
+            }
+        }
+        if surfaces.is_empty() {
+            return Err(anyhow!("texture has no mip-map level {}", level));
+        }
+        Ok(surfaces)
+    }
+
+    /// Assembles the faces of the first cube of the given mip-map level to a horizontal cross:
+    ///
+    /// ```text
+    ///      +Y
+    /// -X   +Z   +X   -Z
+    ///      -Y
+    /// ```
+    ///
+    /// Unused areas are transparent.
+    pub fn decode_cube_cross(&self, level: usize) -> Result<DynamicImage> {
+        if !self.layout.is_cube() {
+            return Err(anyhow!("texture is not a cube map but {:?}", self.layout));
+        }
+        let faces = self.decode_surfaces(level)?;
+        if faces.len() < 6 {
+            return Err(anyhow!("cube map has only {} faces", faces.len()));
+        }
+        // column and row of the faces +X, -X, +Y, -Y, +Z, -Z
+        const POSITIONS: [(u32, u32); 6] = [(2, 1), (0, 1), (1, 0), (1, 2), (1, 1), (3, 1)];
+        let (size_x, size_y) = faces[0].dimensions();
+        let high_precision = faces
+            .iter()
+            .any(|face| face.color().bytes_per_pixel() > face.color().channel_count());
+        let mut cross = if high_precision {
+            DynamicImage::new_rgba16(size_x * 4, size_y * 3)
+        } else {
+            DynamicImage::new_rgba8(size_x * 4, size_y * 3)
+        };
+        for (face, (column, row)) in faces.iter().zip(POSITIONS.iter()) {
+            cross
+                .copy_from(face, column * size_x, row * size_y)
+                .context("cube map faces have different sizes")?;
+        }
+        Ok(cross)
+    }
+}
+
+/// The raw data of a single surface of a mip-map level
+#[derive(Debug)]
+pub struct Mip {
+    /// 0 is the biggest level
+    pub level: u32,
+    /// the cube map face or the array element
+    pub face: u32,
+    pub width: u32,
+    pub height: u32,
+    /// the number of slices of volume textures, 1 otherwise
+    pub depth: u32,
+    pub data: Vec<u8>,
+}
+
+impl Mip {
+    /// Decodes the data of a slice of the mip-map (always 0 unless it is part of a volume texture).
+    /// Returns the image and, for HDR formats, the unclamped RGB values.
+    fn decode(
+        &self,
+        format: TextureFormat,
+        slice: u32,
+    ) -> Result<(DynamicImage, Option<Rgb32FImage>)> {
+        let slice_size = self.data.len() / self.depth.max(1) as usize;
+        let start = slice as usize * slice_size;
+        let data = self
+            .data
+            .get(start..start + slice_size)
+            .ok_or(anyhow!("mip-map has no slice {}", slice))?;
+        if let TextureFormat::BCn(_) = format {
+            // the smallest levels of block compressed textures are padded to whole 4x4 blocks
+            let padded_width = self.width.div_ceil(4) * 4;
+            let padded_height = self.height.div_ceil(4) * 4;
+            if padded_width != self.width || padded_height != self.height {
+                let (image, hdr_image) =
+                    decode(Cursor::new(data), padded_width, padded_height, format)?;
+                let image = image.crop_imm(0, 0, self.width, self.height);
+                let hdr_image = hdr_image.map(|hdr_image| {
+                    imageops::crop_imm(&hdr_image, 0, 0, self.width, self.height).to_image()
+                });
+                return Ok((image, hdr_image));
+            }
+        }
+        decode(Cursor::new(data), self.width, self.height, format)
+    }
+}
+
+/// The color space of the color channels of a texture (alpha is always linear)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpace {
+    Linear,
+    Srgb,
+}
+
+impl ColorSpace {
+    /// Maps Telltales surface gamma, which is -1 if unknown.
+    fn parse(value: i32) -> Option<Self> {
+        match value {
+            0 => Some(Self::Linear),
+            1 => Some(Self::Srgb),
+            _ => None,
+        }
+    }
+}
+
+/// The kind of texture, i.e. how its surfaces are organized
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextureLayout {
+    Texture2D,
+    /// six faces per mip-map level
+    Cube,
+    /// `depth` slices per mip-map level
+    Volume,
+    /// `array_size` surfaces per mip-map level
+    Array2D,
+    /// `array_size` cubes, i.e. six faces each, per mip-map level
+    CubeArray,
+    /// handled like `Texture2D`
+    Unknown(u32),
+}
+
+impl TextureLayout {
+    fn parse(value: u32) -> Self {
+        match value {
+            0 => Self::Texture2D,
+            1 => Self::Cube,
+            2 => Self::Volume,
+            3 => Self::Array2D,
+            4 => Self::CubeArray,
+            _ => Self::Unknown(value),
+        }
+    }
+
+    pub fn is_cube(self) -> bool {
+        matches!(self, Self::Cube | Self::CubeArray)
+    }
+
+    /// Returns the number of data regions (faces or array elements) per mip-map level.
+    fn surfaces_per_mip(self, array_size: u32) -> u32 {
+        match self {
+            Self::Cube => 6,
+            Self::CubeArray => 6 * array_size,
+            Self::Array2D => array_size,
+            Self::Texture2D | Self::Volume | Self::Unknown(_) => 1,
+        }
+    }
+}
+
+/// Decodes the image data of the biggest mip-map in the given format.
+/// Returns the image and, for HDR formats, the unclamped RGB values.
+fn decode<T: Read>(
+    mut input: T,
+    width: u32,
+    height: u32,
+    format: TextureFormat,
+) -> Result<(DynamicImage, Option<Rgb32FImage>)> {
+    match format {
+        TextureFormat::BCn(BCnVariant::BC6H) => {
+            let decoder = DxtDecoder::new(input, width, height, BCnVariant::BC6H)?;
+            let image = decoder
+                .read_hdr_image()
+                .context("could not decode BC6H image data")?;
+            let clamped = DynamicImage::ImageRgb16(bcn_image::hdr_to_rgb16(&image));
+            Ok((clamped, Some(image)))
+        }
+        TextureFormat::BCn(variant) => {
+            let decoder = DxtDecoder::new(input, width, height, variant)?;
+            let image = decoder
+                .read_image()
+                .context("could not decode BCn image data")?;
+            Ok((image, None))
+        }
+        TextureFormat::Uncompressed(format) => {
+            let mut data = Vec::new();
+            input.read_to_end(&mut data)?;
+            uncompressed::decode(&data, width, height, format)
+                .with_context(|| format!("could not decode {:?} image data", format))
+        }
+        TextureFormat::Unknown(code) => Err(anyhow!(
+            "unsupported texture format {} ({:#04X}, {})",
+            code,
+            code,
+            TextureFormat::unsupported_name(code).unwrap_or("unknown format code")
+        )),
+    }
 }
 
 /// The extracted header data from a .d3dtx file
@@ -56,6 +316,18 @@ struct D3DTXHeader {
     width: u32,
     height: u32,
     format: TextureFormat,
+    /// the raw surface format code
+    format_code: u32,
+    /// the size of the biggest mip-map in bytes
+    data_size: u32,
+    color_space: Option<ColorSpace>,
+    layout: TextureLayout,
+    depth: u32,
+    array_size: u32,
+    mip_count: u32,
+    /// the sizes of the data of all surfaces in bytes, in the order of the data.
+    /// Starts with the smallest mip, i.e. the biggest mip is at the end.
+    mip_sizes: Vec<u32>,
 }
 
 impl D3DTXHeader {
@@ -91,25 +363,36 @@ impl D3DTXHeader {
         let mip_maps = input.read_u32::<LittleEndian>()?;
         let width = input.read_u32::<LittleEndian>()?;
         let height = input.read_u32::<LittleEndian>()?;
-        // skip unknowns
-        input.seek(SeekFrom::Current(0x08))?;
+        let depth = input.read_u32::<LittleEndian>()?.max(1);
+        let array_size = input.read_u32::<LittleEndian>()?.max(1);
         let dxt_type = input.read_u32::<LittleEndian>()?;
         let format = TextureFormat::parse(dxt_type);
+        let layout = TextureLayout::parse(input.read_u32::<LittleEndian>()?);
+        if let TextureLayout::Unknown(value) = layout {
+            log::warn!("unknown texture layout {}, handled as 2D texture", value);
+        }
+        let color_space = ColorSpace::parse(input.read_i32::<LittleEndian>()?);
 
         // skip unknowns
-        input.seek(SeekFrom::Current(0x5C))?;
+        input.seek(SeekFrom::Current(0x54))?;
 
         log::debug!(
-            "mip map info start = {:#X}, mip_map_count = {}, width = {}, height = {}, format = {:?}",
+            "mip map info start = {:#X}, mip_map_count = {}, width = {}, height = {}, depth = {}, array_size = {}, format = {:?}, layout = {:?}, color_space = {:?}",
             input.stream_position()?,
             mip_maps,
             width,
             height,
+            depth,
+            array_size,
             format,
+            layout,
+            color_space,
         );
 
         let mut mip_sizes = Vec::new();
-        for _ in 0..mip_maps {
+        // one region per face/array element and mip, note that volume textures store all slices
+        // of a mip in a single region
+        for _ in 0..mip_maps * layout.surfaces_per_mip(array_size) {
             // skip unknowns
             input.seek(SeekFrom::Current(0x0C))?;
             let mip_size = input.read_u32::<LittleEndian>()?;
@@ -120,39 +403,116 @@ impl D3DTXHeader {
 
         log::debug!("data_start = {:#X}", input.stream_position()?);
 
-        // skip mip-size data
-        // the mip_sizes array contains the sizes in number of bytes for the mip header data
-        // the mip_sizes array starts with the smallest mip, i.e. the biggest mip is at the end
-        for mip_size in &mip_sizes[..mip_sizes.len() - 1] {
-            input.seek(SeekFrom::Current(*mip_size as i64))?;
-        }
-
         Ok(Self {
             name: name.to_string(),
             width,
             height,
             format,
+            format_code: dxt_type,
+            data_size: mip_sizes.last().copied().unwrap_or_default(),
+            color_space,
+            layout,
+            depth,
+            array_size,
+            mip_count: mip_maps,
+            mip_sizes,
         })
     }
+
+    /// Reads the data of all mip-maps, which directly follows the header.
+    /// Returns the mip-maps sorted by size, starting with the biggest one, and face.
+    fn read_mips<T: Read>(&self, mut input: T) -> Result<Vec<Mip>> {
+        let surfaces = self.layout.surfaces_per_mip(self.array_size) as usize;
+        let mut mips = Vec::with_capacity(self.mip_sizes.len());
+        for (index, mip_size) in self.mip_sizes.iter().enumerate() {
+            // the data starts with the smallest mip, the surfaces of a mip follow each other
+            let level = self.mip_count - 1 - (index / surfaces) as u32;
+            let face = (index % surfaces) as u32;
+            let mut data = vec![0; *mip_size as usize];
+            input
+                .read_exact(&mut data)
+                .with_context(|| format!("could not read data of mip-map level {}", level))?;
+            let depth = if self.layout == TextureLayout::Volume {
+                (self.depth >> level).max(1)
+            } else {
+                1
+            };
+            mips.push(Mip {
+                level,
+                face,
+                width: (self.width >> level).max(1),
+                height: (self.height >> level).max(1),
+                depth,
+                data,
+            });
+        }
+        mips.sort_by_key(|mip| (mip.level, mip.face));
+        Ok(mips)
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
-enum TextureFormat {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextureFormat {
     BCn(BCnVariant),
-    A8,
+    Uncompressed(PixelFormat),
     Unknown(u32),
 }
 
 impl TextureFormat {
-    fn parse(value: u32) -> Self {
+    /// Maps Telltales surface format codes to the supported formats.
+    pub fn parse(value: u32) -> Self {
         match value {
-            16 | 17 => Self::A8,
+            0x00 => Self::Uncompressed(PixelFormat::ARGB8),
+            0x01 => Self::Uncompressed(PixelFormat::ARGB16),
+            0x02 => Self::Uncompressed(PixelFormat::RGB565),
+            0x03 => Self::Uncompressed(PixelFormat::ARGB1555),
+            0x04 => Self::Uncompressed(PixelFormat::ARGB4),
+            0x05 => Self::Uncompressed(PixelFormat::ARGB2101010),
+            0x06 => Self::Uncompressed(PixelFormat::R16),
+            0x07 => Self::Uncompressed(PixelFormat::RG16),
+            0x08 => Self::Uncompressed(PixelFormat::RGBA16),
+            0x09 => Self::Uncompressed(PixelFormat::RG8),
+            0x0A => Self::Uncompressed(PixelFormat::RGBA8),
+            0x0E => Self::Uncompressed(PixelFormat::R8),
+            0x0F => Self::Uncompressed(PixelFormat::RGBA8S),
+            0x10 => Self::Uncompressed(PixelFormat::A8),
+            0x11 => Self::Uncompressed(PixelFormat::L8),
+            0x12 => Self::Uncompressed(PixelFormat::AL8),
+            0x13 => Self::Uncompressed(PixelFormat::L16),
+            0x20 => Self::Uncompressed(PixelFormat::R16F),
+            0x21 => Self::Uncompressed(PixelFormat::RG16F),
+            0x22 => Self::Uncompressed(PixelFormat::RGBA16F),
+            0x23 => Self::Uncompressed(PixelFormat::R32F),
+            0x24 => Self::Uncompressed(PixelFormat::RG32F),
+            0x25 => Self::Uncompressed(PixelFormat::RGBA32F),
+            0x27 => Self::Uncompressed(PixelFormat::RGB111110F),
+            0x28 => Self::Uncompressed(PixelFormat::RGB9E5F),
             64 => Self::BCn(BCnVariant::BC1),
-            65 => Self::BCn(BCnVariant::BC2), // TODO: only guess, check with real data!
+            65 => Self::BCn(BCnVariant::BC2), // TODO: only guess, check with real data (--diagnose-d3dtx)!
             66 => Self::BCn(BCnVariant::BC3),
             67 => Self::BCn(BCnVariant::BC4),
             68 => Self::BCn(BCnVariant::BC5),
+            70 => Self::BCn(BCnVariant::BC6H),
+            71 => Self::BCn(BCnVariant::BC7),
             _ => Self::Unknown(value),
         }
     }
+
+    /// Returns the name of known surface formats that can not be decoded (yet).
+    fn unsupported_name(value: u32) -> Option<&'static str> {
+        let name = match value {
+            0x0B => "R32 (integer)",
+            0x0C => "RG32 (integer)",
+            0x0D => "RGBA32 (integer)",
+            0x26 => "RGBA1010102F",
+            0x30..=0x37 => "depth/stencil",
+            0x45 => "CTX1",
+            0x50..=0x53 => "PVRTC",
+            0x60..=0x62 => "ATC",
+            0x70..=0x75 => "ETC",
+            0x80 => "ASTC",
+            _ => return None,
+        };
+        Some(name)
+    }
 }
@@ -0,0 +1,116 @@
+//! Builders of .d3dtx files and images for the tests of the d3dtx modules.
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use image::{DynamicImage, RgbaImage};
+
+use super::{Mip, Texture, TextureFormat, TextureLayout};
+
+const MSV6: u32 = 1297307190;
+
+/// Builds a minimal .d3dtx file with zeroed image data, which is accepted by
+/// `D3DTXHeader::parse`, followed by some trailing bytes that have to be preserved.
+pub fn reference(width: u32, height: u32, mip_count: u32, format_code: u32) -> Vec<u8> {
+    let variant = match TextureFormat::parse(format_code) {
+        TextureFormat::BCn(variant) => variant,
+        format => panic!("{:?} is not block compressed", format),
+    };
+    let name = b"test.d3dtx";
+    let mut file = Vec::new();
+    file.write_u32::<LittleEndian>(MSV6).unwrap();
+    file.write_u32::<LittleEndian>(0).unwrap(); // file size
+    file.extend_from_slice(&[0; 0x08]);
+    file.write_u32::<LittleEndian>(0).unwrap(); // parameter count
+    file.extend_from_slice(&[0; 0x14]);
+    file.write_u32::<LittleEndian>(name.len() as u32 + 8)
+        .unwrap();
+    file.write_u32::<LittleEndian>(name.len() as u32).unwrap();
+    file.extend_from_slice(name);
+    file.extend_from_slice(&[0; 0x0C]);
+    file.push(0); // no additional header
+    for value in [mip_count, width, height, 1, 1, format_code, 0, 0] {
+        file.write_u32::<LittleEndian>(value).unwrap();
+    }
+    file.extend_from_slice(&[0; 0x54]);
+
+    // the data starts with the smallest mip-map
+    let sizes: Vec<u32> = (0..mip_count)
+        .rev()
+        .map(|level| {
+            let blocks_x = (width >> level).max(1).div_ceil(4);
+            let blocks_y = (height >> level).max(1).div_ceil(4);
+            blocks_x * blocks_y * variant.encoded_bytes_per_block() as u32
+        })
+        .collect();
+    for size in &sizes {
+        file.extend_from_slice(&[0; 0x0C]);
+        file.write_u32::<LittleEndian>(*size).unwrap();
+        file.extend_from_slice(&[0; 0x08]);
+    }
+    file.resize(file.len() + sizes.iter().sum::<u32>() as usize, 0);
+    file.extend_from_slice(b"trailer");
+    file
+}
+
+/// A smooth image, which every block compression is able to approximate well.
+pub fn gradient(width: u32, height: u32) -> DynamicImage {
+    DynamicImage::ImageRgba8(RgbaImage::from_fn(width, height, |x, y| {
+        [
+            (x * 255 / (width - 1)) as u8,
+            (y * 255 / (height - 1)) as u8,
+            128,
+            (255 - (x + y) * 2) as u8,
+        ]
+        .into()
+    }))
+}
+
+/// Builds a texture with the given layout and mip-map count, in which every byte of the data of a
+/// mip-map is `16 * level + face`, i.e. the mip-maps can be told apart after writing them.
+pub fn texture(
+    format: TextureFormat,
+    layout: TextureLayout,
+    array_size: u32,
+    width: u32,
+    height: u32,
+    mip_count: u32,
+) -> Texture {
+    let mut mips = Vec::new();
+    for level in 0..mip_count {
+        let mip_width = (width >> level).max(1);
+        let mip_height = (height >> level).max(1);
+        let size = match format {
+            TextureFormat::BCn(variant) => {
+                mip_width.div_ceil(4)
+                    * mip_height.div_ceil(4)
+                    * variant.encoded_bytes_per_block() as u32
+            }
+            TextureFormat::Uncompressed(format) => {
+                mip_width * mip_height * format.bytes_per_pixel() as u32
+            }
+            TextureFormat::Unknown(_) => 0,
+        };
+        for face in 0..layout.surfaces_per_mip(array_size) {
+            mips.push(Mip {
+                level,
+                face,
+                width: mip_width,
+                height: mip_height,
+                depth: 1,
+                data: vec![(16 * level + face) as u8; size as usize],
+            });
+        }
+    }
+    Texture {
+        name: "test".to_string(),
+        image: DynamicImage::new_rgba8(width, height),
+        hdr_image: None,
+        format,
+        format_code: 0,
+        color_space: None,
+        layout,
+        depth: 1,
+        array_size,
+        mip_count,
+        mips,
+    }
+}
@@ -0,0 +1,681 @@
+//! Decoding of the uncompressed and floating point surface formats of Telltale textures.
+//!
+//! Every pixel is first decoded to normalized (or HDR) floating point channels, which are then
+//! stored in the `DynamicImage` that best fits the channel count and precision of the format.
+//! Single channel formats are stored as luma, two channel formats as luma + alpha (like BC4 and BC5).
+
+use std::convert::TryInto;
+
+use anyhow::{anyhow, Result};
+use image::{DynamicImage, ImageBuffer, Luma, LumaA, Rgb, Rgba};
+
+use super::{
+    bcn_encoder::f32_to_half,
+    bcn_image::{half_to_f32, Rgb32FImage},
+};
+
+/// An uncompressed surface format, the names follow Telltales naming scheme.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// 8-bit channels, stored as B, G, R, A
+    ARGB8,
+    /// 16-bit channels, stored as R, G, B, A
+    ARGB16,
+    RGB565,
+    ARGB1555,
+    ARGB4,
+    /// 10-bit color channels and 2-bit alpha, R in the least significant bits
+    ARGB2101010,
+    R16,
+    RG16,
+    RGBA16,
+    RG8,
+    /// 8-bit channels, stored as R, G, B, A
+    RGBA8,
+    R8,
+    /// signed 8-bit channels, mapped from [-1, 1] to [0, 1]
+    RGBA8S,
+    A8,
+    L8,
+    /// luminance and alpha
+    AL8,
+    L16,
+    R16F,
+    RG16F,
+    RGBA16F,
+    R32F,
+    RG32F,
+    RGBA32F,
+    RGB111110F,
+    RGB9E5F,
+}
+
+impl PixelFormat {
+    /// Returns the amount of bytes per pixel
+    pub const fn bytes_per_pixel(self) -> usize {
+        match self {
+            Self::R8 | Self::A8 | Self::L8 => 1,
+            Self::RGB565
+            | Self::ARGB1555
+            | Self::ARGB4
+            | Self::R16
+            | Self::RG8
+            | Self::AL8
+            | Self::L16
+            | Self::R16F => 2,
+            Self::ARGB8
+            | Self::ARGB2101010
+            | Self::RG16
+            | Self::RGBA8
+            | Self::RGBA8S
+            | Self::RG16F
+            | Self::R32F
+            | Self::RGB111110F
+            | Self::RGB9E5F => 4,
+            Self::ARGB16 | Self::RGBA16 | Self::RGBA16F | Self::RG32F => 8,
+            Self::RGBA32F => 16,
+        }
+    }
+
+    /// Returns the number of channels of the decoded image
+    pub const fn channels(self) -> usize {
+        match self {
+            Self::R16 | Self::R8 | Self::A8 | Self::L8 | Self::L16 | Self::R16F | Self::R32F => 1,
+            Self::RG16 | Self::RG8 | Self::AL8 | Self::RG16F | Self::RG32F => 2,
+            Self::RGB565 | Self::RGB111110F | Self::RGB9E5F => 3,
+            Self::ARGB8
+            | Self::ARGB16
+            | Self::ARGB1555
+            | Self::ARGB4
+            | Self::ARGB2101010
+            | Self::RGBA16
+            | Self::RGBA8
+            | Self::RGBA8S
+            | Self::RGBA16F
+            | Self::RGBA32F => 4,
+        }
+    }
+
+    /// Whether the values are stored as floats and thus may exceed [0, 1]
+    pub const fn is_float(self) -> bool {
+        matches!(
+            self,
+            Self::R16F
+                | Self::RG16F
+                | Self::RGBA16F
+                | Self::R32F
+                | Self::RG32F
+                | Self::RGBA32F
+                | Self::RGB111110F
+                | Self::RGB9E5F
+        )
+    }
+
+    /// Whether the format has more than 8 bits of precision per channel
+    const fn is_high_precision(self) -> bool {
+        self.is_float()
+            || matches!(
+                self,
+                Self::ARGB16
+                    | Self::ARGB2101010
+                    | Self::R16
+                    | Self::RG16
+                    | Self::RGBA16
+                    | Self::L16
+            )
+    }
+
+    /// Decodes a single pixel into `out`, which has a length of `self.channels()`.
+    fn decode_pixel(self, bytes: &[u8], out: &mut [f32]) {
+        let u8_norm = |value: u8| f32::from(value) / 255.0;
+        let u16_at = |index: usize| u16::from_le_bytes([bytes[index * 2], bytes[index * 2 + 1]]);
+        let u16_norm = |index: usize| f32::from(u16_at(index)) / 65535.0;
+        let f32_at =
+            |index: usize| f32::from_le_bytes(bytes[index * 4..index * 4 + 4].try_into().unwrap());
+        let u32_at =
+            |index: usize| u32::from_le_bytes(bytes[index * 4..index * 4 + 4].try_into().unwrap());
+        let bits = |value: u32, shift: u32, count: u32| {
+            ((value >> shift) & ((1 << count) - 1)) as f32 / ((1 << count) - 1) as f32
+        };
+
+        match self {
+            Self::ARGB8 => {
+                out[0] = u8_norm(bytes[2]);
+                out[1] = u8_norm(bytes[1]);
+                out[2] = u8_norm(bytes[0]);
+                out[3] = u8_norm(bytes[3]);
+            }
+            Self::RGBA8 | Self::RG8 | Self::R8 | Self::A8 | Self::L8 | Self::AL8 => {
+                for (value, byte) in out.iter_mut().zip(bytes) {
+                    *value = u8_norm(*byte);
+                }
+            }
+            Self::RGBA8S => {
+                for (value, byte) in out.iter_mut().zip(bytes) {
+                    let signed = f32::from(*byte as i8) / 127.0;
+                    *value = (signed.max(-1.0) + 1.0) / 2.0;
+                }
+            }
+            Self::ARGB16 | Self::RGBA16 | Self::RG16 | Self::R16 | Self::L16 => {
+                for (index, value) in out.iter_mut().enumerate() {
+                    *value = u16_norm(index);
+                }
+            }
+            Self::RGB565 => {
+                let value = u32::from(u16_at(0));
+                out[0] = bits(value, 11, 5);
+                out[1] = bits(value, 5, 6);
+                out[2] = bits(value, 0, 5);
+            }
+            Self::ARGB1555 => {
+                let value = u32::from(u16_at(0));
+                out[0] = bits(value, 10, 5);
+                out[1] = bits(value, 5, 5);
+                out[2] = bits(value, 0, 5);
+                out[3] = bits(value, 15, 1);
+            }
+            Self::ARGB4 => {
+                let value = u32::from(u16_at(0));
+                out[0] = bits(value, 8, 4);
+                out[1] = bits(value, 4, 4);
+                out[2] = bits(value, 0, 4);
+                out[3] = bits(value, 12, 4);
+            }
+            Self::ARGB2101010 => {
+                let value = u32_at(0);
+                out[0] = bits(value, 0, 10);
+                out[1] = bits(value, 10, 10);
+                out[2] = bits(value, 20, 10);
+                out[3] = bits(value, 30, 2);
+            }
+            Self::R16F | Self::RG16F | Self::RGBA16F => {
+                for (index, value) in out.iter_mut().enumerate() {
+                    *value = half_to_f32(u16_at(index));
+                }
+            }
+            Self::R32F | Self::RG32F | Self::RGBA32F => {
+                for (index, value) in out.iter_mut().enumerate() {
+                    *value = f32_at(index);
+                }
+            }
+            Self::RGB111110F => {
+                let value = u32_at(0);
+                out[0] = small_float_to_f32(value & 0x7FF, 6);
+                out[1] = small_float_to_f32((value >> 11) & 0x7FF, 6);
+                out[2] = small_float_to_f32(value >> 22, 5);
+            }
+            Self::RGB9E5F => {
+                let value = u32_at(0);
+                let scale = 2f32.powi((value >> 27) as i32 - 15 - 9);
+                out[0] = (value & 0x1FF) as f32 * scale;
+                out[1] = ((value >> 9) & 0x1FF) as f32 * scale;
+                out[2] = ((value >> 18) & 0x1FF) as f32 * scale;
+            }
+        }
+    }
+
+    /// Encodes a single pixel with `self.channels()` values into `bytes`, the reverse of `decode_pixel`.
+    fn encode_pixel(self, values: &[f32], bytes: &mut [u8]) {
+        let u8_norm = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+        let u16_norm = |value: f32| (value.clamp(0.0, 1.0) * 65535.0).round() as u16;
+        let bits = |value: f32, shift: u32, count: u32| {
+            ((value.clamp(0.0, 1.0) * ((1 << count) - 1) as f32).round() as u32) << shift
+        };
+
+        match self {
+            Self::ARGB8 => {
+                bytes.copy_from_slice(&[
+                    u8_norm(values[2]),
+                    u8_norm(values[1]),
+                    u8_norm(values[0]),
+                    u8_norm(values[3]),
+                ]);
+            }
+            Self::RGBA8 | Self::RG8 | Self::R8 | Self::A8 | Self::L8 | Self::AL8 => {
+                for (byte, value) in bytes.iter_mut().zip(values) {
+                    *byte = u8_norm(*value);
+                }
+            }
+            Self::RGBA8S => {
+                for (byte, value) in bytes.iter_mut().zip(values) {
+                    *byte = ((value.clamp(0.0, 1.0) * 2.0 - 1.0) * 127.0).round() as i8 as u8;
+                }
+            }
+            Self::ARGB16 | Self::RGBA16 | Self::RG16 | Self::R16 | Self::L16 => {
+                for (chunk, value) in bytes.chunks_mut(2).zip(values) {
+                    chunk.copy_from_slice(&u16_norm(*value).to_le_bytes());
+                }
+            }
+            Self::RGB565 => {
+                let value = bits(values[0], 11, 5) | bits(values[1], 5, 6) | bits(values[2], 0, 5);
+                bytes.copy_from_slice(&(value as u16).to_le_bytes());
+            }
+            Self::ARGB1555 => {
+                let value = bits(values[0], 10, 5)
+                    | bits(values[1], 5, 5)
+                    | bits(values[2], 0, 5)
+                    | bits(values[3], 15, 1);
+                bytes.copy_from_slice(&(value as u16).to_le_bytes());
+            }
+            Self::ARGB4 => {
+                let value = bits(values[0], 8, 4)
+                    | bits(values[1], 4, 4)
+                    | bits(values[2], 0, 4)
+                    | bits(values[3], 12, 4);
+                bytes.copy_from_slice(&(value as u16).to_le_bytes());
+            }
+            Self::ARGB2101010 => {
+                let value = bits(values[0], 0, 10)
+                    | bits(values[1], 10, 10)
+                    | bits(values[2], 20, 10)
+                    | bits(values[3], 30, 2);
+                bytes.copy_from_slice(&value.to_le_bytes());
+            }
+            Self::R16F | Self::RG16F | Self::RGBA16F => {
+                for (chunk, value) in bytes.chunks_mut(2).zip(values) {
+                    chunk.copy_from_slice(&f32_to_half(*value).to_le_bytes());
+                }
+            }
+            Self::R32F | Self::RG32F | Self::RGBA32F => {
+                for (chunk, value) in bytes.chunks_mut(4).zip(values) {
+                    chunk.copy_from_slice(&value.to_le_bytes());
+                }
+            }
+            Self::RGB111110F => {
+                let value = f32_to_small_float(values[0], 6)
+                    | (f32_to_small_float(values[1], 6) << 11)
+                    | (f32_to_small_float(values[2], 5) << 22);
+                bytes.copy_from_slice(&value.to_le_bytes());
+            }
+            Self::RGB9E5F => {
+                // the shared exponent is chosen for the biggest channel
+                let max = values[..3]
+                    .iter()
+                    .fold(0f32, |max, value| max.max(*value))
+                    .min(65408.0);
+                let mut exponent = (max.max(2f32.powi(-16)).log2().floor() as i32 + 1).max(-15);
+                let mut scale = 2f32.powi(exponent - 9);
+                if (max / scale).round() >= 512.0 {
+                    exponent += 1;
+                    scale *= 2.0;
+                }
+                let mantissa =
+                    |value: f32| (value.clamp(0.0, max) / scale).round().min(511.0) as u32;
+                let value = mantissa(values[0])
+                    | (mantissa(values[1]) << 9)
+                    | (mantissa(values[2]) << 18)
+                    | (((exponent + 15) as u32) << 27);
+                bytes.copy_from_slice(&value.to_le_bytes());
+            }
+        }
+    }
+}
+
+/// Converts a non-negative f32 to an unsigned float with a 5-bit exponent and a mantissa of
+/// the given size, the reverse of `small_float_to_f32`.
+fn f32_to_small_float(value: f32, mantissa_bits: u32) -> u32 {
+    // the half-float has the same exponent and a 10-bit mantissa, rounding must not result in infinity
+    let max = (0x1F << mantissa_bits) - 1;
+    ((u32::from(f32_to_half(value.max(0.0))) + (1 << (9 - mantissa_bits))) >> (10 - mantissa_bits))
+        .min(max)
+}
+
+/// Converts an unsigned float with a 5-bit exponent and a mantissa of the given size to a f32,
+/// as used by the packed R11G11B10 format.
+fn small_float_to_f32(value: u32, mantissa_bits: u32) -> f32 {
+    let exponent = (value >> mantissa_bits) as i32;
+    let mantissa = (value & ((1 << mantissa_bits) - 1)) as f32 / (1 << mantissa_bits) as f32;
+    match exponent {
+        0 => mantissa * 2f32.powi(-14),
+        0x1F if mantissa == 0.0 => f32::INFINITY,
+        0x1F => f32::NAN,
+        _ => (1.0 + mantissa) * 2f32.powi(exponent - 15),
+    }
+}
+
+/// Decodes the pixel data of an uncompressed texture.
+/// Returns the image and, for floating point formats, the unclamped RGB values.
+pub fn decode(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    format: PixelFormat,
+) -> Result<(DynamicImage, Option<Rgb32FImage>)> {
+    let pixel_count = width as usize * height as usize;
+    let bytes_per_pixel = format.bytes_per_pixel();
+    if data.len() < pixel_count * bytes_per_pixel {
+        return Err(anyhow!(
+            "data buffer not big enough for {:?} texture ({} bytes for {}x{} pixels)",
+            format,
+            data.len(),
+            width,
+            height
+        ));
+    }
+
+    let channels = format.channels();
+    let mut values = vec![0f32; pixel_count * channels];
+    for (pixel, bytes) in values
+        .chunks_mut(channels)
+        .zip(data.chunks(bytes_per_pixel))
+    {
+        format.decode_pixel(bytes, pixel);
+    }
+
+    let hdr_image = if format.is_float() {
+        let rgb = values
+            .chunks(channels)
+            .flat_map(|pixel| match channels {
+                1 => [pixel[0], pixel[0], pixel[0]],
+                2 => [pixel[0], pixel[1], 0.0],
+                _ => [pixel[0], pixel[1], pixel[2]],
+            })
+            .collect();
+        Some(
+            Rgb32FImage::from_vec(width, height, rgb)
+                .ok_or(anyhow!("could not construct Rgb32F image"))?,
+        )
+    } else {
+        None
+    };
+
+    let image = if format.is_high_precision() {
+        let data: Vec<u16> = values
+            .iter()
+            .map(|value| (value.clamp(0.0, 1.0) * 65535.0).round() as u16)
+            .collect();
+        match channels {
+            1 => ImageBuffer::<Luma<u16>, _>::from_vec(width, height, data)
+                .map(DynamicImage::ImageLuma16),
+            2 => ImageBuffer::<LumaA<u16>, _>::from_vec(width, height, data)
+                .map(DynamicImage::ImageLumaA16),
+            3 => ImageBuffer::<Rgb<u16>, _>::from_vec(width, height, data)
+                .map(DynamicImage::ImageRgb16),
+            _ => ImageBuffer::<Rgba<u16>, _>::from_vec(width, height, data)
+                .map(DynamicImage::ImageRgba16),
+        }
+    } else {
+        let data: Vec<u8> = values
+            .iter()
+            .map(|value| (value.clamp(0.0, 1.0) * 255.0).round() as u8)
+            .collect();
+        match channels {
+            1 => ImageBuffer::<Luma<u8>, _>::from_vec(width, height, data)
+                .map(DynamicImage::ImageLuma8),
+            2 => ImageBuffer::<LumaA<u8>, _>::from_vec(width, height, data)
+                .map(DynamicImage::ImageLumaA8),
+            3 => ImageBuffer::<Rgb<u8>, _>::from_vec(width, height, data)
+                .map(DynamicImage::ImageRgb8),
+            _ => ImageBuffer::<Rgba<u8>, _>::from_vec(width, height, data)
+                .map(DynamicImage::ImageRgba8),
+        }
+    }
+    .ok_or(anyhow!(
+        "could not construct image for {:?} texture",
+        format
+    ))?;
+
+    Ok((image, hdr_image))
+}
+
+/// Encodes an image to the pixel data of an uncompressed texture.
+/// The channels are taken like they are stored by `decode`, e.g. luma + alpha for two channel formats.
+pub fn encode(image: &DynamicImage, format: PixelFormat) -> Vec<u8> {
+    let bytes_per_pixel = format.bytes_per_pixel();
+    let channels = format.channels();
+    let pixels = normalized_pixels(image, channels <= 2);
+
+    let mut data = vec![0u8; pixels.len() * bytes_per_pixel];
+    for (bytes, pixel) in data.chunks_mut(bytes_per_pixel).zip(&pixels) {
+        format.encode_pixel(&pixel[..channels], bytes);
+    }
+    data
+}
+
+/// Returns the channels of all pixels normalized to [0, 1], either as RGBA or as luma + alpha
+/// (in the first two channels). 8-bit images are normalized directly, since the conversion of
+/// `image` to 16 bits does not map 255 to 65535.
+pub(super) fn normalized_pixels(image: &DynamicImage, luma: bool) -> Vec<[f32; 4]> {
+    let u8_norm = |value: u8| f32::from(value) / 255.0;
+    let u16_norm = |value: u16| f32::from(value) / 65535.0;
+    let high_precision = image.color().bytes_per_pixel() > image.color().channel_count();
+    match (luma, high_precision) {
+        (true, false) => image
+            .to_luma_alpha8()
+            .pixels()
+            .map(|pixel| [u8_norm(pixel[0]), u8_norm(pixel[1]), 0.0, 0.0])
+            .collect(),
+        (true, true) => image
+            .to_luma_alpha16()
+            .pixels()
+            .map(|pixel| [u16_norm(pixel[0]), u16_norm(pixel[1]), 0.0, 0.0])
+            .collect(),
+        (false, false) => image
+            .to_rgba8()
+            .pixels()
+            .map(|pixel| pixel.0.map(u8_norm))
+            .collect(),
+        (false, true) => image
+            .to_rgba16()
+            .pixels()
+            .map(|pixel| pixel.0.map(u16_norm))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pixels of every format with hand-computed values, encoding the values results in the bytes again.
+    const PIXELS: &[(PixelFormat, &[u8], &[f32])] = &[
+        (
+            PixelFormat::ARGB8,
+            &[0x10, 0x80, 0xFF, 0x40],
+            &[1.0, 128.0 / 255.0, 16.0 / 255.0, 64.0 / 255.0],
+        ),
+        (
+            PixelFormat::RGBA8,
+            &[0, 51, 102, 255],
+            &[0.0, 0.2, 0.4, 1.0],
+        ),
+        (PixelFormat::RG8, &[255, 0], &[1.0, 0.0]),
+        (PixelFormat::R8, &[51], &[0.2]),
+        (PixelFormat::A8, &[102], &[0.4]),
+        (PixelFormat::L8, &[255], &[1.0]),
+        (PixelFormat::AL8, &[0, 255], &[0.0, 1.0]),
+        // 127, -127, 0 and 64
+        (
+            PixelFormat::RGBA8S,
+            &[0x7F, 0x81, 0x00, 0x40],
+            &[1.0, 0.0, 0.5, (64.0 / 127.0 + 1.0) / 2.0],
+        ),
+        (
+            PixelFormat::ARGB16,
+            &[0xFF, 0xFF, 0x00, 0x00, 0x33, 0x33, 0xCC, 0xCC],
+            &[1.0, 0.0, 0.2, 0.8],
+        ),
+        (
+            PixelFormat::RGBA16,
+            &[0x33, 0x33, 0xCC, 0xCC, 0xFF, 0xFF, 0x00, 0x00],
+            &[0.2, 0.8, 1.0, 0.0],
+        ),
+        (PixelFormat::RG16, &[0x33, 0x33, 0xFF, 0xFF], &[0.2, 1.0]),
+        (PixelFormat::R16, &[0xCC, 0xCC], &[0.8]),
+        (PixelFormat::L16, &[0x00, 0x00], &[0.0]),
+        // R = 31, G = 32, B = 0
+        (PixelFormat::RGB565, &[0x00, 0xFC], &[1.0, 32.0 / 63.0, 0.0]),
+        // A = 1, R = 0, G = 31, B = 10
+        (
+            PixelFormat::ARGB1555,
+            &[0xEA, 0x83],
+            &[0.0, 1.0, 10.0 / 31.0, 1.0],
+        ),
+        // A = 8, R = 15, G = 3, B = 0
+        (
+            PixelFormat::ARGB4,
+            &[0x30, 0x8F],
+            &[1.0, 0.2, 0.0, 8.0 / 15.0],
+        ),
+        // R = 1023, G = 0, B = 341, A = 2
+        (
+            PixelFormat::ARGB2101010,
+            &[0xFF, 0x03, 0x50, 0x95],
+            &[1.0, 0.0, 1.0 / 3.0, 2.0 / 3.0],
+        ),
+        (PixelFormat::R16F, &[0x00, 0x3C], &[1.0]),
+        (PixelFormat::RG16F, &[0x00, 0xC0, 0x00, 0x38], &[-2.0, 0.5]),
+        (
+            PixelFormat::RGBA16F,
+            &[0xFF, 0x7B, 0x00, 0x00, 0x00, 0x34, 0x01, 0x00],
+            &[65504.0, 0.0, 0.25, 1.0 / 16_777_216.0],
+        ),
+        (PixelFormat::R32F, &[0x00, 0x00, 0xC0, 0x3F], &[1.5]),
+        (
+            PixelFormat::RG32F,
+            &[0x00, 0x00, 0x80, 0xBF, 0x00, 0x00, 0x00, 0x3F],
+            &[-1.0, 0.5],
+        ),
+        (
+            PixelFormat::RGBA32F,
+            &[
+                0x00, 0x00, 0x00, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x3F, 0x00, 0x00,
+                0x80, 0x3E,
+            ],
+            &[2.0, 0.0, 1.0, 0.25],
+        ),
+        // R = 1.0 (0x3C0), G = 0.5 (0x380), B = 1.0 (0x1E0)
+        (
+            PixelFormat::RGB111110F,
+            &[0xC0, 0x03, 0x1C, 0x78],
+            &[1.0, 0.5, 1.0],
+        ),
+        // mantissas 256, 128 and 0 with the exponent 16, i.e. a scale of 2^-8
+        (
+            PixelFormat::RGB9E5F,
+            &[0x00, 0x01, 0x01, 0x80],
+            &[1.0, 0.5, 0.0],
+        ),
+    ];
+
+    fn decode_pixel(format: PixelFormat, bytes: &[u8]) -> Vec<f32> {
+        let mut values = vec![0.0; format.channels()];
+        format.decode_pixel(bytes, &mut values);
+        values
+    }
+
+    fn encode_pixel(format: PixelFormat, values: &[f32]) -> Vec<u8> {
+        let mut bytes = vec![0; format.bytes_per_pixel()];
+        format.encode_pixel(values, &mut bytes);
+        bytes
+    }
+
+    #[test]
+    fn decodes_pixels_of_every_format() {
+        for (format, bytes, expected) in PIXELS {
+            assert_eq!(bytes.len(), format.bytes_per_pixel(), "{:?}", format);
+            let values = decode_pixel(*format, bytes);
+            for (value, expected) in values.iter().zip(*expected) {
+                assert!(
+                    (value - expected).abs() < 1e-6,
+                    "{:?}: {:?} != {:?}",
+                    format,
+                    values,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn encodes_pixels_of_every_format() {
+        for (format, bytes, values) in PIXELS {
+            assert_eq!(&encode_pixel(*format, values), bytes, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn round_trips_pixels_of_every_format() {
+        for (format, bytes, _) in PIXELS {
+            let values = decode_pixel(*format, bytes);
+            assert_eq!(&encode_pixel(*format, &values), bytes, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn clamps_the_most_negative_signed_value() {
+        assert_eq!(
+            decode_pixel(PixelFormat::RGBA8S, &[0x80, 0x80, 0x80, 0x80]),
+            [0.0; 4]
+        );
+        assert_eq!(
+            encode_pixel(PixelFormat::RGBA8S, &[0.0; 4]),
+            [0x81, 0x81, 0x81, 0x81]
+        );
+    }
+
+    #[test]
+    fn converts_small_floats() {
+        // zero, one and the maximum with 6 and 5 bit mantissas
+        for (mantissa_bits, max_bits, max) in [(6, 0x7BF, 65024.0), (5, 0x3DF, 64512.0)] {
+            assert_eq!(f32_to_small_float(0.0, mantissa_bits), 0);
+            assert_eq!(small_float_to_f32(0, mantissa_bits), 0.0);
+            assert_eq!(f32_to_small_float(1.0, mantissa_bits), 15 << mantissa_bits);
+            assert_eq!(small_float_to_f32(15 << mantissa_bits, mantissa_bits), 1.0);
+            assert_eq!(f32_to_small_float(max, mantissa_bits), max_bits);
+            assert_eq!(small_float_to_f32(max_bits, mantissa_bits), max);
+            // values above the maximum (even infinity) and negative values are clamped
+            assert_eq!(f32_to_small_float(65504.0, mantissa_bits), max_bits);
+            assert_eq!(f32_to_small_float(f32::INFINITY, mantissa_bits), max_bits);
+            assert_eq!(f32_to_small_float(-1.0, mantissa_bits), 0);
+            // the smallest denormal
+            assert_eq!(
+                small_float_to_f32(1, mantissa_bits),
+                2f32.powi(-14 - mantissa_bits as i32)
+            );
+            assert_eq!(
+                f32_to_small_float(2f32.powi(-14 - mantissa_bits as i32), mantissa_bits),
+                1
+            );
+            assert_eq!(
+                small_float_to_f32(0x1F << mantissa_bits, mantissa_bits),
+                f32::INFINITY
+            );
+        }
+    }
+
+    #[test]
+    fn round_trips_shared_exponent_edge_cases() {
+        let round_trip = |values: [f32; 3]| {
+            decode_pixel(
+                PixelFormat::RGB9E5F,
+                &encode_pixel(PixelFormat::RGB9E5F, &values),
+            )
+        };
+        assert_eq!(encode_pixel(PixelFormat::RGB9E5F, &[0.0; 3]), [0; 4]);
+        assert_eq!(round_trip([0.0; 3]), [0.0; 3]);
+        assert_eq!(round_trip([1.0, 1.0, 1.0]), [1.0, 1.0, 1.0]);
+        // the maximum is 511 * 2^7 in every channel, bigger values are clamped
+        assert_eq!(encode_pixel(PixelFormat::RGB9E5F, &[65408.0; 3]), [0xFF; 4]);
+        assert_eq!(round_trip([1e9, 65408.0, 0.0]), [65408.0, 65408.0, 0.0]);
+        // rounding the mantissa of the biggest channel up to 512 increases the exponent
+        assert_eq!(round_trip([511.75 / 256.0, 0.0, 0.0]), [2.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn decodes_and_encodes_images() {
+        let data = [0, 51, 102, 255, 255, 204, 153, 0];
+        let (image, hdr_image) = decode(&data, 2, 1, PixelFormat::RGBA8).unwrap();
+        assert!(hdr_image.is_none());
+        assert_eq!(image.as_rgba8().unwrap().as_raw(), &data);
+        assert_eq!(encode(&image, PixelFormat::RGBA8), data);
+
+        // two channel formats are stored as luma + alpha
+        let data = [0x00, 0x00, 0x80, 0x3F, 0x00, 0x00, 0x00, 0x40];
+        let (image, hdr_image) = decode(&data, 1, 1, PixelFormat::RG32F).unwrap();
+        assert_eq!(image.as_luma_alpha16().unwrap().as_raw(), &[65535, 65535]);
+        assert_eq!(hdr_image.unwrap().as_raw(), &[1.0, 2.0, 0.0]);
+
+        assert!(decode(&data, 2, 1, PixelFormat::RG32F).is_err());
+    }
+}
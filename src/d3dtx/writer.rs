@@ -0,0 +1,160 @@
+//! Writing of .d3dtx files, i.e. replacing the image data of an existing texture.
+//!
+//! The header of a reference texture is kept as it is, only the data of the mip-maps is
+//! replaced by the encoded image (and the mip-maps generated from it). This way, all fields that
+//! are not understood yet are preserved, but the image needs the same size and is encoded with
+//! the same format and number of mip-maps as the reference.
+
+use std::io::{Cursor, Seek, Write};
+
+use anyhow::{anyhow, Context, Result};
+use image::{imageops::FilterType, DynamicImage, GenericImageView};
+
+use super::{bcn_encoder, uncompressed, D3DTXHeader, TextureFormat, TextureLayout};
+
+/// Encodes the image with the format and mip-map count of the reference .d3dtx file and writes
+/// the resulting .d3dtx file. Only 2D textures with the same size as the reference are supported.
+pub fn write_d3dtx<W: Write>(image: &DynamicImage, reference: &[u8], mut out: W) -> Result<()> {
+    let mut input = Cursor::new(reference);
+    let header = D3DTXHeader::parse(&mut input).context("could not parse D3DTX header")?;
+    let data_start = input.stream_position()? as usize;
+    let data_size: usize = header.mip_sizes.iter().map(|size| *size as usize).sum();
+    let data_end = data_start + data_size;
+    if reference.len() < data_end {
+        return Err(anyhow!(
+            "reference texture is truncated ({} of {} bytes)",
+            reference.len(),
+            data_end
+        ));
+    }
+
+    if !matches!(
+        header.layout,
+        TextureLayout::Texture2D | TextureLayout::Unknown(_)
+    ) {
+        return Err(anyhow!(
+            "only 2D textures can be written, but the reference is a {:?} texture",
+            header.layout
+        ));
+    }
+    if image.dimensions() != (header.width, header.height) {
+        return Err(anyhow!(
+            "image has a size of {}x{}, but the reference texture has {}x{}",
+            image.width(),
+            image.height(),
+            header.width,
+            header.height
+        ));
+    }
+
+    // the data starts with the smallest mip-map
+    let mut data = Vec::with_capacity(data_size);
+    for (index, expected_size) in header.mip_sizes.iter().enumerate() {
+        let level = header.mip_count - 1 - index as u32;
+        let width = (header.width >> level).max(1);
+        let height = (header.height >> level).max(1);
+        let mip = if level == 0 {
+            image.clone()
+        } else {
+            image.resize_exact(width, height, FilterType::Triangle)
+        };
+        let encoded = match header.format {
+            TextureFormat::BCn(variant) => bcn_encoder::encode(&mip, variant),
+            TextureFormat::Uncompressed(format) => uncompressed::encode(&mip, format),
+            TextureFormat::Unknown(code) => {
+                return Err(anyhow!(
+                    "unsupported texture format {} ({:#04X})",
+                    code,
+                    code
+                ))
+            }
+        };
+        if encoded.len() != *expected_size as usize {
+            return Err(anyhow!(
+                "encoded mip-map level {} has {} bytes, but the reference has {} bytes",
+                level,
+                encoded.len(),
+                expected_size
+            ));
+        }
+        data.extend_from_slice(&encoded);
+    }
+
+    out.write_all(&reference[..data_start])?;
+    out.write_all(&data)?;
+    out.write_all(&reference[data_end..])?;
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::d3dtx::{
+        test_files::{gradient, reference},
+        BCnVariant, Texture,
+    };
+
+    /// The image in the color type of the decoded variant.
+    fn as_decoded(image: &DynamicImage, variant: BCnVariant) -> DynamicImage {
+        match variant {
+            BCnVariant::BC1 => DynamicImage::ImageRgb8(image.to_rgb8()),
+            BCnVariant::BC2 | BCnVariant::BC3 | BCnVariant::BC7 => {
+                DynamicImage::ImageRgba8(image.to_rgba8())
+            }
+            BCnVariant::BC4 => DynamicImage::ImageLuma8(image.to_luma8()),
+            BCnVariant::BC5 => DynamicImage::ImageLumaA8(image.to_luma_alpha8()),
+            BCnVariant::BC6H => DynamicImage::ImageRgb16(image.to_rgb16()),
+        }
+    }
+
+    /// The root-mean-square error of all channels in the range of 8-bit values.
+    fn rmse(a: &DynamicImage, b: &DynamicImage) -> f64 {
+        let (a, b) = (a.to_rgba8(), b.to_rgba8());
+        let sum: f64 = a
+            .as_raw()
+            .iter()
+            .zip(b.as_raw())
+            .map(|(a, b)| (f64::from(*a) - f64::from(*b)).powi(2))
+            .sum();
+        (sum / a.as_raw().len() as f64).sqrt()
+    }
+
+    #[test]
+    fn round_trip_bcn() {
+        // the bounds are in 8-bit units and leave some margin above the actual errors, the color
+        // formats approximate the two-dimensional gradient of a block with a line of colors
+        let variants = [
+            (64, BCnVariant::BC1, 8.0),
+            (65, BCnVariant::BC2, 8.0),
+            (66, BCnVariant::BC3, 8.0),
+            (67, BCnVariant::BC4, 2.0),
+            (68, BCnVariant::BC5, 2.0),
+            (70, BCnVariant::BC6H, 8.0),
+            (71, BCnVariant::BC7, 8.0),
+        ];
+        let image = gradient(32, 32);
+        for (format_code, variant, max_error) in variants {
+            let reference = reference(32, 32, 6, format_code);
+            let mut written = Vec::new();
+            write_d3dtx(&image, &reference, &mut written).unwrap();
+            assert_eq!(written.len(), reference.len(), "{:?}", variant);
+            assert!(written.ends_with(b"trailer"), "{:?}", variant);
+
+            let texture = Texture::parse(Cursor::new(&written)).unwrap();
+            assert_eq!(texture.format, TextureFormat::BCn(variant));
+            assert_eq!(texture.mip_count, 6);
+            let error = rmse(&texture.image, &as_decoded(&image, variant));
+            assert!(error < max_error, "{:?} has an RMSE of {}", variant, error);
+            // the smallest mip-map is padded to a whole block
+            assert_eq!(texture.decode_mip(5).unwrap().dimensions(), (1, 1));
+        }
+    }
+
+    #[test]
+    fn size_mismatch() {
+        let reference = reference(32, 32, 1, 64);
+        let result = write_d3dtx(&gradient(16, 16), &reference, Vec::new());
+        assert!(result.is_err());
+    }
+}
@@ -5,15 +5,24 @@ use std::{convert::TryInto, io::Write, path::Path};
 
 use anyhow::{anyhow, Context, Result};
 use cgmath::{Vector3, Vector4};
+use serde_json::{json, Value};
 
 use crate::{
     d3dmesh::{
         self,
+        materials::ParameterValue,
         mesh::{BoneReference, Face},
         polygons::PolygonInfo,
         textures::{TextureMap, TextureType},
     },
     export::rigged_object::MeshSet,
+    image_conversion::{
+        self,
+        alpha::AlphaUsage,
+        orm::OrmSources,
+        specular::{Channel, SpecularProfile},
+        Conversion, TextureFileFormat, TextureInfos,
+    },
     skeleton::Skeleton,
 };
 
@@ -58,21 +67,60 @@ where
 /// Holds information about a typical PBR material with textures
 pub struct Material {
     pub diffuse_texture: Option<String>,
+    /// How the alpha channel of the diffuse texture is used.
+    pub diffuse_alpha: AlphaUsage,
     pub normal_texture: Option<String>,
-    pub occlusion_roughness_metal_specular_texture: Option<String>,
+    /// G: roughness and B: metalness of the spec-map
+    pub metal_roughness_texture: Option<String>,
+    /// R: occlusion of the spec-map, a standalone `occlusion_texture` takes precedence
+    pub specular_occlusion_texture: Option<String>,
+    /// A: specular of the spec-map (see KHR_materials_specular)
+    pub specular_texture: Option<String>,
+    /// occlusion, roughness and metalness composed of several textures (see `image_conversion::orm`),
+    /// takes precedence over all other occlusion and roughness textures
+    pub orm_texture: Option<String>,
+    pub emissive_texture: Option<String>,
+    pub occlusion_texture: Option<String>,
+    pub roughness_texture: Option<String>,
+    pub base_color_factor: Option<[f32; 4]>,
+    pub alpha_cutoff: Option<f32>,
+    pub emissive_factor: Option<[f32; 3]>,
+    pub emissive_strength: Option<f32>,
+    pub texture_transform: Option<TextureTransform>,
+    /// Application specific data of the material, see `material_extras`.
+    pub extras: Option<Value>,
+}
+
+/// Offset and scale of the texture coordinates (see KHR_texture_transform).
+#[derive(Debug, Clone, Copy)]
+pub struct TextureTransform {
+    pub offset: [f32; 2],
+    pub scale: [f32; 2],
 }
 
 /// JointInfo holds the indices for joints from a skeleton
 pub type JointInfo = [u8; 4];
 
+/// The settings of the textures that are referenced by the materials (see `convert_materials`).
+#[derive(Debug, Clone, Copy)]
+pub struct TextureSettings<'a> {
+    /// the folder of the textures relative to the glTF file
+    pub folder: &'a str,
+    /// the requested format, converted textures use the format in `infos` instead
+    pub format: TextureFileFormat,
+    /// how spec-maps are split into textures
+    pub specular_profile: SpecularProfile,
+    /// the properties of the converted textures
+    pub infos: &'a TextureInfos,
+}
+
 /// Writes the mesh to a binary file and returns the correct information for this buffer for glTF 2.0.
 /// buffer_index is the index of the buffer information field for this binary file.
 pub fn mesh_to_binary<W: Write>(
     dst_binary: W,
     file_name_binary: String,
     dst_json: W,
-    texture_folder: &str,
-    output_folder: &str,
+    textures: &TextureSettings,
     mesh: &d3dmesh::Data,
     name: Option<String>,
 ) -> Result<()> {
@@ -88,8 +136,10 @@ pub fn mesh_to_binary<W: Write>(
             None,
         )?;
 
-    let materials = convert_materials(texture_folder, &mesh.materials);
-    let material_reference = single_object.add_materials(&materials, output_folder);
+    let materials = convert_materials(textures, &mesh.materials);
+    let material_reference = single_object
+        .add_materials(&materials)
+        .context("could not add materials")?;
 
     let separated_meshes = separate_mesh(&mesh.polygons, &mesh.mesh.faces);
 
@@ -118,8 +168,7 @@ pub fn rigged_object_to_binary<W: Write>(
     dst_binary: W,
     file_name_binary: String,
     dst_json: W,
-    texture_folder: &str,
-    output_folder: &str,
+    textures: &TextureSettings,
     root_name: Option<String>,
     meshes: &[(String, d3dmesh::Data)],
     skeleton: &Skeleton,
@@ -150,8 +199,10 @@ pub fn rigged_object_to_binary<W: Write>(
             joints.as_deref(),
         )?;
 
-        let materials = convert_materials(texture_folder, &mesh_data.materials);
-        let material_reference = rigged_object.add_materials(&materials, output_folder);
+        let materials = convert_materials(textures, &mesh_data.materials);
+        let material_reference = rigged_object
+            .add_materials(&materials)
+            .context("could not add materials")?;
 
         let separated_meshes = separate_mesh(&mesh_data.polygons, &mesh_data.mesh.faces);
 
@@ -184,48 +235,264 @@ pub fn rigged_object_to_binary<W: Write>(
 
 /// Converts a given list with material information from d3dmesh files to the local glTF Material counterpart.
 fn convert_materials(
-    texture_folder: &str,
+    textures: &TextureSettings,
     materials: &[d3dmesh::materials::Material],
 ) -> Vec<Material> {
+    let TextureSettings {
+        folder: texture_folder,
+        format: texture_format,
+        specular_profile,
+        infos: texture_infos,
+    } = *textures;
     let mut material_information_converted = Vec::new();
     for material in materials {
         let mut material_info = Material {
             diffuse_texture: None,
+            diffuse_alpha: AlphaUsage::Opaque,
             normal_texture: None,
-            occlusion_roughness_metal_specular_texture: None,
+            metal_roughness_texture: None,
+            specular_occlusion_texture: None,
+            specular_texture: None,
+            orm_texture: None,
+            emissive_texture: None,
+            occlusion_texture: None,
+            roughness_texture: None,
+            base_color_factor: material.diffuse_color(),
+            alpha_cutoff: material.alpha_test_threshold(),
+            emissive_factor: None,
+            emissive_strength: None,
+            texture_transform: None,
+            extras: None,
         };
+
+        // emissive colors can exceed 1.0, which glTF only allows via the emissive strength
+        if let Some(color) = material.emissive_color() {
+            let intensity = material.emissive_intensity().unwrap_or(1.0);
+            let max_component = color.iter().cloned().fold(0.0, f32::max);
+            if max_component > 1.0 {
+                material_info.emissive_factor = Some([
+                    color[0] / max_component,
+                    color[1] / max_component,
+                    color[2] / max_component,
+                ]);
+                material_info.emissive_strength = Some(intensity * max_component);
+            } else {
+                material_info.emissive_factor = Some(color);
+                material_info.emissive_strength = Some(intensity);
+            }
+        }
+
+        if material.uv_scale().is_some() || material.uv_offset().is_some() {
+            material_info.texture_transform = Some(TextureTransform {
+                offset: material.uv_offset().unwrap_or([0.0, 0.0]),
+                scale: material.uv_scale().unwrap_or([1.0, 1.0]),
+            });
+        }
+
+        // textures that have no glTF equivalent (e.g. secondary maps) are kept as layers in the extras
+        let mut layers = Vec::new();
         for texture in &material.textures {
-            if texture.map == TextureMap::Map || texture.map == TextureMap::MapA {
+            if texture.name.is_empty() {
+                continue;
+            }
+            let conversion = match Conversion::for_texture_type(&texture.kind) {
+                Some(conversion) => conversion,
+                None => continue,
+            };
+            let info = texture_infos.get(&texture.name);
+            let alpha = info.map_or(AlphaUsage::Opaque, |info| info.alpha);
+            // the format of the conversion, which may have been requested with another kind
+            let extension = info
+                .map_or_else(
+                    || texture_format.for_texture(conversion, &texture.kind, alpha),
+                    |info| info.file_format,
+                )
+                .extension();
+            let is_primary = texture.map == TextureMap::Map || texture.map == TextureMap::MapA;
+            if conversion == Conversion::Specular {
+                // spec-maps may be split into several textures (see `SpecularProfile`)
+                for packed in specular_profile.textures() {
+                    let path = texture_name_to_path(
+                        texture_folder,
+                        &texture.name,
+                        packed.suffix,
+                        extension,
+                    );
+                    if !is_primary
+                        || !assign_specular_texture(&mut material_info, packed.channels, &path)
+                    {
+                        layers.push((texture, path));
+                    }
+                }
+                continue;
+            }
+            let path = texture_name_to_path(texture_folder, &texture.name, "", extension);
+            if is_primary {
                 match texture.kind {
                     TextureType::Diffuse => {
-                        material_info.diffuse_texture =
-                            Some(texture_name_to_path(texture_folder, &texture.name));
+                        material_info.diffuse_texture = Some(path);
+                        material_info.diffuse_alpha = alpha;
                     }
                     TextureType::Normal => {
-                        material_info.normal_texture =
-                            Some(texture_name_to_path(texture_folder, &texture.name));
+                        material_info.normal_texture = Some(path);
+                    }
+                    TextureType::Emission => {
+                        material_info.emissive_texture = Some(path);
+                    }
+                    TextureType::Occlusion => {
+                        material_info.occlusion_texture = Some(path);
                     }
-                    TextureType::Specular => {
-                        material_info.occlusion_roughness_metal_specular_texture =
-                            Some(texture_name_to_path(texture_folder, &texture.name));
+                    TextureType::Gloss => {
+                        material_info.roughness_texture = Some(path);
                     }
-                    _ => {}
+                    _ => layers.push((texture, path)),
                 }
+            } else {
+                layers.push((texture, path));
+            }
+        }
+
+        // the composed texture only exists if it was enabled and the composition succeeded
+        if let Some(sources) = OrmSources::of_material(material) {
+            if let Some(info) = texture_infos.get(&sources.name()) {
+                material_info.orm_texture = Some(format!(
+                    "{}/{}",
+                    texture_folder,
+                    sources.path(info.file_format).to_string_lossy()
+                ));
             }
         }
+
+        material_info.extras = Some(material_extras(material, &layers));
         material_information_converted.push(material_info);
     }
     material_information_converted
 }
 
-/// Uses a texture name (without any file extension) and returns a path with added png file extension as string.
-fn texture_name_to_path(texture_folder: &str, texture_name: &str) -> String {
+/// Assigns a texture of a spec-map to the glTF slots that read its channels, i.e.
+/// `metallicRoughnessTexture` (G: roughness, B: metalness), `occlusionTexture` (R: occlusion)
+/// and the `specularTexture` of KHR_materials_specular (A: specular).
+/// Returns false if the channels do not fit any slot, e.g. grayscale roughness.
+fn assign_specular_texture(material: &mut Material, channels: &[Channel], path: &str) -> bool {
+    let mut assigned = false;
+    if channels.get(1) == Some(&Channel::Roughness) && channels.get(2) == Some(&Channel::Metalness)
+    {
+        material.metal_roughness_texture = Some(path.to_string());
+        assigned = true;
+    }
+    if channels.first() == Some(&Channel::Occlusion) {
+        material.specular_occlusion_texture = Some(path.to_string());
+        assigned = true;
+    }
+    if channels.get(3) == Some(&Channel::Specular) {
+        material.specular_texture = Some(path.to_string());
+        assigned = true;
+    }
+    assigned
+}
+
+/// The version of the JSON structure created by `material_extras`.
+/// Must be increased whenever the structure changes in an incompatible way.
+const MATERIAL_EXTRAS_VERSION: u32 = 1;
+
+/// Creates the application specific data (i.e. glTF `extras`) for a material.
+/// It holds all raw and resolved values of the material from the d3dmesh file.
+/// All hashes are written as 16 digit hexadecimal strings, since JSON numbers can not represent
+/// 64-bit integers reliably. See the Readme for a description of the structure.
+/// The layers are textures of the material, which are converted but not part of the glTF material.
+fn material_extras(
+    material: &d3dmesh::materials::Material,
+    layers: &[(&d3dmesh::textures::Texture, String)],
+) -> Value {
+    let parameters: Vec<Value> = material
+        .parameters
+        .values()
+        .map(|parameter| {
+            let value = match parameter.value {
+                ParameterValue::Float(value) => json!(value),
+                ParameterValue::Vector2(value) => json!(value),
+                ParameterValue::Vector3(value) => json!(value),
+                ParameterValue::Vector4(value) | ParameterValue::Color(value) => json!(value),
+                ParameterValue::Bool(value) => json!(value),
+                ParameterValue::Hash(value) => json!(hash_to_string(value)),
+                ParameterValue::HashPair(first, second) => {
+                    json!([hash_to_string(first), hash_to_string(second)])
+                }
+            };
+            let mut entry = json!({
+                "hash": hash_to_string(parameter.hash),
+                "name": parameter.name,
+                "type": parameter.value.type_name(),
+                "value": value,
+            });
+            if !parameter.value_names.is_empty() {
+                entry["valueNames"] = json!(parameter.value_names);
+            }
+            entry
+        })
+        .collect();
+
+    let textures: Vec<Value> = material
+        .textures
+        .iter()
+        .map(|texture| {
+            json!({
+                "slot": format!("{:?}", texture.kind),
+                "map": format!("{:?}", texture.map),
+                "typeHash": hash_to_string(texture.type_hash),
+                "nameHash": hash_to_string(texture.name_hash),
+                "name": if texture.name.is_empty() { None } else { Some(&texture.name) },
+            })
+        })
+        .collect();
+
+    json!({
+        "telltale": {
+            "version": MATERIAL_EXTRAS_VERSION,
+            "materialId": hash_to_string(material.material_id),
+            "unknownHash": hash_to_string(material.unknown_hash),
+            "unknownHashes": material
+                .unknown_hashes
+                .iter()
+                .map(|hash| hash_to_string(*hash))
+                .collect::<Vec<_>>(),
+            "parameters": parameters,
+            "textures": textures,
+            "layers": layers
+                .iter()
+                .map(|(texture, path)| {
+                    json!({
+                        "slot": format!("{:?}", texture.kind),
+                        "map": format!("{:?}", texture.map),
+                        "uri": path,
+                    })
+                })
+                .collect::<Vec<_>>(),
+            "uvScrollSpeed": material.uv_scroll_speed(),
+            "specularPower": material.specular_power(),
+        }
+    })
+}
+
+/// Formats a CRC64 hash as 16 digit hexadecimal string.
+fn hash_to_string(hash: u64) -> String {
+    format!("{:016x}", hash)
+}
+
+/// Uses a texture name (without any file extension) and returns a path with added suffix
+/// (see `image_conversion::with_suffix`) and file extension as string.
+fn texture_name_to_path(
+    texture_folder: &str,
+    texture_name: &str,
+    suffix: &str,
+    extension: &str,
+) -> String {
     // note: texture_path in glTF needs to be a URI. I.e. a/b is good a\b is bad
-    let texture_with_extension = Path::new(texture_name)
-        .with_extension("png")
-        .to_str()
-        .expect(&format!("invalid texture path {:?}", texture_name))
-        .to_string();
+    let texture_with_extension =
+        image_conversion::with_suffix(&Path::new(texture_name).with_extension(extension), suffix)
+            .to_str()
+            .unwrap_or_else(|| panic!("invalid texture path {:?}", texture_name))
+            .to_string();
     format!("{}/{}", texture_folder, texture_with_extension)
 }
 
@@ -1,11 +1,17 @@
-use std::{collections::HashMap, io::Write, path::Path};
+use std::{
+    collections::{BTreeSet, HashMap},
+    ffi::OsStr,
+    io::Write,
+    path::Path,
+};
 
 use anyhow::{anyhow, Context, Result};
 use byteorder::WriteBytesExt;
+use serde_json::{json, Map, Value};
 
-use crate::{image_conversion, skeleton::Skeleton};
+use crate::{image_conversion::alpha::AlphaUsage, skeleton::Skeleton};
 
-use super::{writer::WriteTo, Material, WriterWithCounter};
+use super::{writer::WriteTo, Material, TextureTransform, WriterWithCounter};
 
 /// Stores all relevant information for a rigged object.
 /// This includes a skeleton (i.e. a number of joints (aka bones)) and one or more meshes
@@ -22,6 +28,31 @@ pub struct RiggedObject<W: Write> {
     images: Vec<gltf_json::Image>,
     textures: Vec<gltf_json::Texture>,
     skins: Vec<gltf_json::Skin>,
+    material_patches: HashMap<u32, MaterialPatch>,
+    /// Extensions of textures by texture index, which reference images in formats that are only
+    /// allowed via an extension (e.g. DDS, WebP or KTX2).
+    texture_patches: HashMap<u32, TexturePatch>,
+    extensions_used: BTreeSet<String>,
+    extensions_required: BTreeSet<String>,
+    /// Maps the path of an image to the index of its texture, so that each image is only added once.
+    texture_indices: HashMap<String, u32>,
+}
+
+/// Additional JSON data of a material, that can not be expressed via gltf_json directly.
+/// This is the case for extensions that are not (yet) supported by the gltf_json crate.
+/// The data is merged into the serialized material when writing the glTF JSON data.
+#[derive(Debug, Default)]
+struct MaterialPatch {
+    extensions: Map<String, Value>,
+    texture_transform: Option<TextureTransform>,
+}
+
+/// Extensions of a texture that are merged into the serialized texture (see `apply_texture_patches`).
+#[derive(Debug)]
+struct TexturePatch {
+    extensions: Map<String, Value>,
+    /// whether the `source` of the texture is kept as fallback for importers without the extension
+    keep_source: bool,
 }
 
 /// Holds references to the base data for one mesh.
@@ -71,6 +102,11 @@ where
             images: Vec::new(),
             textures: Vec::new(),
             skins: Vec::new(),
+            material_patches: HashMap::new(),
+            texture_patches: HashMap::new(),
+            extensions_used: BTreeSet::new(),
+            extensions_required: BTreeSet::new(),
+            texture_indices: HashMap::new(),
         }
     }
 
@@ -284,38 +320,66 @@ where
 
     /// Adds a list of materials and returns the reference data to theses materials so that they can be used in
     /// subsequent calls to add_mesh.
-    pub fn add_materials(
-        &mut self,
-        materials: &[Material],
-        output_folder: &str,
-    ) -> MaterialReference {
+    pub fn add_materials(&mut self, materials: &[Material]) -> Result<MaterialReference> {
         let mut accessor_indices = Vec::new();
         for material in materials {
             let mut gltf_material = gltf_json::Material::default();
 
+            let material_index = self.materials.len() as u32;
+            let mut patch = MaterialPatch::default();
+
             // set AlphaMode based on the diffuse textures alpha channel
             gltf_material.alpha_mode = gltf_json::validation::Checked::Valid({
-                let mut alpha_mode = gltf_json::material::AlphaMode::Opaque; // opaque as default
-
-                // FIXME: using a path to access the texture can lead to race-conditions,
-                // where another thread is writing the texture while this one tries to read it.
-                // See the panics, when using `unwrap` instead of `unwrap_or` below.
-                if let Some(path) = &material.diffuse_texture {
-                    // use output-path + texture-path for opening the file
-                    let path = Path::new(output_folder).join(path);
-                    if image_conversion::texture_has_alpha_information(path)
-                        // use false as default, i.e. opaque if nothing can be determined
-                        .unwrap_or(false)
-                    {
-                        alpha_mode = gltf_json::material::AlphaMode::Blend
+                let alpha = if material.diffuse_texture.is_some() {
+                    material.diffuse_alpha
+                } else {
+                    AlphaUsage::Opaque
+                };
+                // an alpha test threshold of the material overrides the analysis of the texture
+                let alpha = match (alpha, material.alpha_cutoff) {
+                    (AlphaUsage::Opaque, _) => AlphaUsage::Opaque,
+                    (_, Some(cutoff)) => AlphaUsage::Cutout { cutoff },
+                    (alpha, None) => alpha,
+                };
+                match alpha {
+                    AlphaUsage::Opaque => gltf_json::material::AlphaMode::Opaque,
+                    AlphaUsage::Cutout { cutoff } => {
+                        gltf_material.alpha_cutoff = Some(gltf_json::material::AlphaCutoff(cutoff));
+                        gltf_json::material::AlphaMode::Mask
                     }
+                    AlphaUsage::Translucent => gltf_json::material::AlphaMode::Blend,
                 }
-                alpha_mode
             });
 
-            // Use the name of the diffuse texture as the material name (without the .png at the end)
+            if let Some(base_color_factor) = material.base_color_factor {
+                gltf_material.pbr_metallic_roughness.base_color_factor =
+                    gltf_json::material::PbrBaseColorFactor(base_color_factor);
+            }
+
+            if let Some(emissive_factor) = material.emissive_factor {
+                gltf_material.emissive_factor =
+                    gltf_json::material::EmissiveFactor(emissive_factor);
+                if let Some(emissive_strength) = material.emissive_strength {
+                    if emissive_strength != 1.0 {
+                        self.add_extension(
+                            &mut patch,
+                            "KHR_materials_emissive_strength",
+                            json!({ "emissiveStrength": emissive_strength }),
+                        );
+                    }
+                }
+            }
+
+            if material.texture_transform.is_some() {
+                patch.texture_transform = material.texture_transform;
+                self.extensions_used
+                    .insert("KHR_texture_transform".to_string());
+            }
+
             gltf_material.name = if let Some(diffuse_texture) = &material.diffuse_texture {
-                Some(diffuse_texture[..diffuse_texture.len() - 4].to_string())
+                Path::new(diffuse_texture)
+                    .file_stem()
+                    .map(|name| name.to_string_lossy().to_string())
             } else {
                 self.root_object_name.clone()
             };
@@ -327,19 +391,75 @@ where
             if let Some(normal_texture) = material.normal_texture.clone() {
                 gltf_material.normal_texture = Some(self.set_normal_texture(&normal_texture, 0));
             }
-            if let Some(occlusion_roughness_metal_specular_texture) =
-                material.occlusion_roughness_metal_specular_texture.clone()
-            {
+            // the textures of the spec-map depend on the profile (see `assign_specular_texture`)
+            if let Some(orm_texture) = material.orm_texture.clone() {
+                gltf_material
+                    .pbr_metallic_roughness
+                    .metallic_roughness_texture = Some(self.set_general_texture(&orm_texture, 0));
+            } else if let Some(metal_roughness_texture) = material.metal_roughness_texture.clone() {
+                gltf_material
+                    .pbr_metallic_roughness
+                    .metallic_roughness_texture =
+                    Some(self.set_general_texture(&metal_roughness_texture, 0));
+            } else if let Some(roughness_texture) = material.roughness_texture.clone() {
+                // a gloss map is only used if there is no spec-map, which already holds the roughness
                 gltf_material
                     .pbr_metallic_roughness
                     .metallic_roughness_texture =
-                    Some(self.set_general_texture(&occlusion_roughness_metal_specular_texture, 0));
-                // TODO KHR_materials_specular or KHR_materials_pbrSpecularGlossiness extension
+                    Some(self.set_general_texture(&roughness_texture, 0));
             }
+            // a composed or standalone occlusion texture takes precedence over the one of the spec-map
+            if let Some(occlusion_texture) = material
+                .orm_texture
+                .clone()
+                .or_else(|| material.occlusion_texture.clone())
+                .or_else(|| material.specular_occlusion_texture.clone())
+            {
+                gltf_material.occlusion_texture =
+                    Some(self.set_occlusion_texture(&occlusion_texture, 0));
+            }
+            if let Some(specular_texture) = material.specular_texture.clone() {
+                let specular_texture = self.set_general_texture(&specular_texture, 0);
+                self.add_extension(
+                    &mut patch,
+                    "KHR_materials_specular",
+                    json!({
+                        "specularTexture": {
+                            "index": specular_texture.index.value(),
+                            "texCoord": specular_texture.tex_coord,
+                        },
+                    }),
+                );
+            }
+            if let Some(emissive_texture) = material.emissive_texture.clone() {
+                gltf_material.emissive_texture =
+                    Some(self.set_general_texture(&emissive_texture, 0));
+                // the emissive factor defaults to black, i.e. the texture would be ignored
+                if material.emissive_factor.is_none() {
+                    gltf_material.emissive_factor =
+                        gltf_json::material::EmissiveFactor([1.0, 1.0, 1.0]);
+                }
+            }
+            if let Some(extras) = &material.extras {
+                gltf_material.extras = Some(
+                    serde_json::value::to_raw_value(extras)
+                        .context("could not serialize material extras")?,
+                );
+            }
+
             self.materials.push(gltf_material);
-            accessor_indices.push((self.materials.len() - 1) as u32);
+            accessor_indices.push(material_index);
+            if !patch.extensions.is_empty() || patch.texture_transform.is_some() {
+                self.material_patches.insert(material_index, patch);
+            }
         }
-        MaterialReference { accessor_indices }
+        Ok(MaterialReference { accessor_indices })
+    }
+
+    /// Adds the extension to the material patch and marks the extension as used.
+    fn add_extension(&mut self, patch: &mut MaterialPatch, name: &str, value: Value) {
+        patch.extensions.insert(name.to_string(), value);
+        self.extensions_used.insert(name.to_string());
     }
 
     /// Adds a skeleton by adding a skin and the joints with their respective hierarchy.
@@ -519,25 +639,85 @@ where
         }
     }
 
+    /// Sets the given path as the occlusion texture. Only the red channel is used for occlusion.
+    fn set_occlusion_texture(
+        &mut self,
+        path: &str,
+        tex_coord: u32,
+    ) -> gltf_json::material::OcclusionTexture {
+        let index = self.add_image_and_texture(path);
+        gltf_json::material::OcclusionTexture {
+            index: gltf_json::Index::new(index),
+            strength: gltf_json::material::StrengthFactor(1.0),
+            tex_coord,
+            extensions: None,
+            extras: gltf_json::Extras::default(),
+        }
+    }
+
     /// Adds the path as image and texture, returning the texture index.
+    /// Images that were already added are re-used.
+    /// Images in formats that glTF does not support natively (see `image_format`) are
+    /// referenced via their extension, which is required in this case, since there is no PNG fallback.
+    /// KTX2 images are the exception: the PNG next to them stays the `source` of the texture,
+    /// so `KHR_texture_basisu` is only used and not required.
     fn add_image_and_texture(&mut self, path: &str) -> u32 {
-        self.images.push(gltf_json::Image {
-            buffer_view: None,
-            mime_type: None,
+        if let Some(index) = self.texture_indices.get(path) {
+            return *index;
+        }
+        let (mime_type, format_extension) = image_format(path);
+        let fallback = match format_extension {
+            Some("KHR_texture_basisu") => Some(
+                Path::new(path)
+                    .with_extension("png")
+                    .to_string_lossy()
+                    .to_string(),
+            ),
+            _ => None,
+        };
+        let image_index = self.add_image(path, mime_type);
+        let source_index = match &fallback {
+            Some(fallback) => self.add_image(fallback, image_format(fallback).0),
+            None => image_index,
+        };
+        self.textures.push(gltf_json::Texture {
             name: None,
-            uri: Some(path.to_string()),
+            sampler: None,
+            source: gltf_json::Index::new(source_index),
             extensions: None,
             extras: gltf_json::Extras::default(),
         });
-        let image_index = (self.images.len() - 1) as u32;
-        self.textures.push(gltf_json::Texture {
+        let texture_index = (self.textures.len() - 1) as u32;
+        if let Some(extension) = format_extension {
+            let mut extensions = Map::new();
+            extensions.insert(extension.to_string(), json!({ "source": image_index }));
+            self.texture_patches.insert(
+                texture_index,
+                TexturePatch {
+                    extensions,
+                    keep_source: fallback.is_some(),
+                },
+            );
+            self.extensions_used.insert(extension.to_string());
+            if fallback.is_none() {
+                self.extensions_required.insert(extension.to_string());
+            }
+        }
+        self.texture_indices.insert(path.to_string(), texture_index);
+        texture_index
+    }
+
+    /// Adds the path as image, returning the image index.
+    fn add_image(&mut self, path: &str, mime_type: Option<&str>) -> u32 {
+        self.images.push(gltf_json::Image {
+            buffer_view: None,
+            mime_type: mime_type.map(|mime_type| gltf_json::image::MimeType(mime_type.to_string())),
             name: None,
-            sampler: None,
-            source: gltf_json::Index::new(image_index),
+            uri: Some(path.to_string()),
             extensions: None,
             extras: gltf_json::Extras::default(),
         });
-        (self.textures.len() - 1) as u32
+        (self.images.len() - 1) as u32
     }
 
     /// Creates the glTF 2.0 JSON data and writes it to dst.
@@ -562,8 +742,8 @@ where
             scene: None,
             extensions: None,
             extras: gltf_json::Extras::default(),
-            extensions_used: Vec::new(),
-            extensions_required: Vec::new(),
+            extensions_used: self.extensions_used.into_iter().collect(),
+            extensions_required: self.extensions_required.into_iter().collect(),
             cameras: Vec::new(),
             images: self.images,
             materials: self.materials,
@@ -580,8 +760,101 @@ where
             textures: self.textures,
         };
 
-        let json = gltf_json::serialize::to_string_pretty(&gltf)?;
+        let mut json = gltf_json::serialize::to_value(&gltf)?;
+        apply_material_patches(&mut json, &self.material_patches)?;
+        apply_texture_patches(&mut json, &self.texture_patches)?;
+
+        let json = gltf_json::serialize::to_string_pretty(&json)?;
         dst.write_all(json.as_bytes())
             .context("could not write glTF JSON data")
     }
 }
+
+/// Merges the material patches into the serialized glTF JSON data.
+fn apply_material_patches(json: &mut Value, patches: &HashMap<u32, MaterialPatch>) -> Result<()> {
+    for (index, patch) in patches {
+        let material = json
+            .pointer_mut(&format!("/materials/{}", index))
+            .ok_or(anyhow!(
+                "could not find material {} in glTF JSON data",
+                index
+            ))?;
+
+        if let Value::Object(material) = material {
+            for (name, value) in &patch.extensions {
+                extensions_of(material).insert(name.clone(), value.clone());
+            }
+        }
+
+        if let Some(transform) = patch.texture_transform {
+            let transform = json!({
+                "offset": transform.offset,
+                "scale": transform.scale,
+            });
+            // the transform applies to every texture of the material
+            let texture_infos = [
+                "/pbrMetallicRoughness/baseColorTexture",
+                "/pbrMetallicRoughness/metallicRoughnessTexture",
+                "/normalTexture",
+                "/occlusionTexture",
+                "/emissiveTexture",
+                "/extensions/KHR_materials_specular/specularTexture",
+            ];
+            for pointer in &texture_infos {
+                if let Some(Value::Object(texture_info)) = material.pointer_mut(pointer) {
+                    extensions_of(texture_info)
+                        .insert("KHR_texture_transform".to_string(), transform.clone());
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the MIME type of the image and the glTF extension that is needed to reference it, if
+/// glTF does not support the format natively. The format is determined by the file extension of the path.
+fn image_format(path: &str) -> (Option<&'static str>, Option<&'static str>) {
+    match Path::new(path).extension().and_then(OsStr::to_str) {
+        Some("png") => (Some("image/png"), None),
+        Some("jpg") | Some("jpeg") => (Some("image/jpeg"), None),
+        Some("webp") => (Some("image/webp"), Some("EXT_texture_webp")),
+        Some("dds") => (Some("image/vnd-ms.dds"), Some("MSFT_texture_dds")),
+        Some("ktx2") => (Some("image/ktx2"), Some("KHR_texture_basisu")),
+        _ => (None, None),
+    }
+}
+
+/// Merges the texture patches into the serialized glTF JSON data.
+/// The `source` of patched textures is removed, as the image is referenced by the extension,
+/// unless it is kept as fallback.
+fn apply_texture_patches(json: &mut Value, patches: &HashMap<u32, TexturePatch>) -> Result<()> {
+    for (index, patch) in patches {
+        let texture = json
+            .pointer_mut(&format!("/textures/{}", index))
+            .ok_or(anyhow!(
+                "could not find texture {} in glTF JSON data",
+                index
+            ))?;
+
+        if let Value::Object(texture) = texture {
+            if !patch.keep_source {
+                texture.remove("source");
+            }
+            for (name, value) in &patch.extensions {
+                extensions_of(texture).insert(name.clone(), value.clone());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the "extensions" object of a glTF JSON object, creating it if necessary.
+fn extensions_of(object: &mut Map<String, Value>) -> &mut Map<String, Value> {
+    let extensions = object
+        .entry("extensions")
+        .or_insert_with(|| Value::Object(Map::new()));
+    if !extensions.is_object() {
+        *extensions = Value::Object(Map::new());
+    }
+    extensions.as_object_mut().unwrap()
+}
@@ -0,0 +1,102 @@
+//! Analysis of the alpha channel of textures, e.g. to choose the alpha mode of materials.
+
+use image::DynamicImage;
+
+/// Alpha values below this or above its complement are treated as fully transparent or opaque,
+/// since block compression and mip-mapping rarely keep the exact values.
+const CUTOUT_TOLERANCE: usize = 16;
+/// The maximal share of intermediate alpha values (in relation to all pixels that are not
+/// (almost) opaque) of a cutout, i.e. the anti-aliased edges of hair cards or leaves.
+const MAX_CUTOUT_INTERMEDIATE_SHARE: f64 = 0.25;
+
+/// How the alpha channel of a texture is used.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlphaUsage {
+    /// There is no alpha channel or all values are (almost) `0xFF`.
+    Opaque,
+    /// The alpha values are (mostly) either fully transparent or fully opaque, e.g. hair cards or
+    /// foliage. Pixels with an alpha value below `cutoff` (in the range of 0.0 to 1.0) are discarded.
+    Cutout { cutoff: f32 },
+    /// Many alpha values lie in between, e.g. glass or smoke, which requires blending.
+    Translucent,
+}
+
+/// Classifies the alpha channel of the image based on the distribution of its values.
+pub fn analyze_alpha(image: &DynamicImage) -> AlphaUsage {
+    let histogram = match alpha_histogram(image) {
+        Some(histogram) => histogram,
+        None => return AlphaUsage::Opaque,
+    };
+    // values close to opaque are caused by compression and would be discarded by any cutoff
+    let transparent_or_partial: u64 = histogram[..256 - CUTOUT_TOLERANCE].iter().sum();
+    if transparent_or_partial == 0 {
+        return AlphaUsage::Opaque;
+    }
+
+    let intermediate: u64 = histogram[CUTOUT_TOLERANCE..256 - CUTOUT_TOLERANCE]
+        .iter()
+        .sum();
+    if intermediate as f64 <= MAX_CUTOUT_INTERMEDIATE_SHARE * transparent_or_partial as f64 {
+        AlphaUsage::Cutout {
+            cutoff: otsu_cutoff(&histogram) / 255.0,
+        }
+    } else {
+        AlphaUsage::Translucent
+    }
+}
+
+/// Counts the 8-bit alpha values of the image. Returns `None` if the image has no alpha channel.
+fn alpha_histogram(image: &DynamicImage) -> Option<[u64; 256]> {
+    if !image.color().has_alpha() {
+        return None;
+    }
+    let mut histogram = [0; 256];
+    match image {
+        DynamicImage::ImageRgba8(rgba) => {
+            for pixel in rgba.pixels() {
+                histogram[pixel[3] as usize] += 1;
+            }
+        }
+        other => {
+            for pixel in other.to_rgba8().pixels() {
+                histogram[pixel[3] as usize] += 1;
+            }
+        }
+    }
+    Some(histogram)
+}
+
+/// Separates the histogram into two classes with maximal between-class variance (Otsu's method)
+/// and returns the value in the middle of the means of both classes.
+fn otsu_cutoff(histogram: &[u64; 256]) -> f32 {
+    let total: f64 = histogram.iter().sum::<u64>() as f64;
+    let sum_total: f64 = histogram
+        .iter()
+        .enumerate()
+        .map(|(value, count)| value as f64 * *count as f64)
+        .sum();
+
+    let mut weight_lower = 0.0;
+    let mut sum_lower = 0.0;
+    let mut best_variance = -1.0;
+    let mut best_cutoff = 127.5;
+    for (value, count) in histogram.iter().enumerate() {
+        weight_lower += *count as f64;
+        sum_lower += value as f64 * *count as f64;
+        let weight_upper = total - weight_lower;
+        if weight_lower == 0.0 {
+            continue;
+        }
+        if weight_upper == 0.0 {
+            break;
+        }
+        let mean_lower = sum_lower / weight_lower;
+        let mean_upper = (sum_total - sum_lower) / weight_upper;
+        let variance = weight_lower * weight_upper * (mean_lower - mean_upper).powi(2);
+        if variance > best_variance {
+            best_variance = variance;
+            best_cutoff = (mean_lower + mean_upper) / 2.0;
+        }
+    }
+    best_cutoff as f32
+}
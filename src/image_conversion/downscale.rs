@@ -0,0 +1,199 @@
+//! Reduction of textures to a maximum resolution, e.g. for viewers on mobile devices.
+
+use anyhow::{anyhow, Result};
+use image::{
+    imageops::{self, FilterType},
+    ColorType, DynamicImage, GenericImageView, ImageBuffer, Rgb, RgbImage, Rgba,
+};
+
+use super::{linear_to_srgb, resolved_color_space, srgb_to_linear};
+use crate::d3dtx::{self, ColorSpace, TextureLayout};
+
+/// How textures that exceed the maximum size are reduced.
+#[derive(Debug, Clone, Copy)]
+pub enum Downscaling {
+    /// The biggest mip-map level that fits is used, which is cheap and keeps the original data.
+    /// Textures without such a level are resampled from their smallest level with `FilterType::Triangle`.
+    Mip,
+    /// The biggest level is resampled with the given filter.
+    Resample(FilterType),
+}
+
+/// The maximum resolution of the converted textures.
+#[derive(Debug, Clone, Copy)]
+pub struct SizeLimit {
+    /// the maximum width and height in pixels
+    pub max_size: u32,
+    pub downscaling: Downscaling,
+}
+
+/// Parses the name of a downscaling method, i.e. `mip` or the name of a resampling filter
+/// (`nearest`, `triangle`, `catmull-rom`, `gaussian` or `lanczos3`).
+pub fn parse_downscaling(value: &str) -> Result<Downscaling> {
+    match value.to_ascii_lowercase().as_str() {
+        "mip" => Ok(Downscaling::Mip),
+        "nearest" => Ok(Downscaling::Resample(FilterType::Nearest)),
+        "triangle" => Ok(Downscaling::Resample(FilterType::Triangle)),
+        "catmull-rom" => Ok(Downscaling::Resample(FilterType::CatmullRom)),
+        "gaussian" => Ok(Downscaling::Resample(FilterType::Gaussian)),
+        "lanczos3" => Ok(Downscaling::Resample(FilterType::Lanczos3)),
+        _ => Err(anyhow!("unknown downscaling method {}", value)),
+    }
+}
+
+/// Reduces the texture so that neither side of its biggest level exceeds the limit.
+///
+/// All mip-map levels above the limit are removed (see `d3dtx::Texture::drop_mips`), so that
+/// copies of the original data (DDS and KTX2) as well as exported mip-maps and surfaces fit, too.
+/// With `Downscaling::Resample`, `image` of 2D textures is resampled from the original biggest
+/// level instead, i.e. it is not one of the remaining mip-maps unless the limit is a power of two.
+/// Returns whether the texture was reduced.
+pub fn limit_size(
+    mut texture: d3dtx::Texture,
+    limit: &SizeLimit,
+) -> Result<(d3dtx::Texture, bool)> {
+    let (width, height) = texture.image.dimensions();
+    if fits(width, height, limit.max_size) {
+        return Ok((texture, false));
+    }
+    let level = (0..texture.mip_count)
+        .find(|level| fits(width >> level, height >> level, limit.max_size))
+        .unwrap_or_else(|| texture.mip_count.saturating_sub(1));
+    let is_2d = matches!(
+        texture.layout,
+        TextureLayout::Texture2D | TextureLayout::Unknown(_)
+    );
+    let original = match limit.downscaling {
+        Downscaling::Resample(filter) if is_2d => Some((
+            std::mem::replace(&mut texture.image, DynamicImage::new_luma8(0, 0)),
+            texture.hdr_image.take(),
+            filter,
+        )),
+        _ => None,
+    };
+    let mut texture = texture.drop_mips(level)?;
+    let color_space = resolved_color_space(&texture);
+
+    let (image, hdr_image, filter) = match original {
+        Some(original) => original,
+        None => {
+            if fits(
+                texture.image.width(),
+                texture.image.height(),
+                limit.max_size,
+            ) {
+                log::debug!(
+                    "texture {}: using mip-map level {} ({}x{}) instead of {}x{}",
+                    texture.name,
+                    level,
+                    texture.image.width(),
+                    texture.image.height(),
+                    width,
+                    height
+                );
+                return Ok((texture, true));
+            }
+            // the mip-map chain does not reach the limit
+            let image = std::mem::replace(&mut texture.image, DynamicImage::new_luma8(0, 0));
+            (image, texture.hdr_image.take(), FilterType::Triangle)
+        }
+    };
+    let (new_width, new_height) = fitting_size(image.width(), image.height(), limit.max_size);
+    texture.image = resample(&image, new_width, new_height, filter, color_space);
+    texture.hdr_image =
+        hdr_image.map(|hdr_image| imageops::resize(&hdr_image, new_width, new_height, filter));
+    log::debug!(
+        "texture {}: resampled from {}x{} to {}x{} with {:?}",
+        texture.name,
+        width,
+        height,
+        new_width,
+        new_height,
+        filter
+    );
+    Ok((texture, true))
+}
+
+fn fits(width: u32, height: u32, max_size: u32) -> bool {
+    width <= max_size && height <= max_size
+}
+
+/// Returns the biggest size that fits into the limit and keeps the aspect ratio.
+fn fitting_size(width: u32, height: u32, max_size: u32) -> (u32, u32) {
+    let scale = |side: u32, longest: u32| {
+        ((side as u64 * max_size as u64 + longest as u64 / 2) / longest as u64).max(1) as u32
+    };
+    if width >= height {
+        (max_size, scale(height, width))
+    } else {
+        (scale(width, height), max_size)
+    }
+}
+
+/// Resamples the image to the given size and keeps its color type.
+/// sRGB images are resampled in linear space, so that dark and bright areas are averaged correctly.
+fn resample(
+    image: &DynamicImage,
+    width: u32,
+    height: u32,
+    filter: FilterType,
+    color_space: ColorSpace,
+) -> DynamicImage {
+    if color_space == ColorSpace::Linear {
+        return image.resize_exact(width, height, filter);
+    }
+    let to_linear = |value: u16| srgb_to_linear(f32::from(value) / 65535.0);
+    let rgba = image.to_rgba16();
+    let linear: ImageBuffer<Rgba<f32>, Vec<f32>> =
+        ImageBuffer::from_fn(rgba.width(), rgba.height(), |x, y| {
+            let pixel = rgba.get_pixel(x, y);
+            Rgba([
+                to_linear(pixel[0]),
+                to_linear(pixel[1]),
+                to_linear(pixel[2]),
+                f32::from(pixel[3]) / 65535.0,
+            ])
+        });
+    let resized = imageops::resize(&linear, width, height, filter);
+    let to_srgb = |value: f32| (linear_to_srgb(value.clamp(0.0, 1.0)) * 65535.0).round() as u16;
+    let srgb = ImageBuffer::from_fn(width, height, |x, y| {
+        let pixel = resized.get_pixel(x, y);
+        Rgba([
+            to_srgb(pixel[0]),
+            to_srgb(pixel[1]),
+            to_srgb(pixel[2]),
+            (pixel[3].clamp(0.0, 1.0) * 65535.0).round() as u16,
+        ])
+    });
+    with_color_type(DynamicImage::ImageRgba16(srgb), image.color())
+}
+
+/// Converts the image to the given color type (BGR images become RGB).
+fn with_color_type(image: DynamicImage, color: ColorType) -> DynamicImage {
+    match color {
+        ColorType::L8 => DynamicImage::ImageLuma8(image.to_luma8()),
+        ColorType::La8 => DynamicImage::ImageLumaA8(image.to_luma_alpha8()),
+        ColorType::Rgb8 | ColorType::Bgr8 => DynamicImage::ImageRgb8(image.to_rgb8()),
+        ColorType::Rgba8 | ColorType::Bgra8 => DynamicImage::ImageRgba8(image.to_rgba8()),
+        ColorType::L16 => DynamicImage::ImageLuma16(image.to_luma16()),
+        ColorType::La16 => DynamicImage::ImageLumaA16(image.to_luma_alpha16()),
+        ColorType::Rgb16 => DynamicImage::ImageRgb16(image.to_rgb16()),
+        _ => image,
+    }
+}
+
+/// Scales the normals of a converted normal map (see `super::normal_map`) back to unit length,
+/// since resampling averages neighbouring normals, which shortens them.
+pub fn renormalize(normal_map: &mut RgbImage) {
+    for pixel in normal_map.pixels_mut() {
+        let normal = pixel.0.map(|value| f32::from(value) / 255.0 * 2.0 - 1.0);
+        let length = normal.iter().map(|value| value * value).sum::<f32>().sqrt();
+        if length > f32::EPSILON {
+            *pixel = Rgb(normal.map(|value| {
+                ((value / length + 1.0) / 2.0 * 255.0)
+                    .round()
+                    .clamp(0.0, 255.0) as u8
+            }));
+        }
+    }
+}
@@ -0,0 +1,206 @@
+//! Writing of decoded textures as image files in the formats of `TextureFileFormat`.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::{anyhow, Context, Result};
+use image::{
+    codecs::{
+        jpeg::JpegEncoder,
+        png::{CompressionType, FilterType, PngEncoder},
+    },
+    DynamicImage, GenericImageView, ImageEncoder,
+};
+use image_webp::WebPEncoder;
+
+use super::TextureFileFormat;
+
+/// The settings of the image encoders.
+#[derive(Debug, Clone, Copy)]
+pub struct ImageEncoding {
+    /// the compression level of PNG images
+    pub png_compression: CompressionType,
+    /// the filter that is applied to every row of PNG images before compressing them
+    pub png_filter: FilterType,
+    /// the quality of JPEG images from 1 to 100
+    pub jpeg_quality: u8,
+}
+
+/// Parses the name of a PNG compression level, i.e. `fast`, `default`, `best`, `huffman` or `rle`.
+pub fn parse_png_compression(value: &str) -> Result<CompressionType> {
+    match value.to_ascii_lowercase().as_str() {
+        "fast" => Ok(CompressionType::Fast),
+        "default" => Ok(CompressionType::Default),
+        "best" => Ok(CompressionType::Best),
+        "huffman" => Ok(CompressionType::Huffman),
+        "rle" => Ok(CompressionType::Rle),
+        _ => Err(anyhow!("unknown PNG compression level {}", value)),
+    }
+}
+
+/// Parses the name of a PNG filter, i.e. `none`, `sub`, `up`, `avg` or `paeth`.
+pub fn parse_png_filter(value: &str) -> Result<FilterType> {
+    match value.to_ascii_lowercase().as_str() {
+        "none" => Ok(FilterType::NoFilter),
+        "sub" => Ok(FilterType::Sub),
+        "up" => Ok(FilterType::Up),
+        "avg" => Ok(FilterType::Avg),
+        "paeth" => Ok(FilterType::Paeth),
+        _ => Err(anyhow!("unknown PNG filter {}", value)),
+    }
+}
+
+/// Writes the image to the destination in the given format.
+/// WebP images are lossless, JPEG images have no alpha channel and both always have 8 bits
+/// per channel.
+pub fn save_image<Q: AsRef<Path>>(
+    image: &DynamicImage,
+    to: Q,
+    format: TextureFileFormat,
+    encoding: &ImageEncoding,
+) -> Result<()> {
+    let mut out = BufWriter::new(File::create(to).context("could not create image file")?);
+    match format {
+        TextureFileFormat::Png => {
+            let encoder = PngEncoder::new_with_quality(
+                &mut out,
+                encoding.png_compression,
+                encoding.png_filter,
+            );
+            match image {
+                // the PNG encoder does not support BGR(A)
+                DynamicImage::ImageBgr8(_) | DynamicImage::ImageBgra8(_) => {
+                    let rgba = image.to_rgba8();
+                    encoder.write_image(&rgba, rgba.width(), rgba.height(), image::ColorType::Rgba8)
+                }
+                _ => encoder.write_image(
+                    image.as_bytes(),
+                    image.width(),
+                    image.height(),
+                    image.color(),
+                ),
+            }
+            .context("could not encode PNG image")?;
+        }
+        TextureFileFormat::Webp => {
+            let rgba = image.to_rgba8();
+            WebPEncoder::new(&mut out)
+                .encode(
+                    &rgba,
+                    rgba.width(),
+                    rgba.height(),
+                    image_webp::ColorType::Rgba8,
+                )
+                .context("could not encode WebP image")?
+        }
+        TextureFileFormat::Jpeg => {
+            let rgb = image.to_rgb8();
+            JpegEncoder::new_with_quality(&mut out, encoding.jpeg_quality)
+                .encode(&rgb, rgb.width(), rgb.height(), image::ColorType::Rgb8)
+                .context("could not encode JPEG image")?
+        }
+        TextureFileFormat::Dds | TextureFileFormat::Ktx2 => {
+            return Err(anyhow!("{:?} files can not be written from images", format))
+        }
+    }
+    out.flush().context("could not write image file")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Cursor, path::PathBuf};
+
+    use image::{ImageFormat, RgbaImage};
+    use image_webp::WebPDecoder;
+
+    use super::*;
+
+    const ENCODING: ImageEncoding = ImageEncoding {
+        png_compression: CompressionType::Fast,
+        png_filter: FilterType::Sub,
+        jpeg_quality: 90,
+    };
+
+    /// Writes the image with `save_image` and returns the content of the file.
+    fn save(image: &DynamicImage, format: TextureFileFormat, encoding: &ImageEncoding) -> Vec<u8> {
+        let path: PathBuf = std::env::temp_dir().join(format!(
+            "d3dmesh-to-gltf-{}-{:?}.{}",
+            std::process::id(),
+            format,
+            format.extension()
+        ));
+        save_image(image, &path, format, encoding).unwrap();
+        let data = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        data
+    }
+
+    /// An image with an odd size, smooth gradients and some noise.
+    fn test_image() -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(37, 23, |x, y| {
+            let noise = (x * 7919 + y * 104729) % 13;
+            [
+                (x * 6 + noise) as u8,
+                (y * 10) as u8,
+                (x * y) as u8,
+                (255 - x - y) as u8,
+            ]
+            .into()
+        }))
+    }
+
+    #[test]
+    fn webp_is_lossless() {
+        let image = test_image();
+        let data = save(&image, TextureFileFormat::Webp, &ENCODING);
+        let mut decoder = WebPDecoder::new(Cursor::new(&data)).unwrap();
+        assert_eq!(decoder.dimensions(), (37, 23));
+        assert!(decoder.has_alpha());
+        let mut decoded = vec![0; decoder.output_buffer_size().unwrap()];
+        decoder.read_image(&mut decoded).unwrap();
+        assert_eq!(decoded, image.to_rgba8().into_raw());
+    }
+
+    #[test]
+    fn jpeg_respects_quality() {
+        let image = test_image();
+        let rgb = image.to_rgb8();
+        let error = |quality: u8| {
+            let encoding = ImageEncoding {
+                jpeg_quality: quality,
+                ..ENCODING
+            };
+            let data = save(&image, TextureFileFormat::Jpeg, &encoding);
+            let decoded = image::load_from_memory_with_format(&data, ImageFormat::Jpeg)
+                .unwrap()
+                .to_rgb8();
+            assert_eq!(decoded.dimensions(), rgb.dimensions());
+            let sum: f64 = decoded
+                .as_raw()
+                .iter()
+                .zip(rgb.as_raw())
+                .map(|(a, b)| (f64::from(*a) - f64::from(*b)).powi(2))
+                .sum();
+            (sum / rgb.as_raw().len() as f64).sqrt()
+        };
+        let (high, low) = (error(95), error(20));
+        assert!(high < 4.0, "RMSE of {} with quality 95", high);
+        assert!(
+            high < low,
+            "RMSE of {} with quality 95, {} with 20",
+            high,
+            low
+        );
+    }
+
+    #[test]
+    fn png_is_lossless() {
+        let image = test_image();
+        let data = save(&image, TextureFileFormat::Png, &ENCODING);
+        let decoded = image::load_from_memory_with_format(&data, ImageFormat::Png).unwrap();
+        assert_eq!(decoded.to_rgba8(), image.to_rgba8());
+    }
+}
@@ -0,0 +1,217 @@
+//! A fast Fourier transform of arbitrary length, which is used to integrate height maps.
+//!
+//! Lengths that are a power of two use the iterative radix-2 algorithm, all others are reduced
+//! to a power of two via Bluestein's algorithm (chirp z-transform).
+
+use std::{
+    f64::consts::PI,
+    ops::{Add, Mul, Sub},
+};
+
+use rayon::prelude::*;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Complex {
+    pub re: f32,
+    pub im: f32,
+}
+
+impl Complex {
+    pub fn new(re: f32, im: f32) -> Self {
+        Self { re, im }
+    }
+
+    /// Returns `e^(i * angle)`, which is computed with double precision.
+    fn from_angle(angle: f64) -> Self {
+        Self::new(angle.cos() as f32, angle.sin() as f32)
+    }
+
+    pub fn conj(self) -> Self {
+        Self::new(self.re, -self.im)
+    }
+
+    pub fn scale(self, factor: f32) -> Self {
+        Self::new(self.re * factor, self.im * factor)
+    }
+
+    pub fn norm_sqr(self) -> f32 {
+        self.re * self.re + self.im * self.im
+    }
+}
+
+impl Add for Complex {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Self::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        Self::new(
+            self.re * other.re - self.im * other.im,
+            self.re * other.im + self.im * other.re,
+        )
+    }
+}
+
+/// A prepared transform of a fixed length.
+pub struct Fft {
+    len: usize,
+    algorithm: Algorithm,
+}
+
+enum Algorithm {
+    /// `e^(-2πik/len)` for `k < len / 2`
+    Radix2 { twiddles: Vec<Complex> },
+    Bluestein {
+        /// the power of two transform of the convolution
+        inner: Box<Fft>,
+        /// `e^(-πik²/len)` for `k < len`
+        chirp: Vec<Complex>,
+        /// the transformed conjugated chirp, which is the kernel of the convolution
+        kernel: Vec<Complex>,
+    },
+}
+
+impl Fft {
+    pub fn new(len: usize) -> Self {
+        let algorithm = if len.is_power_of_two() {
+            Algorithm::Radix2 {
+                twiddles: (0..len / 2)
+                    .map(|k| Complex::from_angle(-2.0 * PI * k as f64 / len as f64))
+                    .collect(),
+            }
+        } else {
+            let inner = Fft::new((2 * len - 1).next_power_of_two());
+            // k² is reduced modulo 2 * len, since the angle would lose precision otherwise
+            let chirp: Vec<_> = (0..len as u64)
+                .map(|k| {
+                    let k_squared = (k * k) % (2 * len as u64);
+                    Complex::from_angle(-PI * k_squared as f64 / len as f64)
+                })
+                .collect();
+            let mut kernel = vec![Complex::default(); inner.len];
+            kernel[0] = chirp[0].conj();
+            for k in 1..len {
+                kernel[k] = chirp[k].conj();
+                kernel[inner.len - k] = chirp[k].conj();
+            }
+            inner.forward(&mut kernel);
+            Algorithm::Bluestein {
+                inner: Box::new(inner),
+                chirp,
+                kernel,
+            }
+        };
+        Self { len, algorithm }
+    }
+
+    /// Transforms the data in place, i.e. `X_k = Σ x_n e^(-2πikn/len)`.
+    pub fn forward(&self, data: &mut [Complex]) {
+        assert_eq!(data.len(), self.len, "wrong length of FFT input");
+        match &self.algorithm {
+            Algorithm::Radix2 { twiddles } => radix2(data, twiddles),
+            Algorithm::Bluestein {
+                inner,
+                chirp,
+                kernel,
+            } => {
+                let mut buffer = vec![Complex::default(); inner.len];
+                for (value, (x, w)) in buffer.iter_mut().zip(data.iter().zip(chirp)) {
+                    *value = *x * *w;
+                }
+                inner.forward(&mut buffer);
+                for (value, k) in buffer.iter_mut().zip(kernel) {
+                    *value = *value * *k;
+                }
+                inner.inverse(&mut buffer);
+                for (x, (value, w)) in data.iter_mut().zip(buffer.iter().zip(chirp)) {
+                    *x = *value * *w;
+                }
+            }
+        }
+    }
+
+    /// Inverts `forward` in place, including the normalization by `1 / len`.
+    pub fn inverse(&self, data: &mut [Complex]) {
+        data.iter_mut().for_each(|value| *value = value.conj());
+        self.forward(data);
+        let factor = 1.0 / self.len as f32;
+        data.iter_mut()
+            .for_each(|value| *value = value.conj().scale(factor));
+    }
+}
+
+/// The iterative Cooley-Tukey algorithm for lengths that are a power of two.
+fn radix2(data: &mut [Complex], twiddles: &[Complex]) {
+    let len = data.len();
+    if len < 2 {
+        return;
+    }
+    let bits = len.trailing_zeros();
+    for index in 0..len {
+        let reversed = index.reverse_bits() >> (usize::BITS - bits);
+        if index < reversed {
+            data.swap(index, reversed);
+        }
+    }
+    let mut size = 2;
+    while size <= len {
+        let step = len / size;
+        for chunk in data.chunks_exact_mut(size) {
+            let (even, odd) = chunk.split_at_mut(size / 2);
+            for (k, (a, b)) in even.iter_mut().zip(odd.iter_mut()).enumerate() {
+                let t = *b * twiddles[k * step];
+                *b = *a - t;
+                *a = *a + t;
+            }
+        }
+        size *= 2;
+    }
+}
+
+/// Transforms an image with the given width, whose rows are stored after each other, in place.
+/// The rows and columns are transformed in parallel.
+pub fn transform_2d(data: &mut [Complex], width: usize, inverse: bool) {
+    let height = data.len() / width;
+    transform_rows(data, width, inverse);
+    let mut transposed = transpose(data, width, height);
+    transform_rows(&mut transposed, height, inverse);
+    data.copy_from_slice(&transpose(&transposed, height, width));
+}
+
+fn transform_rows(data: &mut [Complex], width: usize, inverse: bool) {
+    let fft = Fft::new(width);
+    data.par_chunks_mut(width).for_each(|row| {
+        if inverse {
+            fft.inverse(row)
+        } else {
+            fft.forward(row)
+        }
+    });
+}
+
+fn transpose(data: &[Complex], width: usize, height: usize) -> Vec<Complex> {
+    let mut transposed = vec![Complex::default(); data.len()];
+    transposed
+        .par_chunks_mut(height)
+        .enumerate()
+        .for_each(|(x, column)| {
+            for (y, value) in column.iter_mut().enumerate() {
+                *value = data[y * width + x];
+            }
+        });
+    transposed
+}
@@ -1,151 +1,311 @@
-use std::{
-    sync::{Arc, Mutex},
-    thread,
+use anyhow::{anyhow, Result};
+use image::{GrayImage, ImageBuffer, Luma, RgbImage};
+use rayon::prelude::*;
+
+use crate::image_conversion::{
+    fft::{self, Complex},
+    sampler,
 };
 
-use image::{GrayImage, ImageBuffer, Luma, RgbImage};
+/// The algorithm that integrates the slopes of a normal map to heights.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HeightAlgorithm {
+    /// Least-squares integration of the slopes over the whole texture (see `integrate_poisson`).
+    Poisson,
+    /// Averages the slopes along rays around every texel (see `integrate_rays`).
+    RayMarching {
+        /// the number of rays per texel
+        rays: u32,
+        /// the length of the rays relative to the longer side of the texture
+        radius: f32,
+    },
+}
 
-use crate::image_conversion::sampler;
+/// The settings of the height map generation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HeightSettings {
+    pub algorithm: HeightAlgorithm,
+    /// scales the heights around the middle gray, 1 uses the full range,
+    /// bigger values exaggerate the relief and clip the highest and lowest areas
+    pub strength: f32,
+    /// whether the texture repeats, i.e. its edges are integrated as neighbours
+    pub tiling: bool,
+}
 
-/// Converts a normal map to a bump map. Inspired by this paper:
-/// https://doi.org/10.1145/2037826.2037839
-pub fn normal_to_height(normal: RgbImage) -> GrayImage {
-    let width = normal.width();
-    let height = normal.height();
-    // Note only x (red) and y (green) are used
-    let mut depth_difference_map_x: ImageBuffer<Luma<f32>, Vec<f32>> =
-        ImageBuffer::new(width, height);
-    let mut depth_difference_map_y: ImageBuffer<Luma<f32>, Vec<f32>> =
-        ImageBuffer::new(width, height);
-    for x in 0..width {
-        for y in 0..height {
-            let normal_x = u8_to_float(normal.get_pixel(x, y)[0]);
-            let normal_y = u8_to_float(normal.get_pixel(x, y)[1]);
-            // transform from [0; 1] to [-1; 1]
-            let normal_x = (normal_x * 2.0) - 1.0;
-            let normal_y = (normal_y * 2.0) - 1.0;
-            // Calculate height (see figure 1 in linked paper)
-            let angle_x = normal_x * (std::f32::consts::PI / 2.0);
-            let angle_y = normal_y * (std::f32::consts::PI / 2.0);
-            let height_difference_x = f32::tan(-1.0 * angle_x);
-            depth_difference_map_x.put_pixel(x, y, [height_difference_x].into());
-            let height_difference_y = f32::tan(-1.0 * angle_y);
-            depth_difference_map_y.put_pixel(x, y, [height_difference_y].into());
-        }
+/// Parses the name of a height map algorithm, i.e. `poisson` or `ray-marching`.
+/// The rays are only used by `ray-marching`.
+pub fn parse_algorithm(value: &str, rays: u32, radius: f32) -> Result<HeightAlgorithm> {
+    match value.to_ascii_lowercase().as_str() {
+        "poisson" => Ok(HeightAlgorithm::Poisson),
+        "ray-marching" => Ok(HeightAlgorithm::RayMarching { rays, radius }),
+        _ => Err(anyhow!("unknown height map algorithm {}", value)),
     }
-    // there are often a couple of outliers in the depth difference map
-    // so clamp to a maximum that is that of the 95 percentile
-    const PERCENTILE: f32 = 0.95;
-    clamp_values_to_percentile(&mut depth_difference_map_x, PERCENTILE);
-    clamp_values_to_percentile(&mut depth_difference_map_y, PERCENTILE);
-    // DEBUG
+}
+
+/// Converts a normal map (see `super::normal_map`) to a height map, whose values are scaled to
+/// the full range around middle gray (see `HeightSettings::strength`).
+pub fn normal_to_height(normal: RgbImage, settings: &HeightSettings) -> GrayImage {
+    let heights = match settings.algorithm {
+        HeightAlgorithm::Poisson => integrate_poisson(&normal, settings.tiling),
+        HeightAlgorithm::RayMarching { rays, radius } => {
+            integrate_rays(&normal, rays, radius, settings.tiling)
+        }
+    };
+    // get lower and upper bounds of values
     let mut lo = f32::INFINITY;
     let mut hi = f32::NEG_INFINITY;
-    for x in 0..width {
-        for y in 0..height {
-            let v = depth_difference_map_x.get_pixel(x, y)[0];
-            lo = f32::min(lo, v);
-            hi = f32::max(hi, v);
-        }
+    for v in heights.pixels().map(|pixel| pixel[0]) {
+        lo = f32::min(lo, v);
+        hi = f32::max(hi, v);
     }
-    let mut output = GrayImage::new(width, height);
-    for x in 0..width {
-        for y in 0..height {
-            let out = f32::abs(depth_difference_map_x.get_pixel(x, y)[0]);
-            let out = (out - lo) / (hi - lo);
-            output.put_pixel(x, y, [float_to_u8(out)].into());
-        }
-    }
-    //return output;
-    // integrate height for one pixel via integrating along rays spread out evenly in 360°
-    // the result of all rays is averaged and denotes the height * -1
-    const NUM_RAYS: u32 = 50;
-    const RAY_LENGTH_PROPORTION_OF_WIDTH: f32 = 0.004;
-    let ray_length_texel =
-        (f32::max(width as f32, height as f32) * RAY_LENGTH_PROPORTION_OF_WIDTH).ceil() as u32;
-    let heights: ImageBuffer<Luma<f32>, Vec<f32>> = ImageBuffer::new(width, height);
-    let heights = Arc::new(Mutex::new(heights));
-    {
-        // use bilinear filtering of the DDM
-        let sampler_ddm_x = Arc::new(sampler::Linear::new(depth_difference_map_x));
-        let sampler_ddm_y = Arc::new(sampler::Linear::new(depth_difference_map_y));
-        let num_threads = num_cpus::get() as u32;
-        let mut handles = Vec::with_capacity(num_threads as usize);
-        for thread_id in 0..num_threads {
-            // clone the atomic references so that each threads has its own reference
-            let heights = heights.clone();
-            let sampler_ddm_x = sampler_ddm_x.clone();
-            let sampler_ddm_y = sampler_ddm_y.clone();
-            // start filtering in separate threads
-            handles.push(thread::spawn(move || {
-                let columns_per_thread = width / num_threads;
-                let start = columns_per_thread * thread_id;
-                let mut end = columns_per_thread * (thread_id + 1);
-                if thread_id == num_threads - 1 {
-                    //last thread has to do the rest
-                    let rest = width % num_threads;
-                    end += rest;
+    let middle = (lo + hi) / 2.0;
+    let range = f32::max(hi - lo, f32::EPSILON);
+    // convert to u8 gray-scale image
+    GrayImage::from_fn(normal.width(), normal.height(), |x, y| {
+        let out = heights.get_pixel(x, y)[0];
+        // scale height into range [0; 1]
+        let out = 0.5 + (out - middle) / range * settings.strength;
+        [float_to_u8(out.clamp(0.0, 1.0))].into()
+    })
+}
+
+/// Integrates the slopes of the normal map by solving the Poisson equation `∇²h = ∇·(p, q)` in
+/// the frequency domain, which is the least-squares solution for the heights `h` whose finite
+/// differences match the slopes `(p, q)` best (Frankot and Chellappa).
+/// The solution is periodic, so non-tiling textures are mirrored at their edges first, which
+/// keeps the edges from influencing each other.
+fn integrate_poisson(normal: &RgbImage, tiling: bool) -> ImageBuffer<Luma<f32>, Vec<f32>> {
+    let width = normal.width() as usize;
+    let height = normal.height() as usize;
+    let (extended_width, extended_height) = if tiling {
+        (width, height)
+    } else {
+        (width * 2, height * 2)
+    };
+    // the slopes are the forward differences of the heights, i.e. h(x + 1) - h(x),
+    // packed as p + iq so that both are transformed at once
+    let mut data = vec![Complex::default(); extended_width * extended_height];
+    for (index, value) in data.iter_mut().enumerate() {
+        let x = index % extended_width;
+        let y = index / extended_width;
+        let (slope_x, slope_y) = if tiling {
+            slopes(normal, x as u32, y as u32)
+        } else {
+            // mirror the heights, i.e. the slopes along the mirrored axis change their sign
+            // and the differences across the mirror axes are zero
+            let slope_x = match mirror_difference(x, width) {
+                Some((source_x, sign)) => {
+                    sign * slopes(normal, source_x as u32, mirror_position(y, height) as u32).0
                 }
-                // filter
-                for x in start..end {
-                    for y in 0..height {
-                        let mut sum = 0.0;
-                        for ray in 0..NUM_RAYS {
-                            // calculate the 2D direction vector of the ray
-                            let angle =
-                                ray as f32 * ((2.0 * std::f32::consts::PI) / (NUM_RAYS as f32));
-                            let dir_x = f32::sin(angle);
-                            let dir_y = f32::cos(angle);
-                            // march the ray through the image with texture-edges clamped
-                            // note that the current texel of the DDM should not be taken into account
-                            let mut ray_sum = 0.0;
-                            for i in 0..ray_length_texel {
-                                let texel_x =
-                                    (x as f32 + (i as f32 * dir_x)).clamp(0.0, width as f32 - 1.0);
-                                let texel_y =
-                                    (y as f32 + (i as f32 * dir_y)).clamp(0.0, height as f32 - 1.0);
-                                let value_x = sampler_ddm_x.get_pixel(texel_x, texel_y);
-                                let value_y = sampler_ddm_y.get_pixel(texel_x, texel_y);
-                                ray_sum += ((dir_x * value_x) + (-1.0 * dir_y * value_y)) / 2.0;
-                            }
-                            sum += ray_sum / (ray_length_texel as f32 * 2.0 + 1.0);
-                        }
-                        sum = sum / NUM_RAYS as f32;
-                        {
-                            heights.lock().unwrap().put_pixel(x, y, [-1.0 * sum].into());
-                        }
-                    }
+                None => 0.0,
+            };
+            let slope_y = match mirror_difference(y, height) {
+                Some((source_y, sign)) => {
+                    sign * slopes(normal, mirror_position(x, width) as u32, source_y as u32).1
                 }
-            }));
+                None => 0.0,
+            };
+            (slope_x, slope_y)
+        };
+        *value = Complex::new(slope_x, slope_y);
+    }
+    fft::transform_2d(&mut data, extended_width, false);
+
+    // the forward difference corresponds to the factor e^(iω) - 1 in the frequency domain
+    let difference = |frequency: usize, len: usize| {
+        let angle = 2.0 * std::f64::consts::PI * frequency as f64 / len as f64;
+        Complex::new(angle.cos() as f32 - 1.0, angle.sin() as f32)
+    };
+    let solve = |x: usize, y: usize, p: Complex, q: Complex| {
+        let difference_x = difference(x, extended_width);
+        let difference_y = difference(y, extended_height);
+        let denominator = difference_x.norm_sqr() + difference_y.norm_sqr();
+        if denominator == 0.0 {
+            // the mean height is arbitrary
+            return Complex::default();
         }
-        for handle in handles {
-            handle.join().unwrap();
+        (difference_x.conj() * p + difference_y.conj() * q).scale(1.0 / denominator)
+    };
+    // separate the transforms of p and q via the symmetry of real signals and solve every pair
+    // of opposite frequencies at once, so that the solution can be stored in place
+    for y in 0..extended_height {
+        for x in 0..extended_width {
+            let opposite_x = (extended_width - x) % extended_width;
+            let opposite_y = (extended_height - y) % extended_height;
+            let index = y * extended_width + x;
+            let opposite = opposite_y * extended_width + opposite_x;
+            if opposite < index {
+                continue;
+            }
+            let z = data[index];
+            let z_opposite = data[opposite];
+            // P(k) = (Z(k) + Z*(-k)) / 2 and Q(k) = (Z(k) - Z*(-k)) / 2i
+            let p = (z + z_opposite.conj()).scale(0.5);
+            let q = (z - z_opposite.conj()) * Complex::new(0.0, -0.5);
+            data[index] = solve(x, y, p, q);
+            if opposite != index {
+                data[opposite] = solve(opposite_x, opposite_y, p.conj(), q.conj());
+            }
         }
     }
-    // unlock mutex, since here are no further threads running
-    let heights = heights.lock().unwrap();
-    // get lower and upper bounds of values
-    let mut lo = f32::INFINITY;
-    let mut hi = f32::NEG_INFINITY;
-    for x in 0..width {
-        for y in 0..height {
-            let v = heights.get_pixel(x, y)[0];
-            lo = f32::min(lo, v);
-            hi = f32::max(hi, v);
+    fft::transform_2d(&mut data, extended_width, true);
+
+    ImageBuffer::from_fn(width as u32, height as u32, |x, y| {
+        [data[y as usize * extended_width + x as usize].re].into()
+    })
+}
+
+/// Maps a coordinate of the texture that is mirrored at its end (with the given size) to the
+/// original texture.
+fn mirror_position(value: usize, size: usize) -> usize {
+    if value < size {
+        value
+    } else {
+        2 * size - 1 - value
+    }
+}
+
+/// Maps the forward difference at a coordinate of the texture that is mirrored at its end
+/// (with the given size) to the original texture. Returns the coordinate and the sign of the
+/// difference, `None` if the difference is zero, i.e. across the mirror axes.
+fn mirror_difference(value: usize, size: usize) -> Option<(usize, f32)> {
+    if value + 1 == size || value + 1 == 2 * size {
+        None
+    } else if value < size {
+        Some((value, 1.0))
+    } else {
+        Some((2 * size - 2 - value, -1.0))
+    }
+}
+
+/// Returns the slopes of the heights in x and y direction (to the right and down) at the texel.
+/// The normal map uses the glTF convention, i.e. green points up.
+fn slopes(normal: &RgbImage, x: u32, y: u32) -> (f32, f32) {
+    let pixel = normal.get_pixel(x, y);
+    let normal_x = u8_to_float(pixel[0]) * 2.0 - 1.0;
+    let normal_y = u8_to_float(pixel[1]) * 2.0 - 1.0;
+    // steep slopes are limited to about 87°, normals with a negative z are broken anyway
+    let normal_z = f32::max(u8_to_float(pixel[2]) * 2.0 - 1.0, 0.05);
+    (-normal_x / normal_z, normal_y / normal_z)
+}
+
+/// Approximates the heights by integrating along rays spread out evenly in 360° around every
+/// texel. Inspired by this paper: https://doi.org/10.1145/2037826.2037839
+fn integrate_rays(
+    normal: &RgbImage,
+    num_rays: u32,
+    radius: f32,
+    tiling: bool,
+) -> ImageBuffer<Luma<f32>, Vec<f32>> {
+    let width = normal.width();
+    let marcher = RayMarcher::new(normal, num_rays, radius, tiling);
+    let mut heights: ImageBuffer<Luma<f32>, Vec<f32>> = ImageBuffer::new(width, normal.height());
+    // every row is written by a single task of the thread pool, i.e. without any locking
+    heights
+        .par_chunks_mut(width as usize)
+        .enumerate()
+        .for_each(|(y, row)| {
+            for (x, value) in row.iter_mut().enumerate() {
+                *value = marcher.height(x as f32, y as f32);
+            }
+        });
+    heights
+}
+
+/// The depth difference maps of a normal map and the rays that are marched through them.
+struct RayMarcher {
+    sampler_ddm_x: sampler::Linear,
+    sampler_ddm_y: sampler::Linear,
+    /// the 2D direction vectors of the rays
+    directions: Vec<(f32, f32)>,
+    ray_length_texel: u32,
+    width: u32,
+    height: u32,
+    tiling: bool,
+}
+
+impl RayMarcher {
+    fn new(normal: &RgbImage, num_rays: u32, radius: f32, tiling: bool) -> Self {
+        let width = normal.width();
+        let height = normal.height();
+        // Note only x (red) and y (green) are used
+        let mut depth_difference_map_x: ImageBuffer<Luma<f32>, Vec<f32>> =
+            ImageBuffer::new(width, height);
+        let mut depth_difference_map_y: ImageBuffer<Luma<f32>, Vec<f32>> =
+            ImageBuffer::new(width, height);
+        for x in 0..width {
+            for y in 0..height {
+                let normal_x = u8_to_float(normal.get_pixel(x, y)[0]);
+                let normal_y = u8_to_float(normal.get_pixel(x, y)[1]);
+                // transform from [0; 1] to [-1; 1]
+                let normal_x = (normal_x * 2.0) - 1.0;
+                let normal_y = (normal_y * 2.0) - 1.0;
+                // Calculate height (see figure 1 in linked paper)
+                let angle_x = normal_x * (std::f32::consts::PI / 2.0);
+                let angle_y = normal_y * (std::f32::consts::PI / 2.0);
+                let height_difference_x = f32::tan(-angle_x);
+                depth_difference_map_x.put_pixel(x, y, [height_difference_x].into());
+                let height_difference_y = f32::tan(-angle_y);
+                depth_difference_map_y.put_pixel(x, y, [height_difference_y].into());
+            }
+        }
+        // there are often a couple of outliers in the depth difference map
+        // so clamp to a maximum that is that of the 95 percentile
+        const PERCENTILE: f32 = 0.95;
+        clamp_values_to_percentile(&mut depth_difference_map_x, PERCENTILE);
+        clamp_values_to_percentile(&mut depth_difference_map_y, PERCENTILE);
+        let num_rays = num_rays.max(1);
+        Self {
+            // use bilinear filtering of the DDM
+            sampler_ddm_x: sampler::Linear::new(depth_difference_map_x),
+            sampler_ddm_y: sampler::Linear::new(depth_difference_map_y),
+            directions: (0..num_rays)
+                .map(|ray| {
+                    let angle = ray as f32 * ((2.0 * std::f32::consts::PI) / (num_rays as f32));
+                    (f32::sin(angle), f32::cos(angle))
+                })
+                .collect(),
+            ray_length_texel: (f32::max(width as f32, height as f32) * radius).ceil() as u32,
+            width,
+            height,
+            tiling,
         }
     }
-    // convert to u8 gray-scale image
-    let mut output = GrayImage::new(width, height);
-    for x in 0..width {
-        for y in 0..height {
-            let out = heights.get_pixel(x, y)[0];
-            // scale height into range [0; 1]
-            let out = (out - lo) / (hi - lo);
-            output.put_pixel(x, y, [float_to_u8(out)].into());
+
+    /// Integrates the height of one texel via integrating along rays spread out evenly in 360°.
+    /// The result of all rays is averaged and denotes the height * -1.
+    fn height(&self, x: f32, y: f32) -> f32 {
+        let mut sum = 0.0;
+        for (dir_x, dir_y) in self.directions.iter().copied() {
+            // march the ray through the image with texture-edges clamped
+            // (or wrapped around for tiling textures)
+            // note that the current texel of the DDM should not be taken into account
+            let mut ray_sum = 0.0;
+            for i in 0..self.ray_length_texel {
+                let texel_x = x + (i as f32 * dir_x);
+                let texel_y = y + (i as f32 * dir_y);
+                let (value_x, value_y) = if self.tiling {
+                    (
+                        self.sampler_ddm_x.get_pixel_wrapping(texel_x, texel_y),
+                        self.sampler_ddm_y.get_pixel_wrapping(texel_x, texel_y),
+                    )
+                } else {
+                    let texel_x = texel_x.clamp(0.0, self.width as f32 - 1.0);
+                    let texel_y = texel_y.clamp(0.0, self.height as f32 - 1.0);
+                    (
+                        self.sampler_ddm_x.get_pixel(texel_x, texel_y),
+                        self.sampler_ddm_y.get_pixel(texel_x, texel_y),
+                    )
+                };
+                ray_sum += ((dir_x * value_x) - (dir_y * value_y)) / 2.0;
+            }
+            sum += ray_sum / (self.ray_length_texel as f32 * 2.0 + 1.0);
         }
+        sum /= self.directions.len() as f32;
+        -sum
     }
-    output
 }
+
 /// Clamp the values of the image to values that lie in the given percentile
 fn clamp_values_to_percentile(image: &mut ImageBuffer<Luma<f32>, Vec<f32>>, percentile: f32) {
     let mut list: Vec<f32> = image
@@ -170,3 +330,102 @@ fn u8_to_float(input: u8) -> f32 {
 fn float_to_u8(input: f32) -> u8 {
     (input * 255.0) as u8
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Mutex, thread, time::Instant};
+
+    use super::*;
+
+    /// A normal map with smooth bumps.
+    fn bumps(size: u32) -> RgbImage {
+        RgbImage::from_fn(size, size, |x, y| {
+            let normal_x = 0.5 + 0.4 * f32::sin(x as f32 * 0.05);
+            let normal_y = 0.5 + 0.4 * f32::cos(y as f32 * 0.07);
+            [float_to_u8(normal_x), float_to_u8(normal_y), 255].into()
+        })
+    }
+
+    /// The previous scheduling of `integrate_rays`: each of the threads (one per core before)
+    /// integrates a range of columns and writes every texel through a lock.
+    fn integrate_rays_locked(
+        normal: &RgbImage,
+        num_rays: u32,
+        radius: f32,
+        tiling: bool,
+        num_threads: u32,
+    ) -> ImageBuffer<Luma<f32>, Vec<f32>> {
+        let (width, height) = normal.dimensions();
+        let marcher = RayMarcher::new(normal, num_rays, radius, tiling);
+        let heights = Mutex::new(ImageBuffer::new(width, height));
+        thread::scope(|scope| {
+            for thread_id in 0..num_threads {
+                let (marcher, heights) = (&marcher, &heights);
+                scope.spawn(move || {
+                    let columns_per_thread = width / num_threads;
+                    let start = columns_per_thread * thread_id;
+                    let mut end = columns_per_thread * (thread_id + 1);
+                    if thread_id == num_threads - 1 {
+                        end += width % num_threads;
+                    }
+                    for x in start..end {
+                        for y in 0..height {
+                            let value = marcher.height(x as f32, y as f32);
+                            heights.lock().unwrap().put_pixel(x, y, [value].into());
+                        }
+                    }
+                });
+            }
+        });
+        heights.into_inner().unwrap()
+    }
+
+    #[test]
+    fn rays_match_locked_integration() {
+        let normal = bumps(64);
+        for tiling in [false, true] {
+            assert_eq!(
+                integrate_rays(&normal, 8, 0.05, tiling),
+                integrate_rays_locked(&normal, 8, 0.05, tiling, 3)
+            );
+        }
+    }
+
+    /// Compares the scheduling of the ray-marcher before and after it was moved to the rayon
+    /// pool with the default settings, on a single thread and on all cores, so that the speedup of
+    /// both over a single thread is shown. Run with `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_ray_marching_2048() {
+        let normal = bumps(2048);
+        let cores = thread::available_parallelism().map_or(1, |count| count.get());
+        let mut thread_counts = vec![1, cores];
+        thread_counts.dedup();
+        let mut single_thread = None;
+        for num_threads in thread_counts {
+            let start = Instant::now();
+            let before = integrate_rays_locked(&normal, 50, 0.004, false, num_threads as u32);
+            let time_before = start.elapsed();
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(num_threads)
+                .build()
+                .unwrap();
+            let start = Instant::now();
+            let after = pool.install(|| integrate_rays(&normal, 50, 0.004, false));
+            let time_after = start.elapsed();
+            assert_eq!(before, after);
+
+            let (single_before, single_after) =
+                *single_thread.get_or_insert((time_before, time_after));
+            eprintln!(
+                "ray-marching 2048x2048 on {} of {} threads: before {:?} ({:.2}x), after {:?} ({:.2}x)",
+                num_threads,
+                cores,
+                time_before,
+                single_before.as_secs_f64() / time_before.as_secs_f64(),
+                time_after,
+                single_after.as_secs_f64() / time_after.as_secs_f64(),
+            );
+        }
+    }
+}
@@ -1,163 +1,529 @@
+pub mod alpha;
+pub mod downscale;
+pub mod encoding;
+mod fft;
 pub mod height;
+pub mod normal;
+pub mod orm;
+pub mod registry;
 mod sampler;
+pub mod specular;
 
-use std::{fs, io::Cursor, path::Path};
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{BufWriter, Cursor, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
 use anyhow::{anyhow, Context, Result};
-use image::{DynamicImage, Rgb, RgbImage, Rgba, RgbaImage};
-
-use crate::d3dtx;
-
-/// Checks whether or not the texture at the given path has useful alpha information.
-/// If true, the texture has useful alpha information.
-/// A texture with an alpha channel where all values are `0xFF` is treated as "no useful alpha values".
-/// The result is false in this case.
-pub fn texture_has_alpha_information<P: AsRef<Path>>(from: P) -> Result<bool> {
-    let image = image::open(&from).context("could not open/decode texture for alpha check")?;
-
-    // compute whether alpha values are present
-    // Note that there is the result of `all` is often negated by a `!`
-    let texture_has_alpha = match &image {
-        DynamicImage::ImageBgra8(bgra) => !bgra
-            .enumerate_pixels()
-            .all(|(_, _, pixel)| pixel[3] == u8::MAX),
-        DynamicImage::ImageRgba8(rgba) => !rgba
-            .enumerate_pixels()
-            .all(|(_, _, pixel)| pixel[3] == u8::MAX),
-        DynamicImage::ImageRgba16(rgba) => !rgba
-            .enumerate_pixels()
-            .all(|(_, _, pixel)| pixel[3] == u16::MAX),
-        DynamicImage::ImageLumaA8(la) => !la
-            .enumerate_pixels()
-            .all(|(_, _, pixel)| pixel[1] == u8::MAX),
-        DynamicImage::ImageLumaA16(la) => !la
-            .enumerate_pixels()
-            .all(|(_, _, pixel)| pixel[1] == u16::MAX),
-        _ => false,
-    };
+use image::{DynamicImage, GenericImageView, Pixel, Rgb, RgbImage};
+
+use self::{
+    alpha::AlphaUsage,
+    encoding::{save_image, ImageEncoding},
+    normal::NormalSettings,
+    specular::SpecularProfile,
+};
+use crate::{
+    d3dmesh::textures::TextureType,
+    d3dtx::{self, ColorSpace},
+};
+
+/// The settings of the conversions that compute new values (see `Conversion::apply`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConversionSettings {
+    pub normal: NormalSettings,
+    /// how the properties of spec-maps are packed into textures
+    pub specular: SpecularProfile,
+}
+
+/// The kind of conversion that is applied to a texture when copying it to the output folder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Conversion {
+    /// The texture is copied without any modifications to its content (see `copy_texture`).
+    Copy,
+    /// See `normal_map`.
+    Normal,
+    /// See `specular_map`.
+    Specular,
+    /// See `gloss_map`.
+    Gloss,
+    /// Occlusion, roughness and metalness of several textures of a material composed into one
+    /// (see `orm::compose`). Never applied to a single texture.
+    Orm,
+}
+
+impl Conversion {
+    /// Returns the conversion for the given kind of texture or `None` if it is not converted at all.
+    pub fn for_texture_type(kind: &TextureType) -> Option<Self> {
+        match kind {
+            TextureType::Diffuse
+            | TextureType::Detail
+            | TextureType::Ink
+            | TextureType::Height
+            | TextureType::Emission
+            | TextureType::Occlusion
+            | TextureType::DamageMask
+            | TextureType::Environment => Some(Self::Copy),
+            TextureType::Normal | TextureType::DetailNormal => Some(Self::Normal),
+            TextureType::Specular => Some(Self::Specular),
+            TextureType::Gloss => Some(Self::Gloss),
+            _ => None,
+        }
+    }
+
+    /// Applies the conversion to an already decoded texture, whose color channels are stored in
+    /// the given color space. Conversions that compute new values work in linear space.
+    /// Returns the converted images with the suffixes of their file names, which are only
+    /// non-empty for spec-maps that are split into several textures (see `specular::SpecularProfile`).
+    pub fn apply(
+        self,
+        image: DynamicImage,
+        color_space: ColorSpace,
+        settings: &ConversionSettings,
+    ) -> Result<Vec<(&'static str, DynamicImage)>> {
+        let linear = |image| to_linear(image, color_space);
+        Ok(match self {
+            Self::Copy => vec![("", image)],
+            Self::Normal => vec![(
+                "",
+                DynamicImage::ImageRgb8(
+                    settings
+                        .normal
+                        .convention
+                        .apply(&normal::decode(linear(image)?, settings.normal.source)?),
+                ),
+            )],
+            Self::Specular => specular::convert(linear(image)?, settings.specular)?,
+            Self::Gloss => vec![(
+                "",
+                DynamicImage::ImageRgb8(convert_gloss_map(linear(image)?)),
+            )],
+            Self::Orm => return Err(anyhow!("ORM textures are composed of several textures")),
+        })
+    }
+}
+
+/// Returns the color space that textures of the given kind typically use.
+/// Only used if the texture itself does not specify its color space.
+/// Textures that are seen as color (e.g. diffuse) are sRGB, all textures with data (e.g. normals) are linear.
+pub fn default_color_space(kind: &TextureType) -> ColorSpace {
+    match kind {
+        TextureType::Diffuse
+        | TextureType::DiffuseLOD
+        | TextureType::DecalDiffuse
+        | TextureType::MicrodetailDiffuse
+        | TextureType::Detail
+        | TextureType::Emission
+        | TextureType::Environment
+        | TextureType::Ink => ColorSpace::Srgb,
+        _ => ColorSpace::Linear,
+    }
+}
+
+/// Reads and decodes a texture. Its color space is resolved, i.e. it is the one stored in the
+/// texture if known and the typical one of the kind of texture otherwise (see `default_color_space`).
+pub fn open_texture<P: AsRef<Path>>(path: P, kind: &TextureType) -> Result<d3dtx::Texture> {
+    let mut texture = parse_d3dtx(path)?;
+    texture.color_space = Some(color_space_of(&texture, kind));
+    Ok(texture)
+}
+
+/// Returns the color space of the texture, i.e. the one stored in the texture if known and
+/// the typical one of the kind of texture otherwise.
+fn color_space_of(texture: &d3dtx::Texture, kind: &TextureType) -> ColorSpace {
+    texture
+        .color_space
+        .unwrap_or_else(|| default_color_space(kind))
+}
+
+/// Returns the color space of a texture that was opened with `open_texture`.
+fn resolved_color_space(texture: &d3dtx::Texture) -> ColorSpace {
+    texture.color_space.unwrap_or(ColorSpace::Linear)
+}
 
-    Ok(texture_has_alpha)
+/// The properties of a decoded texture that are relevant for the export of materials.
+/// Collected while converting the textures, so that the exporter does not need to read them again.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextureInfo {
+    /// the size of the biggest mip-map
+    pub width: u32,
+    pub height: u32,
+    /// the raw surface format code (see `d3dtx::TextureFormat::parse`),
+    /// the one of the first source for composed textures (see `orm::compose`)
+    pub format_code: u32,
+    /// How the alpha channel is used (see `alpha::analyze_alpha`).
+    pub alpha: AlphaUsage,
+    /// the format the texture was written in (see `TextureFileFormat::for_texture`)
+    pub file_format: TextureFileFormat,
 }
 
-/// Reads a texture and writes it without any modifications to its content to the destination.
+impl TextureInfo {
+    pub fn new(texture: &d3dtx::Texture) -> Self {
+        Self {
+            width: texture.image.width(),
+            height: texture.image.height(),
+            format_code: texture.format_code,
+            alpha: alpha::analyze_alpha(&texture.image),
+            file_format: TextureFileFormat::Png,
+        }
+    }
+}
+
+/// The collected properties of textures, identified by the name of the texture (i.e. of the .d3dtx file).
+pub type TextureInfos = HashMap<String, TextureInfo>;
+
+/// The file format of the textures in the output folder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextureFileFormat {
+    /// Decoded textures as PNG.
+    Png,
+    /// The unmodified block data and mip-maps of the d3dtx file (see `copy_texture_dds`).
+    Dds,
+    /// The unmodified block data and mip-maps of the d3dtx file (see `copy_texture_ktx2`),
+    /// which is written next to a PNG that serves as fallback.
+    Ktx2,
+    /// Decoded textures as lossless WebP (see `save_image`).
+    Webp,
+    /// Decoded textures as JPEG (see `save_image`).
+    Jpeg,
+}
+
+impl TextureFileFormat {
+    /// Returns the format that is actually used for a texture of the given kind and with the given
+    /// conversion and alpha channel. Only textures without conversion can keep their original data,
+    /// all others are written as PNG (or WebP, which is lossless as well).
+    /// JPEG is only used for opaque diffuse textures.
+    ///
+    /// Note that a texture is only converted once per conversion (see `registry::TextureRegistry`),
+    /// i.e. the kind of the first request determines the format, which is recorded in `TextureInfo`.
+    pub fn for_texture(
+        self,
+        conversion: Conversion,
+        kind: &TextureType,
+        alpha: AlphaUsage,
+    ) -> Self {
+        match (self, conversion) {
+            (Self::Webp, _) => Self::Webp,
+            (Self::Jpeg, Conversion::Copy)
+                if *kind == TextureType::Diffuse && alpha == AlphaUsage::Opaque =>
+            {
+                Self::Jpeg
+            }
+            (Self::Jpeg, _) => Self::Png,
+            (_, Conversion::Copy) => self,
+            _ => Self::Png,
+        }
+    }
+
+    /// The file extension of this format (without leading dot).
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Dds => "dds",
+            Self::Ktx2 => "ktx2",
+            Self::Webp => "webp",
+            Self::Jpeg => "jpg",
+        }
+    }
+}
+
+impl FromStr for TextureFileFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "png" => Ok(Self::Png),
+            "dds" => Ok(Self::Dds),
+            "ktx2" => Ok(Self::Ktx2),
+            "webp" => Ok(Self::Webp),
+            "jpeg" | "jpg" => Ok(Self::Jpeg),
+            _ => Err(anyhow!("unknown texture file format {}", value)),
+        }
+    }
+}
+
+/// Writes a texture without any modifications to its content to the destination.
 /// Might perform format conversion, though.
-pub fn copy_texture<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> Result<()> {
-    let image = open_d3dtx(&from).context("could not open/decode texture")?;
-
-    image.save(to).context("could not save texture")?;
-    Ok(())
-}
-
-/// Reads in a normal map from The Walking Dead: The Telltale Definitive Series and
-/// converts it to a typical three-component normal map with R: X; G: Y; B: Z.
-pub fn normal_map<P: AsRef<Path>>(from: P) -> Result<RgbImage> {
-    let image = open_d3dtx(&from).context("could not decode normal map image")?;
-    let new_normal = match image {
-        DynamicImage::ImageRgba8(rgba) => {
-            // some normal maps are RGBA and the channels are swopped
-            let mut new_normal = RgbImage::new(rgba.width(), rgba.height());
-            rgba.enumerate_pixels().for_each(|(x, y, pixel)| {
-                // Switch the channels as following:
-                // Blue -> Green
-                // Green -> Blue
-                // Alpha -> Red
-                // Note: invert the red channel, which makes it compatible to the glTF normal map format
-                let new_pixel: Rgb<u8> = [255 - pixel[3], pixel[2], pixel[1]].into();
-                new_normal.put_pixel(x, y, new_pixel);
-            });
-            new_normal
-        }
-        DynamicImage::ImageLumaA8(rg) => {
-            // normals are compressed via two channels that only contain x and y
-            // https://developer.download.nvidia.com/whitepapers/2008/real-time-normal-map-dxt-compression.pdf
-            // chapter 3.3 Tangent-Space 3Dc
-            let mut new_normal = RgbImage::new(rg.width(), rg.height());
-            rg.enumerate_pixels().for_each(|(x, y, pixel)| {
-                // Note: one u8 holds a value in the range [-1; 1] by storing it in the byte range [0; 255]
-                // i.e. 127 is actually the value 0 and 255 is 1 and 0 is -1
-                let normal_x = u8_to_f32_norm(pixel[0]);
-                let normal_y = u8_to_f32_norm(pixel[1]);
-                // input from texture is [0; 1] and mapped to [-1; 1]
-                let normal_x = (normal_x * 2.0) - 1.0;
-                let normal_y = (normal_y * 2.0) - 1.0;
-                // calculate z by using sqrt(1-x²-y²)
-                let normal_z = f32::sqrt(1.0 - (normal_x * normal_x) - (normal_y * normal_y));
-                // map x, y and z from the previous range [-1; 1] to [0; 1]
-                let normal_x = (normal_x + 1.0) / 2.0;
-                let normal_y = (normal_y + 1.0) / 2.0;
-                let normal_z = (normal_z + 1.0) / 2.0;
-                let new_pixel: Rgb<u8> = [
-                    f32_to_u8_norm(normal_x),
-                    f32_to_u8_norm(normal_y),
-                    f32_to_u8_norm(normal_z),
-                ]
-                .into();
-                new_normal.put_pixel(x, y, new_pixel);
-            });
-            new_normal
-        }
-        // Rgb8 format is not converted under the assumption that the format is already correct
-        DynamicImage::ImageRgb8(rgb) => rgb,
-        val => return Err(anyhow!("unknown normal map format {:?}", val.color())),
-    };
+/// Cube maps are stored as a horizontal cross (see `d3dtx::Texture::decode_cube_cross`).
+pub fn copy_texture<Q: AsRef<Path>>(
+    texture: &d3dtx::Texture,
+    to: Q,
+    format: TextureFileFormat,
+    encoding: &ImageEncoding,
+) -> Result<()> {
+    if texture.layout.is_cube() {
+        let cross = texture
+            .decode_cube_cross(0)
+            .context("could not assemble cube map faces")?;
+        save_image(&cross, to, format, encoding)
+    } else {
+        save_image(&texture.image, to, format, encoding)
+    }
+    .context("could not save texture")
+}
+
+/// Writes the data of a texture as DDS file to the destination, including all mip-maps
+/// and faces. The data is not decoded or re-encoded, i.e. block compressed textures stay
+/// block compressed (see `d3dtx::dds::write_dds`).
+/// The data is marked as sRGB according to the color space of the texture (see `open_texture`).
+pub fn copy_texture_dds<Q: AsRef<Path>>(texture: &d3dtx::Texture, to: Q) -> Result<()> {
+    let mut out = BufWriter::new(File::create(to).context("could not create DDS file")?);
+    d3dtx::dds::write_dds(texture, &mut out).context("could not write DDS file")?;
+    out.flush().context("could not write DDS file")
+}
 
-    Ok(new_normal)
+/// Writes the data of a texture as KTX2 file to the destination, including all mip-maps
+/// and faces. The data is not decoded or re-encoded, but the mip-maps are compressed with zlib
+/// if `supercompression` is set (see `d3dtx::ktx2::write_ktx2`).
+/// The data is marked as sRGB according to the color space of the texture (see `open_texture`).
+pub fn copy_texture_ktx2<Q: AsRef<Path>>(
+    texture: &d3dtx::Texture,
+    to: Q,
+    supercompression: bool,
+) -> Result<()> {
+    let mut out = BufWriter::new(File::create(to).context("could not create KTX2 file")?);
+    d3dtx::ktx2::write_ktx2(texture, &mut out, supercompression)
+        .context("could not write KTX2 file")?;
+    out.flush().context("could not write KTX2 file")
 }
 
-/// Reads in a spec-map from The Walking Dead: The Telltale Definitive Series and
-/// converts it to a glTF texture with the following setup:
+/// Exports every face of cube maps and every element or slice of array and volume textures
+/// as separate images next to `to`. Cube map faces are named after their direction, e.g.
+/// `name.png` results in `name.px.png`, `name.nx.png`, ..., `name.nz.png` (cube arrays in
+/// `name.cube1.px.png` and so on), other surfaces are numbered, e.g. `name.slice0.png`.
+/// The given conversion is applied to every surface, spec-maps that are split into several
+/// textures are named like `name_roughness.px.png`. Nothing is exported for plain 2D textures.
+/// Returns the paths of the written images.
+pub fn export_surfaces<Q: AsRef<Path>>(
+    texture: &d3dtx::Texture,
+    to: Q,
+    conversion: Conversion,
+    settings: &ConversionSettings,
+    encoding: &ImageEncoding,
+) -> Result<Vec<PathBuf>> {
+    const FACE_NAMES: [&str; 6] = ["px", "nx", "py", "ny", "pz", "nz"];
+    let surfaces = texture.decode_surfaces(0)?;
+    let mut paths = Vec::new();
+    if surfaces.len() < 2 {
+        return Ok(paths);
+    }
+    let to = to.as_ref();
+    let stem = to
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .ok_or(anyhow!("invalid texture path {:?}", to))?;
+    for (index, surface) in surfaces.into_iter().enumerate() {
+        let suffix = match (texture.layout.is_cube(), index / 6) {
+            (true, 0) => FACE_NAMES[index].to_string(),
+            (true, cube) => format!("cube{}.{}", cube, FACE_NAMES[index % 6]),
+            (false, _) => format!("slice{}", index),
+        };
+        let images = conversion.apply(surface, resolved_color_space(texture), settings)?;
+        for (packed, image) in images {
+            let path = to.with_file_name(format!("{}{}.{}.png", stem, packed, suffix));
+            save_image(&image, &path, TextureFileFormat::Png, encoding)
+                .with_context(|| format!("could not save surface {}", suffix))?;
+            paths.push(path);
+        }
+    }
+    Ok(paths)
+}
+
+/// Takes a normal map from The Walking Dead: The Telltale Definitive Series in the given
+/// encoding (or the detected one if `None`) and converts it to a typical three-component normal
+/// map with R: X; G: Y; B: Z (see `normal::decode`).
+pub fn normal_map(
+    texture: &d3dtx::Texture,
+    encoding: Option<normal::NormalEncoding>,
+) -> Result<RgbImage> {
+    let image = linear_image(texture)?;
+    normal::decode(image, encoding)
+}
+
+/// Takes a spec-map from The Walking Dead: The Telltale Definitive Series and converts it to
+/// the textures of the given profile (see `specular::SpecularProfile::textures`).
+/// Returns the images with the suffixes of their file names.
+pub fn specular_map(
+    texture: &d3dtx::Texture,
+    profile: SpecularProfile,
+) -> Result<Vec<(&'static str, DynamicImage)>> {
+    let image = linear_image(texture)?;
+    specular::convert(image, profile)
+}
+
+/// Takes a gloss map from The Walking Dead: The Telltale Definitive Series and
+/// converts it to a glTF metallic-roughness texture with the following setup:
 ///
-/// 1. R: Occlusion
-/// 2. G: Roughness
-/// 3. B: Metalness
-/// 4. A: Specular
-pub fn specular_map<P: AsRef<Path>>(from: P) -> Result<RgbaImage> {
-    let image = open_d3dtx(&from).context("could not decode specular map image")?;
+/// 1. R: unused (full white)
+/// 2. G: Roughness (i.e. 1-Gloss)
+/// 3. B: Metalness (always zero)
+pub fn gloss_map(texture: &d3dtx::Texture) -> Result<RgbImage> {
+    let image = linear_image(texture)?;
+    Ok(convert_gloss_map(image))
+}
+
+fn convert_gloss_map(image: DynamicImage) -> RgbImage {
+    // Note: gloss maps are gray-scale, so only the first channel is relevant
+    let gloss = image.to_luma8();
+
+    let mut new_roughness = RgbImage::new(gloss.width(), gloss.height());
+    gloss.enumerate_pixels().for_each(|(x, y, pixel)| {
+        let new_pixel: Rgb<u8> = [255, 255 - pixel[0], 0].into();
+        new_roughness.put_pixel(x, y, new_pixel);
+    });
+
+    new_roughness
+}
+
+/// Exports all mip-map levels except the biggest one (which is the converted texture itself)
+/// next to `to`, i.e. `name.png` results in `name.mip1.png`, `name.mip2.png` and so on.
+/// The given conversion is applied to every level, spec-maps that are split into several
+/// textures are named like `name_roughness.mip1.png`. Returns the paths of the written images.
+pub fn export_mips<Q: AsRef<Path>>(
+    texture: &d3dtx::Texture,
+    to: Q,
+    conversion: Conversion,
+    settings: &ConversionSettings,
+    encoding: &ImageEncoding,
+) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    let to = to.as_ref();
+    let stem = to
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .ok_or(anyhow!("invalid texture path {:?}", to))?;
+    for level in 1..texture.mip_count {
+        let image = texture
+            .decode_mip(level as usize)
+            .with_context(|| format!("could not decode mip-map level {}", level))?;
+        let images = conversion.apply(image, resolved_color_space(texture), settings)?;
+        for (packed, image) in images {
+            let path = to.with_file_name(format!("{}{}.mip{}.png", stem, packed, level));
+            save_image(&image, &path, TextureFileFormat::Png, encoding)
+                .with_context(|| format!("could not save mip-map level {}", level))?;
+            paths.push(path);
+        }
+    }
+    Ok(paths)
+}
+
+/// Appends the suffix to the file stem of the path, e.g. `name.png` and `_roughness` result in
+/// `name_roughness.png`.
+pub fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    if suffix.is_empty() {
+        return path.to_path_buf();
+    }
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{}{}.{}", stem, suffix, extension.to_string_lossy()),
+        None => format!("{}{}", stem, suffix),
+    };
+    path.with_file_name(name)
+}
 
+/// Returns the biggest mip-map of the texture with the color channels in linear space.
+fn linear_image(texture: &d3dtx::Texture) -> Result<DynamicImage> {
+    to_linear(texture.image.clone(), resolved_color_space(texture))
+}
+
+/// Converts the color channels of the image from the given color space to linear space.
+/// Alpha is always linear and kept as is.
+fn to_linear(image: DynamicImage, color_space: ColorSpace) -> Result<DynamicImage> {
+    if color_space == ColorSpace::Linear {
+        return Ok(image);
+    }
+    let lut: Vec<u8> = (0..=u8::MAX)
+        .map(|value| (srgb_to_linear(u8_to_f32_norm(value)) * 255.0).round() as u8)
+        .collect();
+    let convert_u8 = |value: u8| lut[value as usize];
+    let convert_u16 =
+        |value: u16| (srgb_to_linear(f32::from(value) / 65535.0) * 65535.0).round() as u16;
     let image = match image {
-        image::DynamicImage::ImageRgba8(rgba) => rgba,
-        image::DynamicImage::ImageRgb8(rgb) => {
-            // convert from RGB to RGBA with A as full roughness (=1)
-            let mut rgba = RgbaImage::new(rgb.width(), rgb.height());
-            rgb.enumerate_pixels().for_each(|(x, y, pixel)| {
-                let new_pixel: Rgba<u8> = [pixel[0], pixel[1], pixel[2], 0].into();
-                rgba.put_pixel(x, y, new_pixel)
-            });
-            rgba
-        }
-        val => return Err(anyhow!("unknown specular map format {:?}", val.color())),
+        DynamicImage::ImageLuma8(mut image) => {
+            image.pixels_mut().for_each(|pixel| pixel.apply(convert_u8));
+            DynamicImage::ImageLuma8(image)
+        }
+        DynamicImage::ImageLumaA8(mut image) => {
+            image
+                .pixels_mut()
+                .for_each(|pixel| pixel.apply_without_alpha(convert_u8));
+            DynamicImage::ImageLumaA8(image)
+        }
+        DynamicImage::ImageRgb8(mut image) => {
+            image.pixels_mut().for_each(|pixel| pixel.apply(convert_u8));
+            DynamicImage::ImageRgb8(image)
+        }
+        DynamicImage::ImageRgba8(mut image) => {
+            image
+                .pixels_mut()
+                .for_each(|pixel| pixel.apply_without_alpha(convert_u8));
+            DynamicImage::ImageRgba8(image)
+        }
+        DynamicImage::ImageLuma16(mut image) => {
+            image
+                .pixels_mut()
+                .for_each(|pixel| pixel.apply(convert_u16));
+            DynamicImage::ImageLuma16(image)
+        }
+        DynamicImage::ImageLumaA16(mut image) => {
+            image
+                .pixels_mut()
+                .for_each(|pixel| pixel.apply_without_alpha(convert_u16));
+            DynamicImage::ImageLumaA16(image)
+        }
+        DynamicImage::ImageRgb16(mut image) => {
+            image
+                .pixels_mut()
+                .for_each(|pixel| pixel.apply(convert_u16));
+            DynamicImage::ImageRgb16(image)
+        }
+        DynamicImage::ImageRgba16(mut image) => {
+            image
+                .pixels_mut()
+                .for_each(|pixel| pixel.apply_without_alpha(convert_u16));
+            DynamicImage::ImageRgba16(image)
+        }
+        image => {
+            return Err(anyhow!(
+                "can not convert {:?} image to linear color space",
+                image.color()
+            ))
+        }
     };
+    Ok(image)
+}
 
-    let mut new_specular = RgbaImage::new(image.width(), image.height());
-    image.enumerate_pixels().for_each(|(x, y, pixel)| {
-        // The Telltale setup is internally:
-        // R: Specular (?)
-        // G: Metalness
-        // B: 1-Occlusion
-        // A: 1-Roughness (sometimes called glossy)
-        let new_pixel: Rgba<u8> = [255 - pixel[2], 255 - pixel[3], pixel[1], pixel[0]].into();
-        new_specular.put_pixel(x, y, new_pixel);
-    });
+/// Converts a sRGB encoded value in the range [0, 1] to linear space.
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
 
-    Ok(new_specular)
+/// Converts a linear value in the range [0, 1] to sRGB encoding.
+fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
 }
 
-fn open_d3dtx<P: AsRef<Path>>(path: P) -> Result<DynamicImage> {
+fn parse_d3dtx<P: AsRef<Path>>(path: P) -> Result<d3dtx::Texture> {
     let file = fs::read(&path).context(format!(
         "could not open d3dtx file (expected at {})",
         path.as_ref().to_string_lossy()
     ))?;
     let input = Cursor::new(file);
-    let image = d3dtx::Texture::parse(input)?;
-    Ok(image.image)
+    d3dtx::Texture::parse(input)
 }
 
 // converts a u8 in the range [0, 255] to a f32 in the range [0, 1].
 fn u8_to_f32_norm(value: u8) -> f32 {
     value as f32 / 255.0
 }
-
-// converts a f32 in the range [0, 1] to a u8 in the range [0, 255].
-fn f32_to_u8_norm(value: f32) -> u8 {
-    (value * 255.0) as u8
-}
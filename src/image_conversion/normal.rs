@@ -0,0 +1,212 @@
+//! Decoding of the different normal map encodings and conversion to the target convention.
+
+use anyhow::{anyhow, Result};
+use image::{DynamicImage, Rgb, RgbImage, RgbaImage};
+
+/// How the normals are stored in the channels of a decoded normal map.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NormalEncoding {
+    /// X, Y and Z in red, green and blue.
+    Xyz,
+    /// X and Y in the first two channels (e.g. BC5 or red and green), Z is reconstructed.
+    Xy,
+    /// Telltale's RGBA normal maps: X inverted in alpha, Y in blue and Z in green.
+    Swizzled,
+    /// X in alpha and Y in green (DXT5nm), Z is reconstructed.
+    Dxt5nm,
+}
+
+/// The convention of the converted normal maps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NormalConvention {
+    /// X, Y and Z in red, green and blue with green pointing up (Y+), which is used by glTF.
+    OpenGl,
+    /// Like `OpenGl`, but green points down (Y-).
+    DirectX,
+    /// X and Y (pointing up) in red and green, blue is zero.
+    TwoChannel,
+}
+
+/// The settings of the normal map conversion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NormalSettings {
+    /// the encoding of the source textures, `None` if it is detected (see `detect_encoding`)
+    pub source: Option<NormalEncoding>,
+    pub convention: NormalConvention,
+}
+
+/// Parses the name of a normal map encoding, i.e. `auto` (returned as `None`),
+/// `xyz`, `xy`, `swizzled` or `dxt5nm`.
+pub fn parse_encoding(value: &str) -> Result<Option<NormalEncoding>> {
+    match value.to_ascii_lowercase().as_str() {
+        "auto" => Ok(None),
+        "xyz" => Ok(Some(NormalEncoding::Xyz)),
+        "xy" => Ok(Some(NormalEncoding::Xy)),
+        "swizzled" => Ok(Some(NormalEncoding::Swizzled)),
+        "dxt5nm" => Ok(Some(NormalEncoding::Dxt5nm)),
+        _ => Err(anyhow!("unknown normal map encoding {}", value)),
+    }
+}
+
+/// Parses the name of a normal map convention, i.e. `opengl`, `directx` or `rg`.
+pub fn parse_convention(value: &str) -> Result<NormalConvention> {
+    match value.to_ascii_lowercase().as_str() {
+        "opengl" => Ok(NormalConvention::OpenGl),
+        "directx" => Ok(NormalConvention::DirectX),
+        "rg" => Ok(NormalConvention::TwoChannel),
+        _ => Err(anyhow!("unknown normal map convention {}", value)),
+    }
+}
+
+impl NormalConvention {
+    /// Converts a normal map in the `OpenGl` convention (see `decode`) to this convention.
+    pub fn apply(self, normal: &RgbImage) -> RgbImage {
+        let mut converted = normal.clone();
+        match self {
+            Self::OpenGl => {}
+            Self::DirectX => converted
+                .pixels_mut()
+                .for_each(|pixel| pixel[1] = 255 - pixel[1]),
+            Self::TwoChannel => converted.pixels_mut().for_each(|pixel| pixel[2] = 0),
+        }
+        converted
+    }
+}
+
+/// The mean deviation from valid normals up to which an encoding is accepted.
+/// Block compression and 8 bit quantization cause small deviations.
+const MAX_ERROR: f32 = 0.1;
+
+/// Returns the channels of the image as RGBA, two-channel images (i.e. BC5) are stored in red
+/// and green. Returns the image and the number of channels.
+fn channels(image: &DynamicImage) -> Result<(RgbaImage, u8)> {
+    match image {
+        DynamicImage::ImageLumaA8(rg) => Ok((
+            RgbaImage::from_fn(rg.width(), rg.height(), |x, y| {
+                let pixel = rg.get_pixel(x, y);
+                [pixel[0], pixel[1], 0, 255].into()
+            }),
+            2,
+        )),
+        DynamicImage::ImageLuma8(_) | DynamicImage::ImageLuma16(_) => {
+            Err(anyhow!("unknown normal map format {:?}", image.color()))
+        }
+        _ => Ok((image.to_rgba8(), image.color().channel_count())),
+    }
+}
+
+/// Returns the components of the normal of the pixel in the range [-1, 1] in the given encoding.
+/// Z is `None` for encodings that only store X and Y.
+fn components(pixel: [u8; 4], encoding: NormalEncoding) -> (f32, f32, Option<f32>) {
+    let [r, g, b, a] = pixel.map(|value| f32::from(value) / 255.0 * 2.0 - 1.0);
+    match encoding {
+        NormalEncoding::Xyz => (r, g, Some(b)),
+        NormalEncoding::Xy => (r, g, None),
+        // Note: invert the alpha channel, which makes it compatible to the glTF normal map format
+        NormalEncoding::Swizzled => (-a, b, Some(g)),
+        NormalEncoding::Dxt5nm => (a, g, None),
+    }
+}
+
+/// Classifies the encoding of a (linear) normal map by the statistics of its channels, i.e. the
+/// encoding whose channels form valid normals (unit length and pointing outwards, or inside the
+/// unit circle if Z is reconstructed). Encodings that store Z are preferred, since X and Y of
+/// such a map are valid as well. Falls back to the typical encoding of the channel count.
+pub fn detect_encoding(image: &DynamicImage) -> Result<NormalEncoding> {
+    let (rgba, channel_count) = channels(image)?;
+    let candidates: &[NormalEncoding] = match channel_count {
+        2 => &[NormalEncoding::Xy],
+        3 => &[NormalEncoding::Xyz, NormalEncoding::Xy],
+        _ => &[
+            NormalEncoding::Xyz,
+            NormalEncoding::Swizzled,
+            NormalEncoding::Xy,
+            NormalEncoding::Dxt5nm,
+        ],
+    };
+    let fallback = match channel_count {
+        2 => NormalEncoding::Xy,
+        3 => NormalEncoding::Xyz,
+        _ => NormalEncoding::Swizzled,
+    };
+    // about 64K evenly spread samples are enough for the statistics
+    let pixel_count = rgba.width() as usize * rgba.height() as usize;
+    let step = (pixel_count / 65536).max(1);
+    let samples: Vec<[u8; 4]> = rgba.pixels().step_by(step).map(|pixel| pixel.0).collect();
+
+    let mut best: Option<(NormalEncoding, f32)> = None;
+    for encoding in candidates.iter().copied() {
+        let error = match encoding_error(&samples, encoding) {
+            Some(error) => error,
+            None => continue,
+        };
+        log::trace!("normal map encoding {:?}: error = {}", encoding, error);
+        let stores_z = matches!(encoding, NormalEncoding::Xyz | NormalEncoding::Swizzled);
+        if stores_z && error < MAX_ERROR && best.is_none_or(|(_, best)| error < best) {
+            best = Some((encoding, error));
+        }
+        if !stores_z && error < MAX_ERROR && best.is_none() {
+            best = Some((encoding, error));
+        }
+    }
+    Ok(best.map_or(fallback, |(encoding, _)| encoding))
+}
+
+/// Returns the mean deviation of the samples from valid normals in the given encoding,
+/// `None` if the encoding is implausible, e.g. because X or Y are constant.
+fn encoding_error(samples: &[[u8; 4]], encoding: NormalEncoding) -> Option<f32> {
+    let count = samples.len().max(1) as f32;
+    let mut error = 0.0;
+    let mut sum_z = 0.0;
+    let (mut min_x, mut max_x) = (f32::INFINITY, f32::NEG_INFINITY);
+    let (mut min_y, mut max_y) = (f32::INFINITY, f32::NEG_INFINITY);
+    for pixel in samples {
+        let (x, y, z) = components(*pixel, encoding);
+        min_x = min_x.min(x);
+        max_x = max_x.max(x);
+        min_y = min_y.min(y);
+        max_y = max_y.max(y);
+        match z {
+            Some(z) => {
+                error += ((x * x + y * y + z * z).sqrt() - 1.0).abs();
+                sum_z += z;
+            }
+            None => error += ((x * x + y * y).sqrt() - 1.0).max(0.0),
+        }
+    }
+    match encoding {
+        // Z of tangent space normals points outwards
+        NormalEncoding::Xyz | NormalEncoding::Swizzled if sum_z / count <= 0.0 => None,
+        // a constant channel is an unused one, unless the whole map is flat
+        NormalEncoding::Xy | NormalEncoding::Dxt5nm
+            if max_x - min_x < 2.0 / 255.0 || max_y - min_y < 2.0 / 255.0 =>
+        {
+            None
+        }
+        _ => Some(error / count),
+    }
+}
+
+/// Converts a (linear) normal map in the given encoding, or the detected one if `None`,
+/// to a typical three-component normal map with R: X; G: Y (up); B: Z, i.e. the
+/// `NormalConvention::OpenGl` convention.
+pub fn decode(image: DynamicImage, encoding: Option<NormalEncoding>) -> Result<RgbImage> {
+    let encoding = match encoding {
+        Some(encoding) => encoding,
+        None => detect_encoding(&image)?,
+    };
+    log::debug!("normal map encoding: {:?}", encoding);
+    let (rgba, _) = channels(&image)?;
+    // map x, y and z from the range [-1; 1] to [0; 255]
+    let to_u8 = |value: f32| ((value + 1.0) / 2.0 * 255.0).round().clamp(0.0, 255.0) as u8;
+    Ok(RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let (normal_x, normal_y, normal_z) = components(rgba.get_pixel(x, y).0, encoding);
+        // normals that only store x and y are compressed via two channels
+        // https://developer.download.nvidia.com/whitepapers/2008/real-time-normal-map-dxt-compression.pdf
+        // chapter 3.3 Tangent-Space 3Dc
+        let normal_z = normal_z.unwrap_or_else(|| {
+            f32::sqrt((1.0 - (normal_x * normal_x) - (normal_y * normal_y)).max(0.0))
+        });
+        Rgb([to_u8(normal_x), to_u8(normal_y), to_u8(normal_z)])
+    }))
+}
@@ -0,0 +1,167 @@
+//! Composition of the occlusion, gloss and spec-maps of a material into one glTF-conformant
+//! occlusion/roughness/metallic texture, i.e. R: occlusion, G: roughness and B: metalness.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use image::{imageops, GrayImage, Rgb, RgbImage};
+
+use super::{alpha::AlphaUsage, linear_image, specular, Conversion, TextureFileFormat};
+use crate::{
+    d3dmesh::{
+        materials::Material,
+        textures::{Texture, TextureMap, TextureType},
+    },
+    d3dtx,
+};
+
+/// The occlusion if the material has no source for it, i.e. no occlusion at all.
+const DEFAULT_OCCLUSION: u8 = 255;
+/// The roughness if the material has no source for it, i.e. fully rough as glTF's default.
+const DEFAULT_ROUGHNESS: u8 = 255;
+/// The metalness if the material has no source for it, i.e. dielectric.
+const DEFAULT_METALNESS: u8 = 0;
+
+/// The textures of a material that provide occlusion, roughness or metalness.
+#[derive(Debug, Clone, Copy)]
+pub struct OrmSources<'a> {
+    /// provides the occlusion (red channel), takes precedence over the spec-map
+    pub occlusion: Option<&'a Texture>,
+    /// provides the roughness (inverted gloss) if there is no spec-map
+    pub gloss: Option<&'a Texture>,
+    /// provides all three properties (see `specular::decode`)
+    pub specular: Option<&'a Texture>,
+}
+
+impl<'a> OrmSources<'a> {
+    /// Returns the sources of the material, i.e. its primary occlusion, gloss and spec-map,
+    /// or `None` if it has none of them.
+    pub fn of_material(material: &'a Material) -> Option<Self> {
+        let find = |kind: TextureType| {
+            material.textures.iter().find(|texture| {
+                texture.kind == kind
+                    && (texture.map == TextureMap::Map || texture.map == TextureMap::MapA)
+                    && !texture.name.is_empty()
+            })
+        };
+        let sources = Self {
+            occlusion: find(TextureType::Occlusion),
+            gloss: find(TextureType::Gloss),
+            specular: find(TextureType::Specular),
+        };
+        sources.textures().next().map(|_| sources)
+    }
+
+    /// Returns the textures that are present.
+    pub fn textures(&self) -> impl Iterator<Item = &'a Texture> {
+        vec![self.occlusion, self.gloss, self.specular]
+            .into_iter()
+            .flatten()
+    }
+
+    /// The name of the composed texture, which is derived from the names of its sources, e.g.
+    /// `name_occ.d3dtx` and `name_gloss.d3dtx` result in `name_occ_name_gloss_orm`.
+    /// Materials with the same sources share the composed texture.
+    pub fn name(&self) -> String {
+        let stems: Vec<_> = self
+            .textures()
+            .map(|texture| {
+                Path::new(&texture.name)
+                    .file_stem()
+                    .map_or(texture.name.clone(), |stem| {
+                        stem.to_string_lossy().to_string()
+                    })
+            })
+            .collect();
+        format!("{}_orm", stems.join("_"))
+    }
+
+    /// The path of the composed texture relative to the texture folder
+    /// if it is written in the given file format (see `file_format`).
+    pub fn path(&self, file_format: TextureFileFormat) -> PathBuf {
+        PathBuf::from(self.name()).with_extension(file_format.extension())
+    }
+}
+
+/// Returns the format that is actually used for composed textures (see `TextureFileFormat::for_texture`).
+pub fn file_format(format: TextureFileFormat) -> TextureFileFormat {
+    format.for_texture(Conversion::Orm, &TextureType::Occlusion, AlphaUsage::Opaque)
+}
+
+/// Composes the (already opened, see `super::open_texture`) sources into one texture with
+/// R: occlusion, G: roughness and B: metalness. Missing properties are filled with constants
+/// (no occlusion, fully rough and dielectric). Sources of different resolution are resized to
+/// the biggest width and height among them.
+pub fn compose(
+    occlusion: Option<&d3dtx::Texture>,
+    gloss: Option<&d3dtx::Texture>,
+    specular: Option<&d3dtx::Texture>,
+) -> Result<RgbImage> {
+    let occlusion = occlusion.map(first_channel).transpose()?;
+    let roughness = gloss.map(first_channel).transpose()?.map(|mut gloss| {
+        gloss
+            .pixels_mut()
+            .for_each(|pixel| pixel[0] = 255 - pixel[0]);
+        gloss
+    });
+    let properties = specular
+        .map(|texture| specular::decode(linear_image(texture)?))
+        .transpose()?;
+    let channel_of_properties = |index: usize| {
+        properties.as_ref().map(|properties| {
+            GrayImage::from_fn(properties.width(), properties.height(), |x, y| {
+                [properties.get_pixel(x, y)[index]].into()
+            })
+        })
+    };
+
+    let channels = [
+        occlusion.or_else(|| channel_of_properties(0)),
+        // the roughness of a spec-map takes precedence over a gloss map
+        channel_of_properties(1).or(roughness),
+        channel_of_properties(2),
+    ];
+    let width = channels
+        .iter()
+        .flatten()
+        .map(|channel| channel.width())
+        .max();
+    let height = channels
+        .iter()
+        .flatten()
+        .map(|channel| channel.height())
+        .max();
+    let (width, height) = (width.unwrap_or(1), height.unwrap_or(1));
+    let [occlusion, roughness, metalness] =
+        channels.map(|channel| channel.map(|channel| resize(channel, width, height)));
+
+    let value = |channel: &Option<GrayImage>, x: u32, y: u32, default: u8| {
+        channel
+            .as_ref()
+            .map_or(default, |channel| channel.get_pixel(x, y)[0])
+    };
+    Ok(RgbImage::from_fn(width, height, |x, y| {
+        Rgb([
+            value(&occlusion, x, y, DEFAULT_OCCLUSION),
+            value(&roughness, x, y, DEFAULT_ROUGHNESS),
+            value(&metalness, x, y, DEFAULT_METALNESS),
+        ])
+    }))
+}
+
+/// Returns the first (i.e. red) channel of the texture in linear space.
+/// Occlusion and gloss maps are gray-scale, so only the first channel is relevant.
+fn first_channel(texture: &d3dtx::Texture) -> Result<GrayImage> {
+    let image = linear_image(texture)?.to_rgb8();
+    Ok(GrayImage::from_fn(image.width(), image.height(), |x, y| {
+        [image.get_pixel(x, y)[0]].into()
+    }))
+}
+
+/// Resizes the (linear) channel to the given resolution if it differs.
+fn resize(channel: GrayImage, width: u32, height: u32) -> GrayImage {
+    if channel.dimensions() == (width, height) {
+        return channel;
+    }
+    imageops::resize(&channel, width, height, imageops::FilterType::Triangle)
+}
@@ -0,0 +1,180 @@
+//! A registry of texture conversions that is shared by all meshes and skeletons of a run.
+//!
+//! Many textures (e.g. skin, eyes or common props) are referenced by several meshes. The textures
+//! of all meshes are collected first and every texture is converted only once per conversion (see
+//! `TextureRegistry::convert_all`), before any mesh is converted. The meshes only look up the
+//! results, i.e. no worker waits for the conversion of another one. Waiting inside rayon's pool
+//! could deadlock, since conversions run nested parallel work and a waiting worker may steal
+//! another task that waits for the conversion of the first one.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use anyhow::{anyhow, Result};
+use rayon::prelude::*;
+
+use super::{Conversion, TextureInfo};
+
+/// A texture (identified by its name) together with the conversion that is applied to it
+pub type JobKey = (String, Conversion);
+
+pub struct TextureRegistry {
+    /// the error is stored as message, since it is handed out to every mesh using the texture
+    results: Mutex<HashMap<JobKey, Result<TextureInfo, String>>>,
+    /// number of requests of all meshes, including the ones that reuse a conversion
+    requests: AtomicUsize,
+    /// number of conversions whose texture exceeded the size limit (see `downscale::limit_size`)
+    downscaled: AtomicUsize,
+}
+
+impl TextureRegistry {
+    pub fn new() -> Self {
+        Self {
+            results: Mutex::new(HashMap::new()),
+            requests: AtomicUsize::new(0),
+            downscaled: AtomicUsize::new(0),
+        }
+    }
+
+    /// Runs `convert` once for every distinct job of the requests in parallel and stores the results.
+    /// The job of the first request of a key is converted, the later requests reuse its result.
+    pub fn convert_all<J, F>(&self, requests: Vec<(JobKey, J)>, convert: F)
+    where
+        J: Send,
+        F: Fn(&JobKey, J) -> Result<TextureInfo> + Sync,
+    {
+        self.requests.fetch_add(requests.len(), Ordering::Relaxed);
+        let mut jobs = BTreeMap::new();
+        for (key, job) in requests {
+            jobs.entry(key).or_insert(job);
+        }
+        let results: Vec<_> = jobs
+            .into_par_iter()
+            .map(|(key, job)| {
+                let result = convert(&key, job).map_err(|err| format!("{:#}", err));
+                (key, result)
+            })
+            .collect();
+        self.results
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .extend(results);
+    }
+
+    /// Returns the properties of the texture converted by `convert_all`.
+    pub fn get(&self, name: &str, conversion: Conversion) -> Result<TextureInfo> {
+        let results = self.results.lock().unwrap_or_else(|err| err.into_inner());
+        match results.get(&(name.to_string(), conversion)) {
+            Some(Ok(info)) => Ok(*info),
+            Some(Err(err)) => Err(anyhow!("conversion {:?} failed: {}", conversion, err)),
+            None => Err(anyhow!("texture {} was not converted", name)),
+        }
+    }
+
+    /// The number of requests that reused the result of another request.
+    pub fn hits(&self) -> usize {
+        self.requests
+            .load(Ordering::Relaxed)
+            .saturating_sub(self.misses())
+    }
+
+    /// The number of conversions that were run.
+    pub fn misses(&self) -> usize {
+        self.results
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .len()
+    }
+
+    /// Records that a conversion reduced its texture to the size limit.
+    pub fn count_downscaled(&self) {
+        self.downscaled.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The number of conversions that reduced their texture to the size limit.
+    pub fn downscaled(&self) -> usize {
+        self.downscaled.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image_conversion::{alpha::AlphaUsage, TextureFileFormat};
+
+    /// Converts the requests and returns the number of conversions by key and the job that
+    /// was converted.
+    fn convert(
+        registry: &TextureRegistry,
+        requests: Vec<(JobKey, u32)>,
+    ) -> HashMap<JobKey, (usize, u32)> {
+        let conversions = Mutex::new(HashMap::new());
+        registry.convert_all(requests, |key, job| {
+            let mut conversions = conversions.lock().unwrap();
+            let entry = conversions.entry(key.clone()).or_insert((0, job));
+            entry.0 += 1;
+            if key.0 == "broken" {
+                return Err(anyhow!("invalid data"));
+            }
+            Ok(TextureInfo {
+                width: job,
+                height: job,
+                format_code: 0,
+                alpha: AlphaUsage::Opaque,
+                file_format: TextureFileFormat::Png,
+            })
+        });
+        conversions.into_inner().unwrap()
+    }
+
+    fn key(name: &str, conversion: Conversion) -> JobKey {
+        (name.to_string(), conversion)
+    }
+
+    #[test]
+    fn converts_every_job_once() {
+        let registry = TextureRegistry::new();
+        let requests = vec![
+            (key("skin", Conversion::Copy), 1),
+            (key("eyes", Conversion::Copy), 2),
+            (key("skin", Conversion::Copy), 3),
+            (key("skin", Conversion::Normal), 4),
+            (key("eyes", Conversion::Copy), 5),
+            (key("skin", Conversion::Copy), 6),
+        ];
+        let conversions = convert(&registry, requests);
+
+        // the job of the first request is converted
+        let mut expected = HashMap::new();
+        expected.insert(key("skin", Conversion::Copy), (1, 1));
+        expected.insert(key("eyes", Conversion::Copy), (1, 2));
+        expected.insert(key("skin", Conversion::Normal), (1, 4));
+        assert_eq!(conversions, expected);
+        assert_eq!(registry.misses(), 3);
+        assert_eq!(registry.hits(), 3);
+
+        assert_eq!(registry.get("skin", Conversion::Copy).unwrap().width, 1);
+        assert_eq!(registry.get("skin", Conversion::Normal).unwrap().width, 4);
+        assert!(registry.get("skin", Conversion::Gloss).is_err());
+    }
+
+    #[test]
+    fn shares_errors() {
+        let registry = TextureRegistry::new();
+        let requests = vec![
+            (key("broken", Conversion::Copy), 1),
+            (key("broken", Conversion::Copy), 2),
+        ];
+        let conversions = convert(&registry, requests);
+        assert_eq!(conversions[&key("broken", Conversion::Copy)], (1, 1));
+        assert_eq!(registry.misses(), 1);
+        assert_eq!(registry.hits(), 1);
+        let err = registry.get("broken", Conversion::Copy).unwrap_err();
+        assert!(format!("{}", err).contains("invalid data"));
+    }
+}
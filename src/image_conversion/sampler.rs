@@ -18,4 +18,25 @@ impl Linear {
         let b = c01 * (1.0 - dx) + c11 * dx;
         (a * (1.0 - dy)) + (b * dy)
     }
+
+    /// Like `get_pixel`, but positions outside of the image wrap around, i.e. the image is
+    /// treated as tiling and the last texel is interpolated with the first one.
+    pub fn get_pixel_wrapping(&self, x: f32, y: f32) -> f32 {
+        let (width, height) = self.image.dimensions();
+        let x = x.rem_euclid(width as f32);
+        let y = y.rem_euclid(height as f32);
+        let dx = x.fract();
+        let dy = y.fract();
+        let x0 = x.floor() as u32 % width;
+        let y0 = y.floor() as u32 % height;
+        let x1 = (x0 + 1) % width;
+        let y1 = (y0 + 1) % height;
+        let c00 = self.image.get_pixel(x0, y0)[0];
+        let c10 = self.image.get_pixel(x1, y0)[0];
+        let c01 = self.image.get_pixel(x0, y1)[0];
+        let c11 = self.image.get_pixel(x1, y1)[0];
+        let a = c00 * (1.0 - dx) + c10 * dx;
+        let b = c01 * (1.0 - dx) + c11 * dx;
+        (a * (1.0 - dy)) + (b * dy)
+    }
 }
@@ -0,0 +1,147 @@
+//! Conversion of Telltale's spec-maps to the channel packing of the target (see `SpecularProfile`).
+
+use anyhow::{anyhow, Result};
+use image::{DynamicImage, GrayImage, Rgb, RgbImage, Rgba, RgbaImage};
+
+/// A material property that is stored in a channel of a converted spec-map.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Occlusion,
+    Roughness,
+    Metalness,
+    Specular,
+}
+
+/// A texture that is written for a spec-map.
+#[derive(Debug)]
+pub struct PackedTexture {
+    /// appended to the name of the spec-map, e.g. `_roughness`
+    pub suffix: &'static str,
+    /// the properties in the red, green, blue and alpha channel,
+    /// a texture with a single property is written as grayscale image
+    pub channels: &'static [Channel],
+}
+
+/// How the properties of the spec-maps are packed into textures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpecularProfile {
+    /// A single texture with R: occlusion, G: roughness, B: metalness as used by glTF
+    /// and A: specular for KHR_materials_specular.
+    GltfOrm,
+    /// R: occlusion, G: roughness, B: metalness as used by Unreal Engine
+    /// and the specular level as separate grayscale texture.
+    UnrealOrm,
+    /// Every property as separate grayscale texture.
+    Separate,
+}
+
+impl SpecularProfile {
+    /// Returns the textures that are written for a spec-map.
+    pub fn textures(self) -> &'static [PackedTexture] {
+        use Channel::*;
+        match self {
+            Self::GltfOrm => &[PackedTexture {
+                suffix: "",
+                channels: &[Occlusion, Roughness, Metalness, Specular],
+            }],
+            Self::UnrealOrm => &[
+                PackedTexture {
+                    suffix: "",
+                    channels: &[Occlusion, Roughness, Metalness],
+                },
+                PackedTexture {
+                    suffix: "_specular",
+                    channels: &[Specular],
+                },
+            ],
+            Self::Separate => &[
+                PackedTexture {
+                    suffix: "_occlusion",
+                    channels: &[Occlusion],
+                },
+                PackedTexture {
+                    suffix: "_roughness",
+                    channels: &[Roughness],
+                },
+                PackedTexture {
+                    suffix: "_metalness",
+                    channels: &[Metalness],
+                },
+                PackedTexture {
+                    suffix: "_specular",
+                    channels: &[Specular],
+                },
+            ],
+        }
+    }
+}
+
+/// Parses the name of a spec-map profile, i.e. `gltf-orm`, `unreal-orm` or `separate`.
+pub fn parse_profile(value: &str) -> Result<SpecularProfile> {
+    match value.to_ascii_lowercase().as_str() {
+        "gltf-orm" => Ok(SpecularProfile::GltfOrm),
+        "unreal-orm" => Ok(SpecularProfile::UnrealOrm),
+        "separate" => Ok(SpecularProfile::Separate),
+        _ => Err(anyhow!("unknown spec-map profile {}", value)),
+    }
+}
+
+/// Takes a (linear) spec-map from The Walking Dead: The Telltale Definitive Series and
+/// returns the properties in the order of `Channel`, i.e. R: occlusion, G: roughness,
+/// B: metalness and A: specular.
+pub fn decode(image: DynamicImage) -> Result<RgbaImage> {
+    if image.color().channel_count() < 3 {
+        return Err(anyhow!("unknown specular map format {:?}", image.color()));
+    }
+    let has_gloss = image.color().has_alpha();
+    let mut properties = image.to_rgba8();
+    properties.pixels_mut().for_each(|pixel| {
+        // The Telltale setup is internally:
+        // R: Specular (?)
+        // G: Metalness
+        // B: 1-Occlusion
+        // A: 1-Roughness (sometimes called glossy)
+        let [specular, metalness, cavity, gloss] = pixel.0;
+        // spec-maps without alpha channel have no gloss, i.e. they are fully rough
+        let roughness = if has_gloss { 255 - gloss } else { 255 };
+        *pixel = Rgba([255 - cavity, roughness, metalness, specular]);
+    });
+    Ok(properties)
+}
+
+/// Packs the properties (see `decode`) into the channels of the given texture.
+pub fn pack(properties: &RgbaImage, texture: &PackedTexture) -> DynamicImage {
+    let (width, height) = properties.dimensions();
+    let value = |x: u32, y: u32, channel: Channel| properties.get_pixel(x, y)[channel as usize];
+    match texture.channels {
+        [channel] => DynamicImage::ImageLuma8(GrayImage::from_fn(width, height, |x, y| {
+            [value(x, y, *channel)].into()
+        })),
+        [r, g, b] => DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+            Rgb([value(x, y, *r), value(x, y, *g), value(x, y, *b)])
+        })),
+        [r, g, b, a] => DynamicImage::ImageRgba8(RgbaImage::from_fn(width, height, |x, y| {
+            Rgba([
+                value(x, y, *r),
+                value(x, y, *g),
+                value(x, y, *b),
+                value(x, y, *a),
+            ])
+        })),
+        channels => unreachable!("packed texture with {} channels", channels.len()),
+    }
+}
+
+/// Converts a (linear) spec-map to the textures of the profile.
+/// Returns the images with the suffixes of their file names.
+pub fn convert(
+    image: DynamicImage,
+    profile: SpecularProfile,
+) -> Result<Vec<(&'static str, DynamicImage)>> {
+    let properties = decode(image)?;
+    Ok(profile
+        .textures()
+        .iter()
+        .map(|texture| (texture.suffix, pack(&properties, texture)))
+        .collect())
+}
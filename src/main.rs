@@ -7,6 +7,7 @@ mod d3dtx;
 mod export;
 mod image_conversion;
 mod logging;
+mod manifest;
 mod runtime_config;
 mod skeleton;
 
@@ -14,17 +15,29 @@ use std::{
     ffi::OsStr,
     fs::{self, File},
     io::Cursor,
-    path::Path,
+    path::{Path, PathBuf},
 };
 
 use anyhow::{anyhow, Context, Result};
 use checksum_mapping::ChecksumMap;
 use chrono::Local;
-use rayon::iter::{ParallelBridge, ParallelIterator};
+use image::{DynamicImage, GenericImageView};
+use manifest::{Inputs, Manifest, Outputs};
+use rayon::prelude::*;
 use runtime_config::Config;
 use skeleton::Skeleton;
 
-use crate::d3dmesh::textures::{TextureMap, TextureType};
+use crate::{
+    d3dmesh::textures::{TextureMap, TextureType},
+    export::TextureSettings,
+    image_conversion::{
+        alpha::AlphaUsage,
+        encoding::save_image,
+        orm::OrmSources,
+        registry::{JobKey, TextureRegistry},
+        Conversion, TextureFileFormat, TextureInfo, TextureInfos,
+    },
+};
 
 fn main() -> Result<()> {
     // Note: all CRC64 checksums are actually CRC64_ECMA_182!
@@ -57,8 +70,50 @@ fn main() -> Result<()> {
             .context("could not set default logging level")?;
     }
 
+    if !config.diagnose_d3dtx.is_empty() {
+        let diagnostics_folder = Path::new(output_folder).join("diagnostics");
+        std::fs::create_dir_all(&diagnostics_folder)?;
+        for path in &config.diagnose_d3dtx {
+            if let Err(err) = diagnose_d3dtx_file(Path::new(path), &diagnostics_folder) {
+                log::error!("Error: {}: {:?}", path, err);
+            }
+        }
+        return Ok(());
+    }
+
+    if let (Some(image_path), Some(reference_path)) = (&config.encode, &config.reference) {
+        let reference_path = Path::new(reference_path);
+        let file_name = reference_path
+            .file_name()
+            .ok_or(anyhow!("reference has no file name"))?;
+        let output_path = Path::new(output_folder).join(file_name);
+        encode_d3dtx_file(Path::new(image_path), reference_path, &output_path)
+            .with_context(|| format!("could not encode {} into a .d3dtx file", image_path))?;
+        log::info!("written {}", output_path.to_string_lossy());
+        return Ok(());
+    }
+
     // static mapping table
     let checksum_mapping = ChecksumMap::new();
+    // textures shared by several meshes are only converted once
+    let texture_registry = TextureRegistry::new();
+    let manifest = Manifest::load(&config).context("could not load manifest")?;
+
+    if !config.disable_d3dmesh_conversion || !config.disable_skl_conversion {
+        log::info!("converting textures...");
+        let requests = collect_texture_requests(&config, &checksum_mapping)
+            .context("could not collect the textures of the meshes")?;
+        texture_registry.convert_all(requests, |key, job| {
+            convert_texture_job(
+                &config,
+                &texture_registry,
+                &manifest,
+                &texture_folder_absolute,
+                key,
+                job,
+            )
+        });
+    }
 
     if !config.disable_d3dmesh_conversion {
         log::info!("converting *.d3dmesh files...");
@@ -79,11 +134,10 @@ fn main() -> Result<()> {
                         let err = handle_d3dmesh_file(
                             &path,
                             &config,
+                            &texture_registry,
+                            &manifest,
                             &checksum_mapping,
-                            input_folder,
                             texture_folder,
-                            &texture_folder_absolute,
-                            output_folder,
                         );
                         if let Err(err) = err {
                             log::error!("Error: {}: {:?}", path.to_string_lossy(), err);
@@ -117,11 +171,10 @@ fn main() -> Result<()> {
                         let err = handle_skl_file(
                             &skeleton_path,
                             &config,
+                            &texture_registry,
+                            &manifest,
                             &checksum_mapping,
-                            input_folder,
                             texture_folder,
-                            &texture_folder_absolute,
-                            output_folder,
                         );
                         if let Err(err) = err {
                             log::error!("Error: {}: {:?}", skeleton_path.to_string_lossy(), err);
@@ -134,6 +187,29 @@ fn main() -> Result<()> {
             });
     }
 
+    log::info!(
+        "textures: {} conversions, {} reused (of {} requests)",
+        texture_registry.misses(),
+        texture_registry.hits(),
+        texture_registry.misses() + texture_registry.hits()
+    );
+    if let Some(limit) = &config.size_limit {
+        log::info!(
+            "textures: {} downscaled to at most {}x{} pixels ({:?})",
+            texture_registry.downscaled(),
+            limit.max_size,
+            limit.max_size,
+            limit.downscaling
+        );
+    }
+    if config.incremental {
+        log::info!(
+            "{} textures, meshes and skeletons were up to date",
+            manifest.skipped()
+        );
+    }
+    manifest.save().context("could not save manifest")?;
+
     Ok(())
 }
 
@@ -141,11 +217,10 @@ fn main() -> Result<()> {
 fn handle_d3dmesh_file<P: AsRef<Path>>(
     path: P,
     config: &Config,
+    texture_registry: &TextureRegistry,
+    manifest: &Manifest,
     checksum_mapping: &ChecksumMap,
-    input_folder: &str,
     texture_folder: &str,
-    texture_folder_absolute: &Path,
-    output_folder: &str,
 ) -> Result<()> {
     let file = fs::read(&path).context("could not open d3dmesh file")?;
     let mut input = Cursor::new(file);
@@ -153,18 +228,29 @@ fn handle_d3dmesh_file<P: AsRef<Path>>(
     let mesh =
         d3dmesh::Data::parse(&mut input, &checksum_mapping).context("could not parse mesh data")?;
 
-    copy_textures(
-        &config,
-        input_folder,
-        &texture_folder_absolute,
+    let mut texture_infos = TextureInfos::new();
+    lookup_textures(
+        config,
+        texture_registry,
         &mesh.materials,
+        &mut texture_infos,
     )
     .context("could not copy textures from input to output")?;
 
     let mesh_name =
         get_file_name_from_path(path.as_ref()).context("could not get mesh file name")?;
-    create_gltf(&mesh, output_folder, texture_folder, mesh_name)
-        .context("could not create glTF 2.0 data")?;
+    manifest.get_or_convert(
+        &format!("mesh/{}", mesh_name),
+        || gltf_inputs(manifest, &[path.as_ref()], &texture_infos),
+        || {
+            let files = create_gltf(&mesh, config, texture_folder, &texture_infos, mesh_name)
+                .context("could not create glTF 2.0 data")?;
+            Ok(Outputs {
+                files,
+                texture_info: None,
+            })
+        },
+    )?;
 
     Ok(())
 }
@@ -173,11 +259,10 @@ fn handle_d3dmesh_file<P: AsRef<Path>>(
 fn handle_skl_file<P: AsRef<Path>>(
     skeleton_path: P,
     config: &Config,
+    texture_registry: &TextureRegistry,
+    manifest: &Manifest,
     checksum_mapping: &ChecksumMap,
-    input_folder: &str,
     texture_folder: &str,
-    texture_folder_absolute: &Path,
-    output_folder: &str,
 ) -> Result<()> {
     let skeleton_file_name = get_file_name_from_path(skeleton_path.as_ref())
         .context("could not get skeleton file name")?;
@@ -189,10 +274,12 @@ fn handle_skl_file<P: AsRef<Path>>(
         .context("could not parse skeleton data")?;
 
     let mut meshes_using_skeleton = Vec::new();
+    let mut mesh_paths = Vec::new();
+    let mut texture_infos = TextureInfos::new();
     // open all potential meshes that use the skeleton
     // the name of the skl-file is used to filter for theses
     // FIXME: only going by name is sometimes wrong: consider sk63_aj and sk63_ajWounded: seem to get merged
-    for entry in fs::read_dir(input_folder)? {
+    for entry in fs::read_dir(&config.input_folder)? {
         let entry = entry?;
         let path = entry.path();
 
@@ -220,153 +307,602 @@ fn handle_skl_file<P: AsRef<Path>>(
             };
 
             // handle the textures of the mesh file
-            copy_textures(
-                &config,
-                input_folder,
-                &texture_folder_absolute,
+            lookup_textures(
+                config,
+                texture_registry,
                 &mesh.materials,
+                &mut texture_infos,
             )
             .context("could not copy textures from input to output")?;
 
             meshes_using_skeleton.push((mesh_file_name.to_string(), mesh));
+            mesh_paths.push(path);
         }
     }
 
-    create_rigged_gltf(
-        &meshes_using_skeleton,
-        output_folder,
-        texture_folder,
-        skeleton_file_name,
-        &skeleton,
-    )
-    .context("could not create rigged glTF files")?;
+    let mut input_paths = vec![skeleton_path.as_ref()];
+    input_paths.extend(mesh_paths.iter().map(PathBuf::as_path));
+    manifest.get_or_convert(
+        &format!("skeleton/{}", skeleton_file_name),
+        || gltf_inputs(manifest, &input_paths, &texture_infos),
+        || {
+            let files = create_rigged_gltf(
+                &meshes_using_skeleton,
+                config,
+                texture_folder,
+                &texture_infos,
+                skeleton_file_name,
+                &skeleton,
+            )
+            .context("could not create rigged glTF files")?;
+            Ok(Outputs {
+                files,
+                texture_info: None,
+            })
+        },
+    )?;
 
     Ok(())
 }
 
+/// Returns the inputs of a glTF file for the manifest, i.e. the checksums of the input files and
+/// the properties of the used textures.
+fn gltf_inputs(
+    manifest: &Manifest,
+    paths: &[&Path],
+    texture_infos: &TextureInfos,
+) -> Result<Inputs> {
+    let mut inputs = Inputs::new();
+    for path in paths {
+        inputs.insert(
+            path.to_string_lossy().to_string(),
+            manifest.checksum_file(path)?,
+        );
+    }
+    for (name, info) in texture_infos {
+        inputs.insert(format!("texture/{}", name), format!("{:?}", info));
+    }
+    Ok(inputs)
+}
+
 /// Note: texture_folder is relative to the output_folder.
 /// E.g. output_folder = "output" and texture_folder = "textures" results in textures being in "output/textures".
 fn create_gltf(
     mesh: &d3dmesh::Data,
-    output_folder: &str,
+    config: &Config,
     texture_folder: &str,
+    texture_infos: &TextureInfos,
     mesh_name: &str,
-) -> Result<()> {
+) -> Result<Vec<PathBuf>> {
     let file_name_binary = format!("{}.bin", mesh_name);
     let file_name_json = format!("{}.gltf", mesh_name);
-    let dst_binary = File::create(format!("{}/{}", output_folder, file_name_binary))
-        .context("could not create binary glTF data file")?;
-    let dst_json = File::create(format!("{}/{}", output_folder, file_name_json))
-        .context("could not create JSON glTF data file")?;
+    let path_binary = Path::new(&config.output_folder).join(&file_name_binary);
+    let path_json = Path::new(&config.output_folder).join(&file_name_json);
+    let dst_binary =
+        File::create(&path_binary).context("could not create binary glTF data file")?;
+    let dst_json = File::create(&path_json).context("could not create JSON glTF data file")?;
+    let textures = TextureSettings {
+        folder: texture_folder,
+        format: config.texture_format,
+        specular_profile: config.conversion.specular,
+        infos: texture_infos,
+    };
 
     export::mesh_to_binary(
         dst_binary,
         file_name_binary,
         dst_json,
-        texture_folder,
-        output_folder,
+        &textures,
         mesh,
         Some(mesh_name.to_string()),
-    )
+    )?;
+    Ok(vec![path_json, path_binary])
 }
 
 /// Note: texture_folder is relative to the output_folder.
 /// E.g. output_folder = "output" and texture_folder = "textures" results in textures being in "output/textures".
 fn create_rigged_gltf(
     meshes: &[(String, d3dmesh::Data)],
-    output_folder: &str,
+    config: &Config,
     texture_folder: &str,
+    texture_infos: &TextureInfos,
     root_name: &str,
     skeleton: &Skeleton,
-) -> Result<()> {
+) -> Result<Vec<PathBuf>> {
     let file_name_binary = format!("{}.bin", root_name);
     let file_name_json = format!("{}.gltf", root_name);
-    let dst_binary = File::create(format!("{}/{}", output_folder, file_name_binary))
-        .context("could not create binary glTF data file")?;
-    let dst_json = File::create(format!("{}/{}", output_folder, file_name_json))
-        .context("could not create JSON glTF data file")?;
+    let path_binary = Path::new(&config.output_folder).join(&file_name_binary);
+    let path_json = Path::new(&config.output_folder).join(&file_name_json);
+    let dst_binary =
+        File::create(&path_binary).context("could not create binary glTF data file")?;
+    let dst_json = File::create(&path_json).context("could not create JSON glTF data file")?;
+    let textures = TextureSettings {
+        folder: texture_folder,
+        format: config.texture_format,
+        specular_profile: config.conversion.specular,
+        infos: texture_infos,
+    };
 
     export::rigged_object_to_binary(
         dst_binary,
         file_name_binary,
         dst_json,
-        texture_folder,
-        output_folder,
+        &textures,
         Some(root_name.to_string()),
         meshes,
         skeleton,
-    )
+    )?;
+    Ok(vec![path_json, path_binary])
 }
 
-/// Copy necessary textures for the materials in the mesh.
-/// Note: the texture_folder needs to be "absolute" (or relative to the executable).
-/// i.e. including the output folder.
-fn copy_textures(
+/// A texture conversion that is requested by a material (see `texture_requests`).
+enum TextureJob {
+    /// a single texture, whose settings that depend on the kind of texture (its default color
+    /// space and whether a height map is generated) are taken from the first request
+    Single(d3dmesh::textures::Texture),
+    /// the sources of an ORM texture (see `OrmSources`)
+    Orm {
+        occlusion: Option<d3dmesh::textures::Texture>,
+        gloss: Option<d3dmesh::textures::Texture>,
+        specular: Option<d3dmesh::textures::Texture>,
+    },
+}
+
+/// Returns the texture conversions that are needed for the materials.
+fn texture_requests(
     config: &Config,
-    input_folder: &str,
-    texture_folder_absolute: &Path,
     materials: &[d3dmesh::materials::Material],
-) -> Result<()> {
-    // copy necessary textures
+) -> Vec<(JobKey, TextureJob)> {
+    let mut requests = Vec::new();
     for material in materials {
         for texture in &material.textures {
-            if texture.map == TextureMap::Map || texture.map == TextureMap::MapA {
-                let texture_name = Path::new(&texture.name);
-                let texture_path = texture_name.with_extension("png");
-                let from = Path::new(input_folder).join(&texture_name);
-                let to = Path::new(texture_folder_absolute).join(&texture_path);
-
-                match texture.kind {
-                    // simply copy textures without any conversion:
-                    TextureType::Diffuse
-                    | TextureType::Detail
-                    | TextureType::Ink
-                    | TextureType::Height => {
-                        image_conversion::copy_texture(&from, &to)
-                            .context(format!("could not copy texture: {}", &texture.name,))?;
-                    }
-                    // textures that need conversion:
-                    TextureType::Normal => {
-                        let new_normal = image_conversion::normal_map(&from).context(format!(
-                            "could not convert normal map texture: {} (expected it in {:?})",
-                            &texture.name, from,
-                        ))?;
-                        new_normal
-                            .save(to)
-                            .context("could not save new normal map")?;
-
-                        // create displacement/height map from the normal map
-                        if config.enable_height_map {
-                            let height_path = texture_name
-                                .file_stem()
-                                .unwrap()
-                                .to_str()
-                                .unwrap()
-                                .trim_end_matches("_nm");
-                            let height_path = Path::new(texture_folder_absolute).join(
-                                Path::new(&format!("{}_height", height_path)).with_extension("png"),
-                            );
-                            let height_map = image_conversion::height::normal_to_height(new_normal);
-                            height_map
-                                .save(height_path)
-                                .context("could not save new height map")?;
-                        }
-                    }
-                    TextureType::Specular => {
-                        let new_specular =
-                            image_conversion::specular_map(&from).context(format!(
-                                "could not convert specular map texture: {} (expected it in {:?})",
-                                &texture.name, from,
-                            ))?;
-                        new_specular
-                            .save(to)
-                            .context("could not save new specular map")?;
+            // Note: textures with unresolved names can not be found in the input folder
+            if texture.name.is_empty() {
+                continue;
+            }
+            if let Some(conversion) = Conversion::for_texture_type(&texture.kind) {
+                requests.push((
+                    (texture.name.clone(), conversion),
+                    TextureJob::Single(texture.clone()),
+                ));
+            }
+        }
+        if config.compose_orm {
+            if let Some(sources) = OrmSources::of_material(material) {
+                requests.push((
+                    (sources.name(), Conversion::Orm),
+                    TextureJob::Orm {
+                        occlusion: sources.occlusion.cloned(),
+                        gloss: sources.gloss.cloned(),
+                        specular: sources.specular.cloned(),
+                    },
+                ));
+            }
+        }
+    }
+    requests
+}
+
+/// Collects the texture conversions of all meshes in the input folder that are converted, i.e. of
+/// all meshes if d3dmesh conversion is enabled and of the meshes of the skeletons otherwise.
+/// The meshes are visited in the order of their paths, so that the first request of a texture
+/// (whose job is converted, see `TextureRegistry::convert_all`) is the same in every run.
+fn collect_texture_requests(
+    config: &Config,
+    checksum_mapping: &ChecksumMap,
+) -> Result<Vec<(JobKey, TextureJob)>> {
+    let mut paths = Vec::new();
+    let mut skeleton_names = Vec::new();
+    for entry in fs::read_dir(&config.input_folder).context("could not read input folder")? {
+        let path = entry?.path();
+        match path.extension().and_then(OsStr::to_str) {
+            Some("d3dmesh") => paths.push(path),
+            Some("skl") => skeleton_names.push(get_file_name_from_path(&path)?.to_string()),
+            _ => {}
+        }
+    }
+    paths.sort();
+    // the same filter as in `handle_skl_file`
+    let is_converted = |path: &Path| {
+        let stem = path.file_stem().and_then(OsStr::to_str).unwrap_or("");
+        !config.disable_d3dmesh_conversion
+            || (!config.disable_skl_conversion
+                && skeleton_names
+                    .iter()
+                    .any(|name| stem.starts_with(name.as_str())))
+    };
+
+    // Note: meshes that can not be parsed are skipped, their conversion reports the error
+    let requests: Vec<_> = paths
+        .par_iter()
+        .filter(|path| is_converted(path))
+        .map(|path| {
+            let file = fs::read(path).ok()?;
+            let mesh = d3dmesh::Data::parse(&mut Cursor::new(file), checksum_mapping).ok()?;
+            Some(texture_requests(config, &mesh.materials))
+        })
+        .collect();
+
+    Ok(requests.into_iter().flatten().flatten().collect())
+}
+
+/// Runs a texture conversion, which is skipped if its outputs are up to date (see `Manifest`).
+/// Returns the properties of the converted texture.
+fn convert_texture_job(
+    config: &Config,
+    registry: &TextureRegistry,
+    manifest: &Manifest,
+    texture_folder_absolute: &Path,
+    (name, conversion): &JobKey,
+    job: TextureJob,
+) -> Result<TextureInfo> {
+    let input_folder = &config.input_folder;
+    let outputs = match job {
+        TextureJob::Single(texture) => {
+            let from = Path::new(input_folder).join(&texture.name);
+            manifest.get_or_convert(
+                &format!("texture/{}/{:?}", name, conversion),
+                || {
+                    let mut inputs = Inputs::new();
+                    inputs.insert(
+                        from.to_string_lossy().to_string(),
+                        manifest.checksum_file(&from)?,
+                    );
+                    inputs.insert("kind".to_string(), format!("{:?}", texture.kind));
+                    Ok(inputs)
+                },
+                || {
+                    convert_texture(
+                        config,
+                        registry,
+                        &from,
+                        texture_folder_absolute,
+                        &texture,
+                        *conversion,
+                    )
+                },
+            )?
+        }
+        TextureJob::Orm {
+            occlusion,
+            gloss,
+            specular,
+        } => {
+            let sources = OrmSources {
+                occlusion: occlusion.as_ref(),
+                gloss: gloss.as_ref(),
+                specular: specular.as_ref(),
+            };
+            manifest.get_or_convert(
+                &format!("texture/{}/{:?}", name, conversion),
+                || {
+                    let mut inputs = Inputs::new();
+                    for texture in sources.textures() {
+                        let from = Path::new(input_folder).join(&texture.name);
+                        inputs.insert(
+                            from.to_string_lossy().to_string(),
+                            manifest.checksum_file(&from)?,
+                        );
                     }
-                    _ => {}
+                    Ok(inputs)
+                },
+                || compose_orm_texture(config, input_folder, texture_folder_absolute, &sources),
+            )?
+        }
+    };
+    outputs
+        .texture_info
+        .ok_or(anyhow!("texture conversion did not return any properties"))
+}
+
+/// Adds the properties of the textures of the materials to `texture_infos`.
+/// The textures were already converted (see `collect_texture_requests`).
+fn lookup_textures(
+    config: &Config,
+    registry: &TextureRegistry,
+    materials: &[d3dmesh::materials::Material],
+    texture_infos: &mut TextureInfos,
+) -> Result<()> {
+    for ((name, conversion), _) in texture_requests(config, materials) {
+        let info = registry.get(&name, conversion)?;
+        texture_infos.insert(name, info);
+    }
+    Ok(())
+}
+
+/// Composes the occlusion, gloss and spec-map of a material into one ORM texture
+/// (see `image_conversion::orm::compose`) and writes it to the texture folder.
+/// Returns the written file and the properties of the composed texture.
+fn compose_orm_texture(
+    config: &Config,
+    input_folder: &str,
+    texture_folder_absolute: &Path,
+    sources: &OrmSources,
+) -> Result<Outputs> {
+    let open = |texture: Option<&d3dmesh::textures::Texture>| {
+        texture
+            .map(|texture| {
+                let from = Path::new(input_folder).join(&texture.name);
+                let d3dtx_texture = image_conversion::open_texture(&from, &texture.kind)
+                    .context(format!("could not open/decode texture: {}", &texture.name))?;
+                match &config.size_limit {
+                    Some(limit) => image_conversion::downscale::limit_size(d3dtx_texture, limit)
+                        .map(|(d3dtx_texture, _)| d3dtx_texture)
+                        .context(format!("could not downscale texture: {}", &texture.name)),
+                    None => Ok(d3dtx_texture),
+                }
+            })
+            .transpose()
+    };
+    let occlusion = open(sources.occlusion)?;
+    let gloss = open(sources.gloss)?;
+    let specular = open(sources.specular)?;
+    let orm = image_conversion::orm::compose(occlusion.as_ref(), gloss.as_ref(), specular.as_ref())
+        .context(format!("could not compose ORM texture: {}", sources.name()))?;
+
+    let first = occlusion.iter().chain(&gloss).chain(&specular).next();
+    let info = TextureInfo {
+        width: orm.width(),
+        height: orm.height(),
+        format_code: first.map_or(0, |texture| texture.format_code),
+        alpha: AlphaUsage::Opaque,
+        file_format: image_conversion::orm::file_format(config.texture_format),
+    };
+    let file_format = info.file_format;
+    let to = texture_folder_absolute.join(sources.path(file_format));
+    save_image(
+        &DynamicImage::ImageRgb8(orm),
+        &to,
+        file_format,
+        &config.image_encoding,
+    )
+    .context("could not save new ORM texture")?;
+    Ok(Outputs {
+        files: vec![to],
+        texture_info: Some(info),
+    })
+}
+
+/// Converts a single texture and writes it to the texture folder.
+/// Returns the written files and the properties of the decoded texture.
+fn convert_texture(
+    config: &Config,
+    registry: &TextureRegistry,
+    from: &Path,
+    texture_folder_absolute: &Path,
+    texture: &d3dmesh::textures::Texture,
+    conversion: Conversion,
+) -> Result<Outputs> {
+    let d3dtx_texture = image_conversion::open_texture(from, &texture.kind)
+        .context(format!("could not open/decode texture: {}", &texture.name))?;
+    let mut info = TextureInfo::new(&d3dtx_texture);
+    let (d3dtx_texture, downscaled) = match &config.size_limit {
+        Some(limit) => image_conversion::downscale::limit_size(d3dtx_texture, limit)
+            .context(format!("could not downscale texture: {}", &texture.name))?,
+        None => (d3dtx_texture, false),
+    };
+    if downscaled {
+        registry.count_downscaled();
+        info.width = d3dtx_texture.image.width();
+        info.height = d3dtx_texture.image.height();
+        // filtering blurs the edges of cutouts, which may turn them translucent
+        info.alpha = image_conversion::alpha::analyze_alpha(&d3dtx_texture.image);
+    }
+    log::debug!(
+        "texture {}: {}x{}, {:?}, alpha = {:?}",
+        &texture.name,
+        info.width,
+        info.height,
+        d3dtx::TextureFormat::parse(info.format_code),
+        info.alpha
+    );
+
+    let texture_name = Path::new(&texture.name);
+    let file_format = config
+        .texture_format
+        .for_texture(conversion, &texture.kind, info.alpha);
+    info.file_format = file_format;
+    let texture_path = texture_name.with_extension(file_format.extension());
+    let to = Path::new(texture_folder_absolute).join(&texture_path);
+    let mut files = Vec::new();
+    let encoding = &config.image_encoding;
+
+    match conversion {
+        // simply copy textures without any conversion:
+        Conversion::Copy => {
+            match file_format {
+                TextureFileFormat::Png | TextureFileFormat::Webp | TextureFileFormat::Jpeg => {
+                    image_conversion::copy_texture(&d3dtx_texture, &to, file_format, encoding)
+                        .context(format!("could not copy texture: {}", &texture.name,))?
+                }
+                TextureFileFormat::Dds => {
+                    image_conversion::copy_texture_dds(&d3dtx_texture, &to)
+                        .context(format!("could not copy texture data: {}", &texture.name,))?
+                }
+                TextureFileFormat::Ktx2 => {
+                    image_conversion::copy_texture_ktx2(&d3dtx_texture, &to, config.ktx2_zlib)
+                        .context(format!("could not copy texture data: {}", &texture.name,))?;
+                    // the PNG is the fallback of the KTX2 image (see `add_image_and_texture`)
+                    let png = to.with_extension(TextureFileFormat::Png.extension());
+                    image_conversion::copy_texture(
+                        &d3dtx_texture,
+                        &png,
+                        TextureFileFormat::Png,
+                        encoding,
+                    )
+                    .context(format!("could not copy texture: {}", &texture.name,))?;
+                    files.push(png);
                 }
             }
+            files.push(to.clone());
+        }
+        // textures that need conversion:
+        Conversion::Normal => {
+            let mut new_normal =
+                image_conversion::normal_map(&d3dtx_texture, config.conversion.normal.source)
+                    .context(format!(
+                        "could not convert normal map texture: {}",
+                        &texture.name
+                    ))?;
+            if downscaled {
+                image_conversion::downscale::renormalize(&mut new_normal);
+            }
+            save_image(
+                &DynamicImage::ImageRgb8(config.conversion.normal.convention.apply(&new_normal)),
+                &to,
+                file_format,
+                encoding,
+            )
+            .context("could not save new normal map")?;
+            files.push(to.clone());
+
+            // create displacement/height map from the (primary) normal map
+            if config.enable_height_map
+                && texture.kind == TextureType::Normal
+                && matches!(texture.map, TextureMap::Map | TextureMap::MapA)
+            {
+                let height_path = texture_name
+                    .file_stem()
+                    .unwrap()
+                    .to_str()
+                    .unwrap()
+                    .trim_end_matches("_nm");
+                let height_path = Path::new(texture_folder_absolute)
+                    .join(Path::new(&format!("{}_height", height_path)).with_extension("png"));
+                let height_map =
+                    image_conversion::height::normal_to_height(new_normal, &config.height_map);
+                save_image(
+                    &DynamicImage::ImageLuma8(height_map),
+                    &height_path,
+                    TextureFileFormat::Png,
+                    encoding,
+                )
+                .context("could not save new height map")?;
+                files.push(height_path);
+            }
+        }
+        Conversion::Specular => {
+            let packed = image_conversion::specular_map(&d3dtx_texture, config.conversion.specular)
+                .context(format!(
+                    "could not convert specular map texture: {}",
+                    &texture.name
+                ))?;
+            for (suffix, image) in packed {
+                let path = image_conversion::with_suffix(&to, suffix);
+                save_image(&image, &path, file_format, encoding)
+                    .context("could not save new specular map")?;
+                files.push(path);
+            }
+        }
+        Conversion::Gloss => {
+            let new_roughness = image_conversion::gloss_map(&d3dtx_texture).context(format!(
+                "could not convert gloss map texture: {}",
+                &texture.name
+            ))?;
+            save_image(
+                &DynamicImage::ImageRgb8(new_roughness),
+                &to,
+                file_format,
+                encoding,
+            )
+            .context("could not save new roughness map")?;
+            files.push(to.clone());
+        }
+        Conversion::Orm => {
+            return Err(anyhow!(
+                "ORM textures are composed of several textures (see compose_orm_texture)"
+            ))
         }
     }
+
+    if config.export_faces {
+        files.extend(
+            image_conversion::export_surfaces(
+                &d3dtx_texture,
+                &to,
+                conversion,
+                &config.conversion,
+                encoding,
+            )
+            .context(format!(
+                "could not export faces of texture: {}",
+                &texture.name
+            ))?,
+        );
+    }
+    if config.export_mips {
+        files.extend(
+            image_conversion::export_mips(
+                &d3dtx_texture,
+                &to,
+                conversion,
+                &config.conversion,
+                encoding,
+            )
+            .context(format!(
+                "could not export mip-maps of texture: {}",
+                &texture.name
+            ))?,
+        );
+    }
+    Ok(Outputs {
+        files,
+        texture_info: Some(info),
+    })
+}
+
+/// Decodes a .d3dtx file with every plausible format, logs the scores and writes the decoded
+/// images to the diagnostics folder.
+fn diagnose_d3dtx_file(path: &Path, diagnostics_folder: &Path) -> Result<()> {
+    let data = fs::read(path).context("could not read file")?;
+    let diagnosis =
+        d3dtx::diagnostics::diagnose(Cursor::new(data)).context("could not diagnose texture")?;
+    log::info!(
+        "{}: {}x{}, format code {} ({:#04X}) is mapped to {}, {} bytes in the biggest mip-map",
+        diagnosis.name,
+        diagnosis.width,
+        diagnosis.height,
+        diagnosis.format_code,
+        diagnosis.format_code,
+        diagnosis.mapped_format,
+        diagnosis.data_size,
+    );
+    if diagnosis.candidates.is_empty() {
+        log::warn!("no format could decode the texture");
+    }
+
+    let file_name = get_file_name_from_path(path)?;
+    for (rank, candidate) in diagnosis.candidates.iter().enumerate() {
+        log::info!(
+            "{:>2}. {:<28} score = {:>7.3}, block discontinuity = {:>7.3}, noise = {:.3}, alpha plausibility = {:.3}",
+            rank + 1,
+            candidate.format,
+            candidate.score,
+            candidate.block_discontinuity,
+            candidate.noise,
+            candidate.alpha_plausibility,
+        );
+        let image_path = diagnostics_folder.join(format!(
+            "{}.{}.{}.png",
+            file_name,
+            rank + 1,
+            candidate.format.replace(|c: char| !c.is_alphanumeric(), "")
+        ));
+        candidate
+            .image
+            .save(&image_path)
+            .with_context(|| format!("could not save {}", image_path.to_string_lossy()))?;
+    }
+    Ok(())
+}
+
+/// Encodes the image into a .d3dtx file based on the reference texture.
+fn encode_d3dtx_file(image_path: &Path, reference_path: &Path, output_path: &Path) -> Result<()> {
+    let image = image::open(image_path).context("could not read image")?;
+    let reference = fs::read(reference_path).context("could not read reference texture")?;
+    let mut data = Vec::new();
+    d3dtx::writer::write_d3dtx(&image, &reference, &mut data)?;
+    fs::write(output_path, data).context("could not write texture")?;
     Ok(())
 }
 
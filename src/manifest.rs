@@ -0,0 +1,496 @@
+//! The manifest of an incremental conversion (see `--incremental`).
+//!
+//! The manifest is stored as `manifest.json` in the output folder and records the inputs
+//! (e.g. CRC64 checksums of files) and outputs of every converted texture, mesh and skeleton.
+//! A conversion is skipped if its inputs did not change since the previous run and all of its
+//! outputs still exist. The whole manifest is discarded if the version of this program or of the
+//! manifest format or one of the options that affect the outputs changed.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use anyhow::{anyhow, Context, Result};
+use crc::{Crc, CRC_64_ECMA_182};
+use serde_json::{json, Map, Value};
+
+use crate::{
+    image_conversion::{alpha::AlphaUsage, TextureInfo},
+    runtime_config::Config,
+};
+
+const FILE_NAME: &str = "manifest.json";
+/// The version of the structure of the manifest and its keys, which has to be increased whenever
+/// they change, since the version of this program is not increased for every change.
+const FORMAT_VERSION: u64 = 2;
+
+/// Fingerprints of the inputs of a conversion, e.g. the checksum of an input file by its path.
+pub type Inputs = BTreeMap<String, String>;
+
+/// The results of a conversion.
+#[derive(Debug, Clone, Default)]
+pub struct Outputs {
+    /// all written files
+    pub files: Vec<PathBuf>,
+    /// the properties of converted textures, which are needed even if the conversion is skipped
+    pub texture_info: Option<TextureInfo>,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    inputs: Inputs,
+    outputs: Outputs,
+}
+
+pub struct Manifest {
+    path: PathBuf,
+    /// `false` if the conversion is not incremental, i.e. nothing is skipped or recorded
+    enabled: bool,
+    /// the options that affect the outputs (see `options`)
+    options: Value,
+    /// the entries of the previous run
+    previous: HashMap<String, Entry>,
+    /// the entries of this run
+    current: Mutex<HashMap<String, Entry>>,
+    /// the checksums of all files that were hashed so far
+    file_checksums: Mutex<HashMap<PathBuf, String>>,
+    skipped: AtomicUsize,
+}
+
+impl Manifest {
+    /// Loads the manifest of the previous run from the output folder if the conversion is incremental.
+    /// The previous entries are ignored if `--force` is set, the manifest is outdated or invalid.
+    pub fn load(config: &Config) -> Result<Self> {
+        let mut manifest = Self {
+            path: Path::new(&config.output_folder).join(FILE_NAME),
+            enabled: config.incremental,
+            options: options(config),
+            previous: HashMap::new(),
+            current: Mutex::new(HashMap::new()),
+            file_checksums: Mutex::new(HashMap::new()),
+            skipped: AtomicUsize::new(0),
+        };
+        if !manifest.enabled || config.force || !manifest.path.exists() {
+            return Ok(manifest);
+        }
+
+        let data = fs::read(&manifest.path).context("could not read manifest")?;
+        let json: Value = match serde_json::from_slice(&data) {
+            Ok(json) => json,
+            Err(err) => {
+                log::warn!("ignoring invalid manifest: {}", err);
+                return Ok(manifest);
+            }
+        };
+        if json["version"] != env!("CARGO_PKG_VERSION")
+            || json["formatVersion"] != FORMAT_VERSION
+            || json["options"] != manifest.options
+        {
+            log::info!("manifest was written by another version or with other options, converting everything");
+            return Ok(manifest);
+        }
+        match parse_entries(&json["entries"]) {
+            Ok(entries) => manifest.previous = entries,
+            Err(err) => log::warn!("ignoring invalid manifest: {:?}", err),
+        }
+        Ok(manifest)
+    }
+
+    /// Returns the outputs of the conversion with the given key (e.g. `mesh/name`) without
+    /// running `convert`, if the inputs are the same as in the previous run and all its output
+    /// files still exist. Otherwise, `convert` is run and its outputs are recorded.
+    /// The inputs are only determined if the conversion is incremental.
+    pub fn get_or_convert<I, F>(&self, key: &str, inputs: I, convert: F) -> Result<Outputs>
+    where
+        I: FnOnce() -> Result<Inputs>,
+        F: FnOnce() -> Result<Outputs>,
+    {
+        if !self.enabled {
+            return convert();
+        }
+        let inputs = inputs().context("could not determine inputs")?;
+        if let Some(previous) = self.previous.get(key) {
+            if previous.inputs == inputs && previous.outputs.files.iter().all(|file| file.exists())
+            {
+                log::debug!("{} is up to date", key);
+                self.skipped.fetch_add(1, Ordering::Relaxed);
+                self.current
+                    .lock()
+                    .unwrap_or_else(|err| err.into_inner())
+                    .insert(key.to_string(), previous.clone());
+                return Ok(previous.outputs.clone());
+            }
+        }
+
+        let outputs = convert()?;
+        self.current
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .insert(
+                key.to_string(),
+                Entry {
+                    inputs,
+                    outputs: outputs.clone(),
+                },
+            );
+        Ok(outputs)
+    }
+
+    /// Returns the CRC64 checksum of the content of the file as hexadecimal string.
+    /// Every file is only read once per run.
+    pub fn checksum_file<P: AsRef<Path>>(&self, path: P) -> Result<String> {
+        let path = path.as_ref();
+        if let Some(checksum) = self
+            .file_checksums
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .get(path)
+        {
+            return Ok(checksum.clone());
+        }
+        let data =
+            fs::read(path).with_context(|| format!("could not read {}", path.to_string_lossy()))?;
+        let checksum = format!("{:016x}", Crc::<u64>::new(&CRC_64_ECMA_182).checksum(&data));
+        self.file_checksums
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .insert(path.to_path_buf(), checksum.clone());
+        Ok(checksum)
+    }
+
+    /// The number of conversions that were skipped, since their outputs were up to date.
+    pub fn skipped(&self) -> usize {
+        self.skipped.load(Ordering::Relaxed)
+    }
+
+    /// Writes the manifest to the output folder if the conversion is incremental.
+    /// Entries of the previous run that were not needed in this run (e.g. since the conversion
+    /// of skeletons was disabled) are kept.
+    pub fn save(&self) -> Result<()> {
+        if !self.enabled {
+            return Ok(());
+        }
+        let current = self.current.lock().unwrap_or_else(|err| err.into_inner());
+        let mut entries = Map::new();
+        for (key, entry) in self.previous.iter().chain(current.iter()) {
+            entries.insert(key.clone(), entry_to_json(entry));
+        }
+        let json = json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "formatVersion": FORMAT_VERSION,
+            "options": self.options,
+            "entries": entries,
+        });
+        fs::write(&self.path, serde_json::to_vec_pretty(&json)?).context("could not write manifest")
+    }
+}
+
+/// The options of the configuration that affect the outputs.
+fn options(config: &Config) -> Value {
+    json!({
+        "textureFormat": config.texture_format.extension(),
+        "ktx2Zlib": config.ktx2_zlib,
+        "pngCompression": format!("{:?}", config.image_encoding.png_compression),
+        "pngFilter": format!("{:?}", config.image_encoding.png_filter),
+        "jpegQuality": config.image_encoding.jpeg_quality,
+        "sizeLimit": config.size_limit.map(|limit| format!("{:?}", limit)),
+        "heightMap": config.enable_height_map,
+        "heightMapSettings": format!("{:?}", config.height_map),
+        "normalMap": format!("{:?}", config.conversion.normal),
+        "specularProfile": format!("{:?}", config.conversion.specular),
+        "exportMips": config.export_mips,
+        "exportFaces": config.export_faces,
+        "composeOrm": config.compose_orm,
+    })
+}
+
+fn entry_to_json(entry: &Entry) -> Value {
+    let files: Vec<String> = entry
+        .outputs
+        .files
+        .iter()
+        .map(|file| file.to_string_lossy().to_string())
+        .collect();
+    let texture_info = entry.outputs.texture_info.map(|info| {
+        json!({
+            "width": info.width,
+            "height": info.height,
+            "formatCode": info.format_code,
+            "alpha": match info.alpha {
+                AlphaUsage::Opaque => json!("opaque"),
+                AlphaUsage::Cutout { cutoff } => json!({ "cutoff": cutoff }),
+                AlphaUsage::Translucent => json!("translucent"),
+            },
+            "fileFormat": info.file_format.extension(),
+        })
+    });
+    json!({
+        "inputs": entry.inputs,
+        "outputs": files,
+        "texture": texture_info,
+    })
+}
+
+fn parse_entries(json: &Value) -> Result<HashMap<String, Entry>> {
+    let invalid = || anyhow!("invalid entry");
+    let mut entries = HashMap::new();
+    for (key, entry) in json.as_object().ok_or_else(invalid)? {
+        let inputs = entry["inputs"]
+            .as_object()
+            .ok_or_else(invalid)?
+            .iter()
+            .map(|(name, value)| {
+                Ok((
+                    name.clone(),
+                    value.as_str().ok_or_else(invalid)?.to_string(),
+                ))
+            })
+            .collect::<Result<Inputs>>()?;
+        let files = entry["outputs"]
+            .as_array()
+            .ok_or_else(invalid)?
+            .iter()
+            .map(|file| Ok(PathBuf::from(file.as_str().ok_or_else(invalid)?)))
+            .collect::<Result<Vec<_>>>()?;
+        let texture_info = match &entry["texture"] {
+            Value::Null => None,
+            info => {
+                let number = |name: &str| -> Result<u32> {
+                    Ok(info[name].as_u64().ok_or_else(invalid)? as u32)
+                };
+                Some(TextureInfo {
+                    width: number("width")?,
+                    height: number("height")?,
+                    format_code: number("formatCode")?,
+                    alpha: match &info["alpha"] {
+                        Value::String(alpha) if alpha == "opaque" => AlphaUsage::Opaque,
+                        Value::String(alpha) if alpha == "translucent" => AlphaUsage::Translucent,
+                        alpha => AlphaUsage::Cutout {
+                            cutoff: alpha["cutoff"].as_f64().ok_or_else(invalid)? as f32,
+                        },
+                    },
+                    file_format: info["fileFormat"].as_str().ok_or_else(invalid)?.parse()?,
+                })
+            }
+        };
+        entries.insert(
+            key.clone(),
+            Entry {
+                inputs,
+                outputs: Outputs {
+                    files,
+                    texture_info,
+                },
+            },
+        );
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use image::codecs::png::{CompressionType, FilterType};
+
+    use super::*;
+    use crate::image_conversion::{
+        encoding::ImageEncoding,
+        height::{HeightAlgorithm, HeightSettings},
+        normal::{NormalConvention, NormalSettings},
+        specular::SpecularProfile,
+        ConversionSettings, TextureFileFormat,
+    };
+
+    /// An empty output folder in the temp directory, which is removed when it is dropped.
+    struct TempFolder(PathBuf);
+
+    impl TempFolder {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "d3dmesh-to-gltf-{}-manifest-{}",
+                std::process::id(),
+                name
+            ));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempFolder {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn config(folder: &TempFolder) -> Config {
+        Config {
+            disable_d3dmesh_conversion: false,
+            disable_skl_conversion: false,
+            enable_height_map: false,
+            height_map: HeightSettings {
+                algorithm: HeightAlgorithm::Poisson,
+                strength: 1.0,
+                tiling: false,
+            },
+            export_mips: false,
+            export_faces: false,
+            compose_orm: false,
+            texture_format: TextureFileFormat::Png,
+            conversion: ConversionSettings {
+                normal: NormalSettings {
+                    source: None,
+                    convention: NormalConvention::OpenGl,
+                },
+                specular: SpecularProfile::GltfOrm,
+            },
+            ktx2_zlib: false,
+            image_encoding: ImageEncoding {
+                png_compression: CompressionType::Fast,
+                png_filter: FilterType::Sub,
+                jpeg_quality: 90,
+            },
+            size_limit: None,
+            incremental: true,
+            force: false,
+            input_folder: folder.0.to_string_lossy().to_string(),
+            output_folder: folder.0.to_string_lossy().to_string(),
+            verbose: false,
+            diagnose_d3dtx: Vec::new(),
+            encode: None,
+            reference: None,
+        }
+    }
+
+    const TEXTURE_INFO: TextureInfo = TextureInfo {
+        width: 64,
+        height: 32,
+        format_code: 66,
+        alpha: AlphaUsage::Cutout { cutoff: 0.5 },
+        file_format: TextureFileFormat::Png,
+    };
+
+    /// Runs the conversion of `name`, whose input is the file `name.d3dtx` and output `name.png`
+    /// in the folder. Returns the outputs and whether the conversion was run.
+    fn convert(manifest: &Manifest, folder: &TempFolder, name: &str) -> (Outputs, bool) {
+        let input = folder.0.join(format!("{}.d3dtx", name));
+        let output = folder.0.join(format!("{}.png", name));
+        let converted = Cell::new(false);
+        let outputs = manifest
+            .get_or_convert(
+                &format!("texture/{}", name),
+                || {
+                    let mut inputs = Inputs::new();
+                    inputs.insert(name.to_string(), manifest.checksum_file(&input)?);
+                    Ok(inputs)
+                },
+                || {
+                    converted.set(true);
+                    fs::write(&output, b"png")?;
+                    Ok(Outputs {
+                        files: vec![output.clone()],
+                        texture_info: Some(TEXTURE_INFO),
+                    })
+                },
+            )
+            .unwrap();
+        (outputs, converted.get())
+    }
+
+    /// Converts `name` once and saves the manifest.
+    fn convert_and_save(config: &Config, folder: &TempFolder, name: &str) {
+        fs::write(folder.0.join(format!("{}.d3dtx", name)), b"d3dtx").unwrap();
+        let manifest = Manifest::load(config).unwrap();
+        assert!(convert(&manifest, folder, name).1);
+        manifest.save().unwrap();
+    }
+
+    #[test]
+    fn skips_unchanged_inputs() {
+        let folder = TempFolder::new("unchanged");
+        let config = config(&folder);
+        convert_and_save(&config, &folder, "a");
+
+        let manifest = Manifest::load(&config).unwrap();
+        let (outputs, converted) = convert(&manifest, &folder, "a");
+        assert!(!converted);
+        assert_eq!(manifest.skipped(), 1);
+        assert_eq!(outputs.files, [folder.0.join("a.png")]);
+        let texture_info = outputs.texture_info.unwrap();
+        assert_eq!(texture_info.width, 64);
+        assert_eq!(texture_info.height, 32);
+        assert_eq!(texture_info.format_code, 66);
+        assert_eq!(texture_info.alpha, AlphaUsage::Cutout { cutoff: 0.5 });
+        assert_eq!(texture_info.file_format, TextureFileFormat::Png);
+    }
+
+    #[test]
+    fn reconverts_changed_checksums() {
+        let folder = TempFolder::new("checksum");
+        let config = config(&folder);
+        convert_and_save(&config, &folder, "a");
+
+        fs::write(folder.0.join("a.d3dtx"), b"changed").unwrap();
+        let manifest = Manifest::load(&config).unwrap();
+        assert!(convert(&manifest, &folder, "a").1);
+        assert_eq!(manifest.skipped(), 0);
+    }
+
+    #[test]
+    fn reconverts_changed_options() {
+        let folder = TempFolder::new("options");
+        let mut config = config(&folder);
+        convert_and_save(&config, &folder, "a");
+
+        config.ktx2_zlib = true;
+        let manifest = Manifest::load(&config).unwrap();
+        assert!(convert(&manifest, &folder, "a").1);
+    }
+
+    #[test]
+    fn reconverts_deleted_outputs() {
+        let folder = TempFolder::new("deleted");
+        let config = config(&folder);
+        convert_and_save(&config, &folder, "a");
+
+        fs::remove_file(folder.0.join("a.png")).unwrap();
+        let manifest = Manifest::load(&config).unwrap();
+        assert!(convert(&manifest, &folder, "a").1);
+        assert!(folder.0.join("a.png").exists());
+    }
+
+    #[test]
+    fn force_always_converts() {
+        let folder = TempFolder::new("force");
+        let mut config = config(&folder);
+        convert_and_save(&config, &folder, "a");
+
+        config.force = true;
+        for _ in 0..2 {
+            let manifest = Manifest::load(&config).unwrap();
+            assert!(convert(&manifest, &folder, "a").1);
+            manifest.save().unwrap();
+        }
+    }
+
+    #[test]
+    fn keeps_previous_entries() {
+        let folder = TempFolder::new("merge");
+        let config = config(&folder);
+        convert_and_save(&config, &folder, "a");
+        // `a` is not needed in this run, but its entry is kept
+        convert_and_save(&config, &folder, "b");
+
+        let manifest = Manifest::load(&config).unwrap();
+        assert!(!convert(&manifest, &folder, "a").1);
+        assert!(!convert(&manifest, &folder, "b").1);
+        assert_eq!(manifest.skipped(), 2);
+    }
+}
@@ -1,4 +1,12 @@
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
+
+use crate::image_conversion::{
+    downscale::{self, SizeLimit},
+    encoding::{self, ImageEncoding},
+    height::{self, HeightSettings},
+    normal::{self, NormalSettings},
+    specular, ConversionSettings, TextureFileFormat,
+};
 
 /// Holds the runtime configuration for the program.
 /// Used to turn features on/off.
@@ -6,9 +14,33 @@ pub struct Config {
     pub disable_d3dmesh_conversion: bool,
     pub disable_skl_conversion: bool,
     pub enable_height_map: bool,
+    /// how height maps are derived from normal maps if `enable_height_map` is set
+    pub height_map: HeightSettings,
+    pub export_mips: bool,
+    pub export_faces: bool,
+    /// compose the occlusion, gloss and spec-maps of every material into one ORM texture
+    pub compose_orm: bool,
+    pub texture_format: TextureFileFormat,
+    /// the source encoding and target convention of normal maps and the packing of spec-maps
+    pub conversion: ConversionSettings,
+    pub ktx2_zlib: bool,
+    /// the settings of the PNG, WebP and JPEG encoders
+    pub image_encoding: ImageEncoding,
+    /// the maximum resolution of the textures, `None` if they keep their resolution
+    pub size_limit: Option<SizeLimit>,
+    /// skip conversions whose outputs are up to date (see `Manifest`)
+    pub incremental: bool,
+    /// convert everything even if `incremental` is set
+    pub force: bool,
     pub input_folder: String,
     pub output_folder: String,
     pub verbose: bool,
+    /// .d3dtx files that are decoded with every plausible format instead of running a conversion
+    pub diagnose_d3dtx: Vec<String>,
+    /// an image that is encoded into a .d3dtx file based on `reference` instead of running a conversion
+    pub encode: Option<String>,
+    /// the .d3dtx file whose header and format are used when encoding `encode`
+    pub reference: Option<String>,
 }
 
 impl Config {
@@ -33,6 +65,144 @@ impl Config {
             "enable-height-map",
             "enables generating of height maps derived from the normal maps",
         );
+        const HEIGHT_MAP_ALGORITHM: (&str, &str, &[&str], &str) = (
+            "height-map-algorithm",
+            "how the slopes of the normal maps are integrated to heights. \
+                'poisson' finds the heights that match the slopes best over the whole texture, \
+                'ray-marching' averages the slopes along rays around every texel (see --height-map-rays)",
+            &["poisson", "ray-marching"],
+            "poisson",
+        );
+        const HEIGHT_MAP_STRENGTH: (&str, &str, &str) = (
+            "height-map-strength",
+            "scales the heights around middle gray. 1 uses the full range, \
+                bigger values exaggerate the relief and clip the highest and lowest areas",
+            "1",
+        );
+        const HEIGHT_MAP_TILING: (&str, &str) = (
+            "height-map-tiling",
+            "treats the normal maps as repeating textures, i.e. their opposite edges are integrated as neighbours",
+        );
+        const HEIGHT_MAP_RAYS: (&str, &str, &str) = (
+            "height-map-rays",
+            "the number of rays per texel of --height-map-algorithm ray-marching",
+            "50",
+        );
+        const HEIGHT_MAP_RADIUS: (&str, &str, &str) = (
+            "height-map-radius",
+            "the length of the rays of --height-map-algorithm ray-marching relative to the longer side of the texture",
+            "0.004",
+        );
+        const EXPORT_MIPS: (&str, &str) = (
+            "export-mips",
+            "exports all mip-map levels of the textures as separate PNGs (e.g. name.mip1.png) \
+                next to the biggest level",
+        );
+        const COMPOSE_ORM: (&str, &str) = (
+            "compose-orm",
+            "composes the occlusion, gloss and spec-maps of every material into one texture \
+                (e.g. name_occ_name_gloss_orm.png) with R: occlusion, G: roughness, B: metalness, \
+                which is used as occlusion and metallic-roughness texture of the glTF material. \
+                Missing properties are filled with constants (no occlusion, fully rough, not metallic) \
+                and sources of different resolution are resized to the biggest one",
+        );
+        const EXPORT_FACES: (&str, &str) = (
+            "export-faces",
+            "exports every face of cube maps and every slice of array and volume textures \
+                as separate PNGs (e.g. name.px.png or name.slice1.png). \
+                Cube maps are always exported as a horizontal cross as well",
+        );
+        const TEXTURE_FORMAT: (&str, &str, &[&str], &str) = (
+            "texture-format",
+            "the file format of the textures. \
+                'dds' keeps the original compressed data and mip-maps of textures that are not converted \
+                (e.g. diffuse textures) and references them via the glTF extension MSFT_texture_dds, \
+                converted textures (e.g. normal maps) are written as PNG. \
+                'ktx2' writes such textures as KTX2 with the original data and references them via the glTF \
+                extension KHR_texture_basisu, with an additional PNG as fallback. \
+                'webp' writes all textures as lossless WebP (8 bits per channel), which is referenced via \
+                the glTF extension EXT_texture_webp. \
+                'jpeg' writes opaque diffuse textures as JPEG (see --jpeg-quality) and all others as PNG",
+            &["png", "dds", "ktx2", "webp", "jpeg"],
+            "png",
+        );
+        const NORMAL_MAP_CONVENTION: (&str, &str, &[&str], &str) = (
+            "normal-map-convention",
+            "the convention of the converted normal maps. \
+                'opengl' stores X, Y and Z with green pointing up, which is expected by glTF, \
+                'directx' inverts green (Y pointing down) and 'rg' only stores X and Y (blue is zero)",
+            &["opengl", "directx", "rg"],
+            "opengl",
+        );
+        const NORMAL_MAP_SOURCE: (&str, &str, &[&str], &str) = (
+            "normal-map-source",
+            "how the normals are stored in the .d3dtx files. \
+                'auto' detects it per texture from the statistics of the channels, \
+                'xyz' uses red, green and blue, 'xy' only red and green (e.g. BC5), \
+                'swizzled' inverted alpha, blue and green and 'dxt5nm' alpha and green",
+            &["auto", "xyz", "xy", "swizzled", "dxt5nm"],
+            "auto",
+        );
+        const SPECULAR_PROFILE: (&str, &str, &[&str], &str) = (
+            "specular-profile",
+            "how occlusion, roughness, metalness and specular of the spec-maps are packed. \
+                'gltf-orm' writes one texture with R: occlusion, G: roughness, B: metalness \
+                and A: specular (KHR_materials_specular), \
+                'unreal-orm' writes an RGB texture with occlusion, roughness and metalness and \
+                specular as separate grayscale texture (_specular) \
+                and 'separate' writes every property as grayscale texture (e.g. _roughness)",
+            &["gltf-orm", "unreal-orm", "separate"],
+            "gltf-orm",
+        );
+        const KTX2_ZLIB: (&str, &str) = (
+            "ktx2-zlib",
+            "compresses the mip-maps of KTX2 textures with zlib (KTX2 supercompression)",
+        );
+        const PNG_COMPRESSION: (&str, &str, &[&str], &str) = (
+            "png-compression",
+            "the compression level of PNG images. \
+                'fast' is the fastest, 'best' creates the smallest files, \
+                'huffman' and 'rle' only use Huffman coding and run-length encoding respectively",
+            &["fast", "default", "best", "huffman", "rle"],
+            "fast",
+        );
+        const PNG_FILTER: (&str, &str, &[&str], &str) = (
+            "png-filter",
+            "the filter that is applied to every row of PNG images before compressing them",
+            &["none", "sub", "up", "avg", "paeth"],
+            "sub",
+        );
+        const JPEG_QUALITY: (&str, &str, &str) = (
+            "jpeg-quality",
+            "the quality of JPEG images from 1 (smallest files) to 100 (best quality)",
+            "90",
+        );
+        const MAX_TEXTURE_SIZE: (&str, &str) = (
+            "max-texture-size",
+            "the maximum width and height of the textures in pixels. \
+                Bigger textures are reduced according to --downscale, \
+                mip-map levels above the limit are dropped (e.g. from DDS and KTX2 files)",
+        );
+        const DOWNSCALE: (&str, &str, &[&str], &str) = (
+            "downscale",
+            "how textures are reduced to --max-texture-size. \
+                'mip' uses the biggest mip-map level that fits, which is fast and keeps the original data. \
+                All others resample the biggest level with the given filter (sRGB textures in linear space), \
+                except for cube maps, arrays and volumes, DDS and KTX2 files, which always use mip-map levels. \
+                Normal maps are renormalized after downscaling",
+            &["mip", "nearest", "triangle", "catmull-rom", "gaussian", "lanczos3"],
+            "mip",
+        );
+        const INCREMENTAL: (&str, &str) = (
+            "incremental",
+            "skips textures, meshes and skeletons whose inputs did not change since the previous run \
+                and whose outputs still exist. The inputs are recorded in the file manifest.json in the output folder. \
+                Everything is converted again if the version of this program or the texture options changed",
+        );
+        const FORCE: (&str, &str) = (
+            "force",
+            "converts everything with --incremental, even outputs that are up to date",
+        );
         const INPUT: (&str, &str, &str, &str) = (
             "input",
             "i",
@@ -45,6 +215,23 @@ impl Config {
             "the path to the output folder where the converted files are stored",
             "output",
         );
+        const DIAGNOSE_D3DTX: (&str, &str) = (
+            "diagnose-d3dtx",
+            "decodes the given .d3dtx files with every format that fits their size and scores the results. \
+                The decoded images are written to the folder 'diagnostics' in the output folder. \
+                No other conversion takes place if set",
+        );
+        const ENCODE: (&str, &str) = (
+            "encode",
+            "encodes the given image (e.g. an edited PNG) into a .d3dtx file with the same format, \
+                mip-map count and header as the texture given by --reference. \
+                The file is written to the output folder with the name of the reference. \
+                No other conversion takes place if set",
+        );
+        const REFERENCE: (&str, &str) = (
+            "reference",
+            "the .d3dtx file that is used as template for --encode",
+        );
         use clap::{App, Arg};
         let matches = App::new(env!("CARGO_PKG_NAME"))
             .version(env!("CARGO_PKG_VERSION"))
@@ -74,6 +261,147 @@ impl Config {
                     .help(ENABLE_HEIGHT_MAP.1)
                     .takes_value(false),
             )
+            .arg(
+                Arg::with_name(HEIGHT_MAP_ALGORITHM.0)
+                    .long(HEIGHT_MAP_ALGORITHM.0)
+                    .help(HEIGHT_MAP_ALGORITHM.1)
+                    .possible_values(HEIGHT_MAP_ALGORITHM.2)
+                    .default_value(HEIGHT_MAP_ALGORITHM.3)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(HEIGHT_MAP_STRENGTH.0)
+                    .long(HEIGHT_MAP_STRENGTH.0)
+                    .help(HEIGHT_MAP_STRENGTH.1)
+                    .default_value(HEIGHT_MAP_STRENGTH.2)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(HEIGHT_MAP_TILING.0)
+                    .long(HEIGHT_MAP_TILING.0)
+                    .help(HEIGHT_MAP_TILING.1)
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name(HEIGHT_MAP_RAYS.0)
+                    .long(HEIGHT_MAP_RAYS.0)
+                    .help(HEIGHT_MAP_RAYS.1)
+                    .default_value(HEIGHT_MAP_RAYS.2)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(HEIGHT_MAP_RADIUS.0)
+                    .long(HEIGHT_MAP_RADIUS.0)
+                    .help(HEIGHT_MAP_RADIUS.1)
+                    .default_value(HEIGHT_MAP_RADIUS.2)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(COMPOSE_ORM.0)
+                    .long(COMPOSE_ORM.0)
+                    .help(COMPOSE_ORM.1)
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name(EXPORT_MIPS.0)
+                    .long(EXPORT_MIPS.0)
+                    .help(EXPORT_MIPS.1)
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name(EXPORT_FACES.0)
+                    .long(EXPORT_FACES.0)
+                    .help(EXPORT_FACES.1)
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name(TEXTURE_FORMAT.0)
+                    .long(TEXTURE_FORMAT.0)
+                    .help(TEXTURE_FORMAT.1)
+                    .possible_values(TEXTURE_FORMAT.2)
+                    .default_value(TEXTURE_FORMAT.3)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(NORMAL_MAP_CONVENTION.0)
+                    .long(NORMAL_MAP_CONVENTION.0)
+                    .help(NORMAL_MAP_CONVENTION.1)
+                    .possible_values(NORMAL_MAP_CONVENTION.2)
+                    .default_value(NORMAL_MAP_CONVENTION.3)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(NORMAL_MAP_SOURCE.0)
+                    .long(NORMAL_MAP_SOURCE.0)
+                    .help(NORMAL_MAP_SOURCE.1)
+                    .possible_values(NORMAL_MAP_SOURCE.2)
+                    .default_value(NORMAL_MAP_SOURCE.3)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(SPECULAR_PROFILE.0)
+                    .long(SPECULAR_PROFILE.0)
+                    .help(SPECULAR_PROFILE.1)
+                    .possible_values(SPECULAR_PROFILE.2)
+                    .default_value(SPECULAR_PROFILE.3)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(KTX2_ZLIB.0)
+                    .long(KTX2_ZLIB.0)
+                    .help(KTX2_ZLIB.1)
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name(PNG_COMPRESSION.0)
+                    .long(PNG_COMPRESSION.0)
+                    .help(PNG_COMPRESSION.1)
+                    .possible_values(PNG_COMPRESSION.2)
+                    .default_value(PNG_COMPRESSION.3)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(PNG_FILTER.0)
+                    .long(PNG_FILTER.0)
+                    .help(PNG_FILTER.1)
+                    .possible_values(PNG_FILTER.2)
+                    .default_value(PNG_FILTER.3)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(JPEG_QUALITY.0)
+                    .long(JPEG_QUALITY.0)
+                    .help(JPEG_QUALITY.1)
+                    .default_value(JPEG_QUALITY.2)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(MAX_TEXTURE_SIZE.0)
+                    .long(MAX_TEXTURE_SIZE.0)
+                    .help(MAX_TEXTURE_SIZE.1)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(DOWNSCALE.0)
+                    .long(DOWNSCALE.0)
+                    .help(DOWNSCALE.1)
+                    .possible_values(DOWNSCALE.2)
+                    .default_value(DOWNSCALE.3)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(INCREMENTAL.0)
+                    .long(INCREMENTAL.0)
+                    .help(INCREMENTAL.1)
+                    .takes_value(false),
+            )
+            .arg(
+                Arg::with_name(FORCE.0)
+                    .long(FORCE.0)
+                    .help(FORCE.1)
+                    .takes_value(false)
+                    .requires(INCREMENTAL.0),
+            )
             .arg(
                 Arg::with_name(INPUT.0)
                     .short(INPUT.1)
@@ -90,15 +418,122 @@ impl Config {
                     .default_value(OUTPUT.3)
                     .takes_value(true),
             )
+            .arg(
+                Arg::with_name(DIAGNOSE_D3DTX.0)
+                    .long(DIAGNOSE_D3DTX.0)
+                    .help(DIAGNOSE_D3DTX.1)
+                    .takes_value(true)
+                    .multiple(true),
+            )
+            .arg(
+                Arg::with_name(ENCODE.0)
+                    .long(ENCODE.0)
+                    .help(ENCODE.1)
+                    .takes_value(true)
+                    .requires(REFERENCE.0),
+            )
+            .arg(
+                Arg::with_name(REFERENCE.0)
+                    .long(REFERENCE.0)
+                    .help(REFERENCE.1)
+                    .takes_value(true)
+                    .requires(ENCODE.0),
+            )
             .get_matches();
 
         Ok(Self {
             disable_d3dmesh_conversion: matches.is_present(DISABLE_D3DMESH_CONVERSION.0),
             disable_skl_conversion: matches.is_present(DISABLE_SKL_CONVERSION.0),
             enable_height_map: matches.is_present(ENABLE_HEIGHT_MAP.0),
+            height_map: HeightSettings {
+                algorithm: height::parse_algorithm(
+                    matches.value_of(HEIGHT_MAP_ALGORITHM.0).unwrap(),
+                    match matches.value_of(HEIGHT_MAP_RAYS.0).unwrap().parse() {
+                        Ok(rays) if rays > 0 => rays,
+                        _ => {
+                            return Err(anyhow!(
+                                "the number of height map rays must be a positive number"
+                            ))
+                        }
+                    },
+                    match matches
+                        .value_of(HEIGHT_MAP_RADIUS.0)
+                        .unwrap()
+                        .parse::<f32>()
+                    {
+                        Ok(radius) if radius > 0.0 && radius <= 1.0 => radius,
+                        _ => {
+                            return Err(anyhow!(
+                                "the height map radius must be a number from 0 to 1"
+                            ))
+                        }
+                    },
+                )?,
+                strength: match matches
+                    .value_of(HEIGHT_MAP_STRENGTH.0)
+                    .unwrap()
+                    .parse::<f32>()
+                {
+                    Ok(strength) if strength > 0.0 && strength.is_finite() => strength,
+                    _ => return Err(anyhow!("the height map strength must be a positive number")),
+                },
+                tiling: matches.is_present(HEIGHT_MAP_TILING.0),
+            },
+            export_mips: matches.is_present(EXPORT_MIPS.0),
+            export_faces: matches.is_present(EXPORT_FACES.0),
+            compose_orm: matches.is_present(COMPOSE_ORM.0),
+            texture_format: matches
+                .value_of(TEXTURE_FORMAT.0)
+                .unwrap()
+                .parse()
+                .context("invalid texture format")?,
+            conversion: ConversionSettings {
+                normal: NormalSettings {
+                    source: normal::parse_encoding(matches.value_of(NORMAL_MAP_SOURCE.0).unwrap())?,
+                    convention: normal::parse_convention(
+                        matches.value_of(NORMAL_MAP_CONVENTION.0).unwrap(),
+                    )?,
+                },
+                specular: specular::parse_profile(matches.value_of(SPECULAR_PROFILE.0).unwrap())?,
+            },
+            ktx2_zlib: matches.is_present(KTX2_ZLIB.0),
+            image_encoding: ImageEncoding {
+                png_compression: encoding::parse_png_compression(
+                    matches.value_of(PNG_COMPRESSION.0).unwrap(),
+                )?,
+                png_filter: encoding::parse_png_filter(matches.value_of(PNG_FILTER.0).unwrap())?,
+                jpeg_quality: match matches.value_of(JPEG_QUALITY.0).unwrap().parse() {
+                    Ok(quality @ 1..=100) => quality,
+                    _ => return Err(anyhow!("the JPEG quality must be a number from 1 to 100")),
+                },
+            },
+            size_limit: match matches.value_of(MAX_TEXTURE_SIZE.0) {
+                Some(value) => Some(SizeLimit {
+                    max_size: match value.parse() {
+                        Ok(max_size) if max_size > 0 => max_size,
+                        _ => {
+                            return Err(anyhow!(
+                                "the maximum texture size must be a positive number"
+                            ))
+                        }
+                    },
+                    downscaling: downscale::parse_downscaling(
+                        matches.value_of(DOWNSCALE.0).unwrap(),
+                    )?,
+                }),
+                None => None,
+            },
+            incremental: matches.is_present(INCREMENTAL.0),
+            force: matches.is_present(FORCE.0),
             input_folder: matches.value_of(INPUT.0).unwrap().to_string(),
             output_folder: matches.value_of(OUTPUT.0).unwrap().to_string(),
             verbose: matches.is_present(VERBOSE.0),
+            diagnose_d3dtx: matches
+                .values_of(DIAGNOSE_D3DTX.0)
+                .map(|values| values.map(String::from).collect())
+                .unwrap_or_default(),
+            encode: matches.value_of(ENCODE.0).map(String::from),
+            reference: matches.value_of(REFERENCE.0).map(String::from),
         })
     }
 }